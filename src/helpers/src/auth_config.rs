@@ -0,0 +1,183 @@
+//! Authentication methods configured in the website repository
+//! (e.g. who can log in and with which profiles).
+
+use std::{
+    fs::File,
+    io::{BufRead as _, BufReader},
+};
+
+use tracing::{error, info};
+
+use crate::{generate::Error, workspace::Workspace};
+
+/// Credentials allowing HTTP Basic authentication, mapped to profiles.
+#[derive(Debug, Clone)]
+pub struct BasicAuthCredentials {
+    pub username: String,
+    /// Argon2 hash in the PHC string format (e.g. `$argon2id$v=19$m=19456,t=2,p=1$…`).
+    pub password_hash: String,
+    pub profiles: Vec<String>,
+}
+
+/// A user allowed to log in using their email address.
+#[derive(Debug, Clone)]
+pub struct EmailUser {
+    /// Lowercased.
+    pub email: String,
+    pub profiles: Vec<String>,
+}
+
+/// A rule giving profiles to users logged in with OpenID Connect.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OidcRule {
+    /// `sub`, `email` or `groups`.
+    pub claim: String,
+    /// Expected value. A leading `*` matches any prefix (e.g. `*@example.org`).
+    pub value: String,
+    pub profiles: Vec<String>,
+}
+
+impl OidcRule {
+    pub fn matches(
+        &self,
+        value: &str,
+    ) -> bool {
+        match self.value.strip_prefix('*') {
+            Some(suffix) => value.ends_with(suffix),
+            None => value == self.value,
+        }
+    }
+}
+
+fn parse_oidc_rule(line: &str) -> Result<Option<OidcRule>, ()> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    // NOTE: Profiles never contain `:`, but values (e.g. groups) might.
+    let (condition, profiles) = line.rsplit_once(':').ok_or(())?;
+    let (claim, value) = condition.split_once('=').ok_or(())?;
+    Ok(Some(OidcRule {
+        claim: claim.trim().to_owned(),
+        value: value.trim().to_owned(),
+        profiles: (profiles.split(','))
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(ToOwned::to_owned)
+            .collect(),
+    }))
+}
+
+impl Workspace {
+    /// Reads `basic_auth.txt` at the root of the website repository.
+    ///
+    /// Each line is `username:password_hash:profile1,profile2`.
+    /// Empty lines and lines starting with `#` are ignored.
+    pub fn read_basic_auth_credentials(&self) -> Result<Vec<BasicAuthCredentials>, Error> {
+        let credentials_file_path = self.website_root.join("basic_auth.txt");
+        let Ok(credentials_file) = File::open(&credentials_file_path) else {
+            info!(
+                "Basic authentication credentials file not found at <{}>. Basic authentication disabled.",
+                credentials_file_path.display(),
+            );
+            return Ok(Vec::new());
+        };
+
+        let mut credentials = Vec::new();
+        for line in BufReader::new(credentials_file).lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            // NOTE: PHC strings never contain `:`.
+            let mut parts = line.splitn(3, ':');
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(username), Some(password_hash), Some(profiles)) => {
+                    credentials.push(BasicAuthCredentials {
+                        username: username.to_owned(),
+                        password_hash: password_hash.to_owned(),
+                        profiles: (profiles.split(','))
+                            .map(str::trim)
+                            .filter(|p| !p.is_empty())
+                            .map(ToOwned::to_owned)
+                            .collect(),
+                    })
+                },
+                _ => error!(
+                    "Invalid line in <{}>, skipping.",
+                    credentials_file_path.display()
+                ),
+            }
+        }
+        info!(
+            "Found {} Basic authentication credential(s).",
+            credentials.len()
+        );
+        Ok(credentials)
+    }
+
+    /// Reads `users.txt` at the root of the website repository.
+    ///
+    /// Each line is `email:profile1,profile2`.
+    /// Empty lines and lines starting with `#` are ignored.
+    pub fn read_email_users(&self) -> Result<Vec<EmailUser>, Error> {
+        let users_file_path = self.website_root.join("users.txt");
+        let Ok(users_file) = File::open(&users_file_path) else {
+            info!(
+                "Users file not found at <{}>. Email login disabled.",
+                users_file_path.display(),
+            );
+            return Ok(Vec::new());
+        };
+
+        let mut users = Vec::new();
+        for line in BufReader::new(users_file).lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once(':') {
+                Some((email, profiles)) if email.contains('@') => users.push(EmailUser {
+                    email: email.trim().to_lowercase(),
+                    profiles: (profiles.split(','))
+                        .map(str::trim)
+                        .filter(|p| !p.is_empty())
+                        .map(ToOwned::to_owned)
+                        .collect(),
+                }),
+                _ => error!("Invalid line in <{}>, skipping.", users_file_path.display()),
+            }
+        }
+        info!("Found {} user(s) allowed to log in by email.", users.len());
+        Ok(users)
+    }
+
+    /// Reads `oidc_rules.txt` at the root of the website repository.
+    ///
+    /// Each line is `claim=value:profile1,profile2`.
+    /// Empty lines and lines starting with `#` are ignored.
+    pub fn read_oidc_rules(&self) -> Result<Vec<OidcRule>, Error> {
+        let rules_file_path = self.website_root.join("oidc_rules.txt");
+        let Ok(rules_file) = File::open(&rules_file_path) else {
+            info!(
+                "OpenID Connect rules file not found at <{}>. Considering no rule.",
+                rules_file_path.display(),
+            );
+            return Ok(Vec::new());
+        };
+
+        let mut rules = Vec::new();
+        for line in BufReader::new(rules_file).lines() {
+            let line = line?;
+            match parse_oidc_rule(&line) {
+                Ok(Some(rule)) => rules.push(rule),
+                Ok(None) => {},
+                Err(()) => error!("Invalid line in <{}>, skipping.", rules_file_path.display()),
+            }
+        }
+        info!("Found {} OpenID Connect rule(s).", rules.len());
+        Ok(rules)
+    }
+}
@@ -2,12 +2,14 @@ use std::{env, path::PathBuf};
 
 use lazy_static::lazy_static;
 
+use crate::workspace::WORKSPACE;
+
 pub const THEME_NAME: &str = "Orangutan";
 pub const DATA_FILE_EXTENSION: &str = "orangutan";
 pub const DEFAULT_PROFILE: &str = "_default";
-pub const ROOT_KEY_NAME: &'static str = "_biscuit_root";
+pub const ROOT_KEY_NAME: &str = "_biscuit_root";
 
-pub(super) const WEBSITE_DIR_NAME: &'static str = "website";
+pub(super) const WEBSITE_DIR_NAME: &str = "website";
 
 lazy_static! {
     pub static ref WEBSITE_REPOSITORY: String = env::var("WEBSITE_REPOSITORY")
@@ -18,10 +20,13 @@ lazy_static! {
 lazy_static! {
     static ref WORK_DIR: PathBuf = env::current_dir().unwrap();
     pub static ref BASE_DIR: PathBuf = WORK_DIR.join(".orangutan");
-    pub static ref TMP_DIR: PathBuf = BASE_DIR.join("tmp");
     pub static ref KEYS_DIR: PathBuf = BASE_DIR.join("keys");
-    pub(super) static ref WEBSITE_ROOT: PathBuf = BASE_DIR.join("website-src");
-    pub(super) static ref HUGO_CONFIG_DIR: PathBuf = BASE_DIR.join("hugo-config");
-    pub static ref DEST_DIR: PathBuf = BASE_DIR.join("out");
-    pub static ref WEBSITE_DATA_DIR: PathBuf = DEST_DIR.join("data");
+}
+// NOTE: Paths of the default workspace, kept for compatibility.
+lazy_static! {
+    pub static ref TMP_DIR: PathBuf = WORKSPACE.tmp_dir.clone();
+    /// Tokens revoked at runtime, in addition to the website's `revoked_tokens.txt`.
+    pub static ref LOCAL_REVOKED_TOKENS_FILE: PathBuf = WORKSPACE.local_revoked_tokens_file.clone();
+    pub static ref DEST_DIR: PathBuf = WORKSPACE.dest_dir.clone();
+    pub static ref WEBSITE_DATA_DIR: PathBuf = WORKSPACE.website_data_dir.clone();
 }
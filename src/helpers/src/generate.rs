@@ -3,145 +3,286 @@ use std::{
     env,
     fs::{self, File},
     io::{self, BufRead as _, BufReader, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::{Command, Output, Stdio},
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc, Mutex, MutexGuard,
-    },
+    sync::{atomic::Ordering, Arc, MutexGuard},
 };
 
-use lazy_static::lazy_static;
+use sha2::{Digest as _, Sha256};
 use tracing::{debug, error, info, trace};
 
-use crate::{config::*, copy_directory, website_id::*};
+use crate::{
+    config::*,
+    copy_directory,
+    profile_index::ProfileIndex,
+    website_id::*,
+    workspace::{Workspace, WORKSPACE},
+};
 
-static HUGO_CONFIG_GENERATED: AtomicBool = AtomicBool::new(false);
-static DATA_FILES_GENERATED: AtomicBool = AtomicBool::new(false);
+// NOTE: Functions using the default workspace, kept for compatibility.
 
-lazy_static! {
-    // NOTE: `Arc` prevents race conditions
-    static ref GENERATED_WEBSITES: Arc<Mutex<HashSet<PathBuf>>> = Arc::new(Mutex::new(HashSet::new()));
-    static ref TRASH_DIR: PathBuf = TMP_DIR.join("trash");
+pub fn generate_default_website() -> Result<(), Error> {
+    WORKSPACE.generate_default_website()
 }
 
-pub fn generate_default_website() -> Result<(), Error> {
-    // Generate the website
-    generate_website_if_needed(&WebsiteId::default())?;
+pub fn clone_repository() -> Result<(), Error> {
+    WORKSPACE.clone_repository()
+}
 
-    // Generate Orangutan data files
-    generate_data_files_if_needed()?;
+pub fn pull_repository() -> Result<(), Error> {
+    WORKSPACE.pull_repository()
+}
 
-    Ok(())
+pub fn read_revoked_tokens() -> Result<HashSet<Vec<u8>>, Error> {
+    WORKSPACE.read_revoked_tokens()
 }
 
-pub fn clone_repository() -> Result<(), Error> {
-    if WEBSITE_ROOT.is_dir() {
-        return pull_repository();
-    }
+pub fn revoke_tokens(revocation_ids: &[Vec<u8>]) -> Result<(), Error> {
+    WORKSPACE.revoke_tokens(revocation_ids)
+}
 
-    _clone_repository()?;
-    _init_submodules()?;
-    Ok(())
+pub fn generate_website_if_needed(website_id: &WebsiteId) -> Result<PathBuf, Error> {
+    WORKSPACE.generate_website_if_needed(website_id)
 }
 
-fn _clone_repository() -> Result<(), Error> {
-    let mut command = Command::new("git");
-    command
-        .args(vec![
-            "clone",
-            &WEBSITE_REPOSITORY,
-            &WEBSITE_ROOT.display().to_string(),
-        ])
-        .args(vec!["--depth", "1"]);
-
-    trace!("Running `{:?}`…", command);
-    let output = command
-        .output()
-        .map_err(|e| Error::CannotExecuteCommand(format!("{:?}", command), e))?;
-
-    if output.status.success() {
-        Ok(())
-    } else {
-        Err(Error::CommandExecutionFailed {
-            command: format!("{:?}", command),
-            code: output.status.code(),
-            stderr: output.stderr,
-        })
-    }
+pub fn generated_websites() -> Vec<PathBuf> {
+    WORKSPACE.generated_websites()
 }
 
-fn _init_submodules() -> Result<(), Error> {
-    let mut command = Command::new("git");
-    command
-        .args(vec!["-C", &WEBSITE_ROOT.display().to_string()])
-        .args(vec!["submodule", "update", "--init"]);
+pub fn generate_data_files_if_needed() -> Result<(), Error> {
+    WORKSPACE.generate_data_files_if_needed()
+}
 
-    trace!("Running `{:?}`…", command);
-    let output = command
-        .output()
-        .map_err(|e| Error::CannotExecuteCommand(format!("{:?}", command), e))?;
+pub fn hugo_gen(
+    params: Vec<&str>,
+    destination: String,
+) -> Result<(), Error> {
+    WORKSPACE.hugo_gen(params, destination)
+}
 
-    if output.status.success() {
-        Ok(())
-    } else {
-        Err(Error::CommandExecutionFailed {
-            command: format!("{:?}", command),
-            code: output.status.code(),
-            stderr: output.stderr,
-        })
-    }
+pub fn create_tmp_dir() -> Result<(), Error> {
+    WORKSPACE.create_tmp_dir()
 }
 
-pub fn pull_repository() -> Result<(), Error> {
-    _pull_repository()?;
-    _update_submodules()?;
-    Ok(())
+pub fn trash_outdated_websites() -> Result<State, Error> {
+    WORKSPACE.trash_outdated_websites()
+}
+
+pub fn recover_trash(state: State) -> Result<(), Error> {
+    WORKSPACE.recover_trash(state)
 }
 
-fn _pull_repository() -> Result<(), Error> {
-    let mut command = Command::new("git");
-    command
-        .args(vec!["-C", &WEBSITE_ROOT.display().to_string()])
-        .args(vec!["pull", "--rebase"]);
+pub fn empty_trash(state: State) -> Result<(), Error> {
+    WORKSPACE.empty_trash(state)
+}
 
-    trace!("Running `{:?}`…", command);
-    let output = command
-        .output()
-        .map_err(|e| Error::CannotExecuteCommand(format!("{:?}", command), e))?;
+impl Workspace {
+    pub fn generate_default_website(&self) -> Result<(), Error> {
+        // Generate the website
+        self.generate_website_if_needed(&WebsiteId::default())?;
+
+        // Generate Orangutan data files
+        self.generate_data_files_if_needed()?;
 
-    if output.status.success() {
         Ok(())
-    } else {
-        Err(Error::CommandExecutionFailed {
-            command: format!("{:?}", command),
-            code: output.status.code(),
-            stderr: output.stderr,
-        })
     }
-}
 
-fn _update_submodules() -> Result<(), Error> {
-    let mut command = Command::new("git");
-    command
-        .args(vec!["-C", &WEBSITE_ROOT.display().to_string()])
-        .args(vec!["submodule", "update", "--remote", "--recursive"]);
+    pub fn clone_repository(&self) -> Result<(), Error> {
+        if self.website_root.is_dir() {
+            return self.pull_repository();
+        }
+
+        self._clone_repository()?;
+        self._init_submodules()?;
+        Ok(())
+    }
+
+    fn _clone_repository(&self) -> Result<(), Error> {
+        if self.repository.is_empty() {
+            return Err(Error::MissingRepository);
+        }
+
+        let mut command = Command::new("git");
+        command
+            .args(vec![
+                "clone",
+                &self.repository,
+                &self.website_root.display().to_string(),
+            ])
+            .args(vec!["--depth", "1"]);
+
+        trace!("Running `{:?}`…", command);
+        let output = command
+            .output()
+            .map_err(|e| Error::CannotExecuteCommand(format!("{:?}", command), e))?;
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(Error::CommandExecutionFailed {
+                command: format!("{:?}", command),
+                code: output.status.code(),
+                stderr: output.stderr,
+            })
+        }
+    }
 
-    trace!("Running `{:?}`…", command);
-    let output = command
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .output()
-        .map_err(|e| Error::CannotExecuteCommand(format!("{:?}", command), e))?;
+    fn _init_submodules(&self) -> Result<(), Error> {
+        let mut command = Command::new("git");
+        command
+            .args(vec!["-C", &self.website_root.display().to_string()])
+            .args(vec!["submodule", "update", "--init"]);
+
+        trace!("Running `{:?}`…", command);
+        let output = command
+            .output()
+            .map_err(|e| Error::CannotExecuteCommand(format!("{:?}", command), e))?;
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(Error::CommandExecutionFailed {
+                command: format!("{:?}", command),
+                code: output.status.code(),
+                stderr: output.stderr,
+            })
+        }
+    }
 
-    if output.status.success() {
+    pub fn pull_repository(&self) -> Result<(), Error> {
+        self._pull_repository()?;
+        self._update_submodules()?;
+        Ok(())
+    }
+
+    fn _pull_repository(&self) -> Result<(), Error> {
+        let mut command = Command::new("git");
+        command
+            .args(vec!["-C", &self.website_root.display().to_string()])
+            .args(vec!["pull", "--rebase"]);
+
+        trace!("Running `{:?}`…", command);
+        let output = command
+            .output()
+            .map_err(|e| Error::CannotExecuteCommand(format!("{:?}", command), e))?;
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(Error::CommandExecutionFailed {
+                command: format!("{:?}", command),
+                code: output.status.code(),
+                stderr: output.stderr,
+            })
+        }
+    }
+
+    fn _update_submodules(&self) -> Result<(), Error> {
+        let mut command = Command::new("git");
+        command
+            .args(vec!["-C", &self.website_root.display().to_string()])
+            .args(vec!["submodule", "update", "--remote", "--recursive"]);
+
+        trace!("Running `{:?}`…", command);
+        let output = command
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|e| Error::CannotExecuteCommand(format!("{:?}", command), e))?;
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(Error::CommandExecutionFailed {
+                command: format!("{:?}", command),
+                code: output.status.code(),
+                stderr: output.stderr,
+            })
+        }
+    }
+
+    /// Identifies the content of the website repository (its commit
+    /// and the ones of its submodules), computed once per content update.
+    pub fn revision(&self) -> Result<String, Error> {
+        if let Some(revision) = self.revision.read().unwrap().as_ref() {
+            return Ok(revision.clone());
+        }
+
+        // NOTE: The revision ends up in `ETag`s, it must be stable across builds.
+        let mut hasher = Sha256::new();
+        for args in [vec!["rev-parse", "HEAD"], vec![
+            "submodule",
+            "status",
+            "--recursive",
+        ]] {
+            hasher.update(self.git_output(args)?);
+        }
+        let revision = hex::encode(hasher.finalize());
+        trace!("Website repository is at revision {revision}");
+
+        *self.revision.write().unwrap() = Some(revision.clone());
+        Ok(revision)
+    }
+
+    fn git_output(
+        &self,
+        args: Vec<&str>,
+    ) -> Result<Vec<u8>, Error> {
+        let mut command = Command::new("git");
+        command
+            .args(vec!["-C", &self.website_root.display().to_string()])
+            .args(args);
+
+        trace!("Running `{:?}`…", command);
+        let output = command
+            .output()
+            .map_err(|e| Error::CannotExecuteCommand(format!("{:?}", command), e))?;
+
+        if output.status.success() {
+            Ok(output.stdout)
+        } else {
+            Err(Error::CommandExecutionFailed {
+                command: format!("{:?}", command),
+                code: output.status.code(),
+                stderr: output.stderr,
+            })
+        }
+    }
+
+    // NOTE: This is just a hotfix. I had to quickly revoke a token. I'll improve this one day.
+    pub fn read_revoked_tokens(&self) -> Result<HashSet<Vec<u8>>, Error> {
+        let mut revoked_tokens = HashSet::new();
+        for revoked_tokens_file_path in [
+            self.website_root.join("revoked_tokens.txt"),
+            self.local_revoked_tokens_file.to_path_buf(),
+        ] {
+            let Ok(revoked_tokens_file) = File::open(&revoked_tokens_file_path) else {
+                info!(
+                    "Revoked tokens file not found at <{}>. Skipping.",
+                    revoked_tokens_file_path.display(),
+                );
+                continue;
+            };
+            revoked_tokens.extend(read_file_lines_as_hex_(revoked_tokens_file)?);
+        }
+        info!("Found {} revoked token(s).", revoked_tokens.len());
+        Ok(revoked_tokens)
+    }
+
+    /// Saves revocation identifiers in [`Workspace::local_revoked_tokens_file`],
+    /// for tokens revoked at runtime (as we cannot push to the website repository).
+    pub fn revoke_tokens(
+        &self,
+        revocation_ids: &[Vec<u8>],
+    ) -> Result<(), Error> {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.local_revoked_tokens_file.as_path())?;
+        for revocation_id in revocation_ids {
+            writeln!(file, "{}", hex::encode(revocation_id))?;
+        }
         Ok(())
-    } else {
-        Err(Error::CommandExecutionFailed {
-            command: format!("{:?}", command),
-            code: output.status.code(),
-            stderr: output.stderr,
-        })
     }
 }
 
@@ -163,293 +304,364 @@ fn read_file_lines_as_hex_(file: File) -> io::Result<HashSet<Vec<u8>>> {
     Ok(set)
 }
 
-// NOTE: This is just a hotfix. I had to quickly revoke a token. I'll improve this one day.
-pub fn read_revoked_tokens() -> Result<HashSet<Vec<u8>>, Error> {
-    let revoked_tokens_file_path = WEBSITE_ROOT.join("revoked_tokens.txt");
-    let Ok(revoked_tokens_file) = File::open(&revoked_tokens_file_path) else {
-        info!(
-            "Revoked tokens file not found at <{}>. Considering no revoked token.",
-            revoked_tokens_file_path.display(),
-        );
-        return Ok(HashSet::new());
-    };
-    let revoked_tokens = read_file_lines_as_hex_(revoked_tokens_file)?;
-    info!("Found {} revoked token(s).", revoked_tokens.len());
-    Ok(revoked_tokens)
-}
+impl Workspace {
+    fn _copy_hugo_config(&self) -> Result<(), Error> {
+        debug!("Copying hugo config…");
 
-fn _copy_hugo_config() -> Result<(), Error> {
-    debug!("Copying hugo config…");
+        // Copy config dir
+        // TODO: Support config that is not directory-based
+        let source = self.website_root.join("config");
+        let dest = self.hugo_config_dir.join("_default");
+        copy_directory(source.as_path(), dest.as_path())
+            .map_err(Error::CannotCreateHugoConfigFile)?;
+        debug!("Hugo config will be saved in <{}>", &dest.display());
 
-    // Copy config dir
-    // TODO: Support config that is not directory-based
-    let source = WEBSITE_ROOT.join("config");
-    let dest = HUGO_CONFIG_DIR.join("_default");
-    copy_directory(source.as_path(), dest.as_path()).map_err(Error::CannotCreateHugoConfigFile)?;
-    debug!("Hugo config will be saved in <{}>", &dest.display());
+        self.hugo_config_generated.store(true, Ordering::Relaxed);
 
-    HUGO_CONFIG_GENERATED.store(true, Ordering::Relaxed);
-
-    Ok(())
-}
-
-fn gen_hugo_config(website_id: &WebsiteId) -> Result<(), Error> {
-    // Create config dir
-    let config_dir = HUGO_CONFIG_DIR.join(website_id.dir_name());
-    fs::create_dir_all(&config_dir).map_err(Error::CannotCreateHugoConfigFile)?;
+        Ok(())
+    }
 
-    // Create new config
-    let profiles: Vec<String> = website_id.profiles.iter().map(|s| s.clone()).collect();
-    let profiles_json = serde_json::to_string(&profiles).unwrap();
-    let config = format!(
-        "[Params]
+    fn gen_hugo_config(
+        &self,
+        website_id: &WebsiteId,
+    ) -> Result<(), Error> {
+        // Create config dir
+        let config_dir = self.hugo_config_dir.join(website_id.dir_name());
+        fs::create_dir_all(&config_dir).map_err(Error::CannotCreateHugoConfigFile)?;
+
+        // Create new config
+        let profiles: Vec<String> = website_id.profiles.iter().cloned().collect();
+        let profiles_json = serde_json::to_string(&profiles).unwrap();
+        let config = format!(
+            "[Params]
   currentProfiles = {}
 ",
-        profiles_json
-    );
+            profiles_json
+        );
 
-    // Write new config file
-    let config_file = config_dir.join("hugo.toml");
-    let res = File::create(config_file)
-        .map_err(Error::CannotCreateHugoConfigFile)?
-        .write_all(&config.as_bytes())
-        .map_err(Error::CannotCreateHugoConfigFile)?;
+        // Write new config file
+        let config_file = config_dir.join("hugo.toml");
+        File::create(config_file)
+            .map_err(Error::CannotCreateHugoConfigFile)?
+            .write_all(config.as_bytes())
+            .map_err(Error::CannotCreateHugoConfigFile)?;
 
-    Ok(res)
-}
-
-fn copy_hugo_config_if_needed() -> Result<(), Error> {
-    if HUGO_CONFIG_GENERATED.load(Ordering::Relaxed) {
         Ok(())
-    } else {
-        _copy_hugo_config()
     }
-}
 
-fn generate_website(
-    id: &WebsiteId,
-    destination: &PathBuf,
-    generated_websites: &mut MutexGuard<'_, HashSet<PathBuf>>,
-) -> Result<(), Error> {
-    info!("Generating website for {:?}…", id.profiles);
-    debug!(
-        "Website for {:?} will be generated at <{}>",
-        id.profiles,
-        destination.display()
-    );
-
-    copy_hugo_config_if_needed()?;
-    gen_hugo_config(id)?;
-
-    let config_dir = HUGO_CONFIG_DIR.display().to_string();
-    let environment = id.dir_name();
-    let mut params = vec![
-        "--disableKinds",
-        "RSS,sitemap",
-        "--cleanDestinationDir",
-        "--configDir",
-        &config_dir,
-        "--environment",
-        &environment,
-    ];
-    if env::var("LOCALHOST") == Ok("true".to_string()) {
-        params.append(&mut vec!["--baseURL", "http://localhost:8080"]);
+    fn copy_hugo_config_if_needed(&self) -> Result<(), Error> {
+        if self.hugo_config_generated.load(Ordering::Relaxed) {
+            Ok(())
+        } else {
+            self._copy_hugo_config()
+        }
     }
-    let res = hugo_gen(params, destination.display().to_string())
-        .map_err(|e| Error::CannotGenerateWebsite(Box::new(e)))?;
 
-    // Temporary fix to avoid leakage of page existence and content
-    // TODO(RemiBardon): Find a solution to avoid removing this file
-    empty_index_json(destination).map_err(Error::CannotEmptyIndexJson)?;
+    fn generate_website(
+        &self,
+        id: &WebsiteId,
+        destination: &Path,
+        generated_websites: &mut MutexGuard<'_, HashSet<PathBuf>>,
+    ) -> Result<(), Error> {
+        info!("Generating website for {:?}…", id.profiles);
+        debug!(
+            "Website for {:?} will be generated at <{}>",
+            id.profiles,
+            destination.display()
+        );
 
-    generated_websites.insert(destination.clone());
+        self.copy_hugo_config_if_needed()?;
+        self.gen_hugo_config(id)?;
 
-    Ok(res)
-}
+        let config_dir = self.hugo_config_dir.display().to_string();
+        let environment = id.dir_name();
+        let mut params = vec![
+            "--disableKinds",
+            "RSS,sitemap",
+            "--cleanDestinationDir",
+            "--configDir",
+            &config_dir,
+            "--environment",
+            &environment,
+        ];
+        if env::var("LOCALHOST") == Ok("true".to_string()) {
+            params.append(&mut vec!["--baseURL", "http://localhost:8080"]);
+        }
+        self.hugo_gen(params, destination.display().to_string())
+            .map_err(|e| Error::CannotGenerateWebsite(Box::new(e)))?;
 
-/// Generate the website
-pub fn generate_website_if_needed(website_id: &WebsiteId) -> Result<PathBuf, Error> {
-    let website_dir = website_dir(&website_id);
+        // Avoid leaking page existence and content through the search index
+        // NOTE: The profile index is built from data files.
+        self.generate_data_files_if_needed()?;
+        self.filter_index_json(destination, id)
+            .map_err(Error::CannotFilterIndexJson)?;
 
-    let mut generated_websites = GENERATED_WEBSITES.lock().unwrap();
-    if !generated_websites.contains(&website_dir) {
-        generate_website(&website_id, &website_dir, &mut generated_websites)?;
-    }
+        generated_websites.insert(destination.to_path_buf());
 
-    Ok(website_dir)
-}
+        Ok(())
+    }
 
-fn _generate_data_files() -> Result<(), Error> {
-    info!("Generating Orangutan data files…");
-
-    // Copy some files if needed
-    // FIXME: Do not hardcode "PaperMod"
-    let shortcodes_dir = WEBSITE_ROOT.join("themes/PaperMod/layouts/shortcodes");
-    let shortcodes_dest_dir_path = format!("themes/{}/layouts/shortcodes", THEME_NAME);
-    let shortcodes_dest_dir = WEBSITE_ROOT.join(&shortcodes_dest_dir_path);
-    trace!(
-        "Copying shortcodes from {} to {}…",
-        shortcodes_dir.display(),
-        shortcodes_dest_dir.display()
-    );
-    copy_directory(&shortcodes_dir, &shortcodes_dest_dir).unwrap();
-
-    let res = hugo_gen(
-        vec![
-            "--disableKinds",
-            "RSS,sitemap,home",
-            "--theme",
-            THEME_NAME,
-        ],
-        WEBSITE_DATA_DIR.display().to_string(),
-    )?;
+    /// Generate the website
+    pub fn generate_website_if_needed(
+        &self,
+        website_id: &WebsiteId,
+    ) -> Result<PathBuf, Error> {
+        let website_dir = self.website_dir(website_id);
 
-    DATA_FILES_GENERATED.store(true, Ordering::Relaxed);
+        let mut generated_websites = self.generated_websites.lock().unwrap();
+        if !generated_websites.contains(&website_dir) {
+            self.generate_website(website_id, &website_dir, &mut generated_websites)?;
+        }
 
-    Ok(res)
-}
+        Ok(website_dir)
+    }
 
-pub fn generate_data_files_if_needed() -> Result<(), Error> {
-    if DATA_FILES_GENERATED.load(Ordering::Relaxed) {
-        Ok(())
-    } else {
-        _generate_data_files()
+    /// Returns the directories of websites generated since the last content update.
+    pub fn generated_websites(&self) -> Vec<PathBuf> {
+        let mut websites: Vec<PathBuf> = self
+            .generated_websites
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect();
+        websites.sort();
+        websites
     }
-}
 
-pub fn hugo_gen(
-    params: Vec<&str>,
-    destination: String,
-) -> Result<(), Error> {
-    let website_root = WEBSITE_ROOT.display().to_string();
-    let base_params: Vec<&str> = vec![
-        "--source",
-        website_root.as_str(),
-        "--destination",
-        destination.as_str(),
-    ];
-    hugo(
-        base_params.into_iter().chain(params.into_iter()).collect(),
-        false,
-    )?;
-
-    Ok(())
-}
+    fn _generate_data_files(&self) -> Result<(), Error> {
+        info!("Generating Orangutan data files…");
+
+        // Copy some files if needed
+        // FIXME: Do not hardcode "PaperMod"
+        let shortcodes_dir = self.website_root.join("themes/PaperMod/layouts/shortcodes");
+        let shortcodes_dest_dir_path = format!("themes/{}/layouts/shortcodes", THEME_NAME);
+        let shortcodes_dest_dir = self.website_root.join(&shortcodes_dest_dir_path);
+        trace!(
+            "Copying shortcodes from {} to {}…",
+            shortcodes_dir.display(),
+            shortcodes_dest_dir.display()
+        );
+        copy_directory(&shortcodes_dir, &shortcodes_dest_dir).unwrap();
 
-fn hugo(
-    params: Vec<&str>,
-    pipe_stdout: bool,
-) -> Result<Output, Error> {
-    let mut command = Command::new("hugo");
-
-    let website_root = WEBSITE_ROOT.display().to_string();
-    let base_params: Vec<&str> = vec!["--source", website_root.as_str()];
-    let params = base_params.iter().chain(params.iter());
-    command.args(params);
-
-    // `Stdio::piped()` is the default when using `.output()`,
-    // so we must override it the other way around
-    command.stderr(Stdio::inherit());
-    if !pipe_stdout {
-        command.stdout(Stdio::inherit());
+        self.hugo_gen(
+            vec![
+                "--disableKinds",
+                "RSS,sitemap,home",
+                "--theme",
+                THEME_NAME,
+            ],
+            self.website_data_dir.display().to_string(),
+        )?;
+
+        self.data_files_generated.store(true, Ordering::Relaxed);
+        self.reload_indexes();
+
+        Ok(())
     }
 
-    trace!("Running `{:?}`…", command);
-    let output = command
-        .output()
-        .map_err(|e| Error::CannotExecuteCommand(format!("{:?}", command), e))?;
-
-    if output.status.success() {
-        Ok(output.clone())
-    } else {
-        Err(Error::CommandExecutionFailed {
-            command: format!("{:?}", command),
-            code: output.status.code(),
-            stderr: output.stderr,
-        })
+    pub fn generate_data_files_if_needed(&self) -> Result<(), Error> {
+        if self.data_files_generated.load(Ordering::Relaxed) {
+            Ok(())
+        } else {
+            self._generate_data_files()
+        }
     }
-}
 
-fn empty_index_json(website_dir: &PathBuf) -> Result<(), io::Error> {
-    let index_json_path = website_dir.join("index.json");
-    // Open the file in write mode, which will truncate the file if it already exists
-    let mut file = File::create(index_json_path)?;
-    file.write(b"[]")?;
-    Ok(())
-}
+    pub fn hugo_gen(
+        &self,
+        params: Vec<&str>,
+        destination: String,
+    ) -> Result<(), Error> {
+        let website_root = self.website_root.display().to_string();
+        let base_params: Vec<&str> = vec![
+            "--source",
+            website_root.as_str(),
+            "--destination",
+            destination.as_str(),
+        ];
+        self.hugo(base_params.into_iter().chain(params).collect(), false)?;
 
-pub struct State {
-    hugo_config_generated: bool,
-    data_files_generated: bool,
-    generated_websites: HashSet<PathBuf>,
-    used_profiles: Option<&'static HashSet<String>>,
-}
-
-pub fn create_tmp_dir() -> Result<(), Error> {
-    trace!("Creating temporary directory at <{}>…", TMP_DIR.display());
-    fs::create_dir_all(TMP_DIR.as_path())?;
+        Ok(())
+    }
 
-    Ok(())
-}
+    fn hugo(
+        &self,
+        params: Vec<&str>,
+        pipe_stdout: bool,
+    ) -> Result<Output, Error> {
+        let mut command = Command::new("hugo");
+
+        let website_root = self.website_root.display().to_string();
+        let base_params: Vec<&str> = vec!["--source", website_root.as_str()];
+        let params = base_params.iter().chain(params.iter());
+        command.args(params);
+
+        // `Stdio::piped()` is the default when using `.output()`,
+        // so we must override it the other way around
+        command.stderr(Stdio::inherit());
+        if !pipe_stdout {
+            command.stdout(Stdio::inherit());
+        }
 
-pub fn trash_outdated_websites() -> Result<State, Error> {
-    trace!("Trashing outdated websites…");
-
-    // Empty the trash (it's at least `HEAD~2` so we can safely delete it)
-    // NOTE: This whould not be necessary since the directory should be deleted
-    //   but there might be edge cases where it's still there and the next
-    //   `fs::rename` will fail if it's the case.
-    if TRASH_DIR.exists() {
-        fs::remove_dir_all(TRASH_DIR.as_path())?;
+        trace!("Running `{:?}`…", command);
+        let output = command
+            .output()
+            .map_err(|e| Error::CannotExecuteCommand(format!("{:?}", command), e))?;
+
+        if output.status.success() {
+            Ok(output.clone())
+        } else {
+            Err(Error::CommandExecutionFailed {
+                command: format!("{:?}", command),
+                code: output.status.code(),
+                stderr: output.stderr,
+            })
+        }
     }
 
-    // Remove outdated websites
-    // NOTE: `DEST_DIR` might not exist if last website generation failed
-    //   and we didn’t recover properly. We should fix recovery, but this is
-    //   a workaround.
-    if DEST_DIR.exists() {
-        fs::rename(DEST_DIR.as_path(), TRASH_DIR.as_path())?;
+    pub fn create_tmp_dir(&self) -> Result<(), Error> {
+        trace!(
+            "Creating temporary directory at <{}>…",
+            self.tmp_dir.display()
+        );
+        fs::create_dir_all(self.tmp_dir.as_path())?;
+
+        Ok(())
     }
 
-    // Save caches (in case we need to recover)
-    let state = State {
-        hugo_config_generated: HUGO_CONFIG_GENERATED.load(Ordering::Relaxed),
-        data_files_generated: DATA_FILES_GENERATED.load(Ordering::Relaxed),
-        generated_websites: GENERATED_WEBSITES.lock().unwrap().to_owned(),
-        used_profiles: super::USED_PROFILES.read().unwrap().to_owned(),
-    };
+    pub fn trash_outdated_websites(&self) -> Result<State, Error> {
+        trace!("Trashing outdated websites…");
 
-    // Clear caches
-    HUGO_CONFIG_GENERATED.store(false, Ordering::Relaxed);
-    DATA_FILES_GENERATED.store(false, Ordering::Relaxed);
-    GENERATED_WEBSITES.lock().unwrap().clear();
-    *super::USED_PROFILES.write().unwrap() = None;
+        // Empty the trash (it's at least `HEAD~2` so we can safely delete it)
+        // NOTE: This whould not be necessary since the directory should be deleted
+        //   but there might be edge cases where it's still there and the next
+        //   `fs::rename` will fail if it's the case.
+        if self.trash_dir.exists() {
+            fs::remove_dir_all(self.trash_dir.as_path())?;
+        }
 
-    Ok(state)
-}
+        // Remove outdated websites
+        // NOTE: `dest_dir` might not exist if last website generation failed
+        //   and we didn’t recover properly. We should fix recovery, but this is
+        //   a workaround.
+        if self.dest_dir.exists() {
+            fs::rename(self.dest_dir.as_path(), self.trash_dir.as_path())?;
+        }
 
-pub fn recover_trash(state: State) -> Result<(), Error> {
-    trace!("Recovering trash…");
+        // Save caches (in case we need to recover)
+        let state = State {
+            hugo_config_generated: self.hugo_config_generated.load(Ordering::Relaxed),
+            data_files_generated: self.data_files_generated.load(Ordering::Relaxed),
+            generated_websites: self.generated_websites.lock().unwrap().to_owned(),
+            profile_index: self.profile_index.read().unwrap().to_owned(),
+            revision: self.revision.read().unwrap().to_owned(),
+        };
+
+        // Clear caches
+        self.hugo_config_generated.store(false, Ordering::Relaxed);
+        self.data_files_generated.store(false, Ordering::Relaxed);
+        self.generated_websites.lock().unwrap().clear();
+        *self.profile_index.write().unwrap() = None;
+        // NOTE: Rules come from the website repository, which might have changed.
+        *self.static_file_rules.write().unwrap() = None;
+        // NOTE: The repository was pulled, so generated websites will change.
+        *self.revision.write().unwrap() = None;
+
+        Ok(state)
+    }
 
-    // Reload files
-    fs::rename(TRASH_DIR.as_path(), DEST_DIR.as_path())?;
+    pub fn recover_trash(
+        &self,
+        state: State,
+    ) -> Result<(), Error> {
+        trace!("Recovering trash…");
+
+        // Reload files
+        fs::rename(self.trash_dir.as_path(), self.dest_dir.as_path())?;
+
+        // Relaod caches
+        self.hugo_config_generated
+            .store(state.hugo_config_generated, Ordering::Relaxed);
+        self.data_files_generated
+            .store(state.data_files_generated, Ordering::Relaxed);
+        *self.generated_websites.lock().unwrap() = state.generated_websites;
+        *self.profile_index.write().unwrap() = state.profile_index;
+        // NOTE: Restored websites were generated from the previous revision.
+        *self.revision.write().unwrap() = state.revision;
 
-    // Relaod caches
-    HUGO_CONFIG_GENERATED.store(state.hugo_config_generated, Ordering::Relaxed);
-    DATA_FILES_GENERATED.store(state.data_files_generated, Ordering::Relaxed);
-    *GENERATED_WEBSITES.lock().unwrap() = state.generated_websites;
-    *super::USED_PROFILES.write().unwrap() = state.used_profiles;
+        Ok(())
+    }
 
-    Ok(())
+    /// NOTE: Needs a `State` to take ownership and make sure we don't keep outdated information.
+    pub fn empty_trash(
+        &self,
+        _state: State,
+    ) -> Result<(), Error> {
+        trace!("Emptying trash…");
+
+        fs::remove_dir_all(self.trash_dir.as_path())?;
+
+        Ok(())
+    }
 }
 
-/// NOTE: Needs a `State` to take ownership and make sure we don't keep outdated information.
-pub fn empty_trash(_state: State) -> Result<(), Error> {
-    trace!("Emptying trash…");
+impl Workspace {
+    /// Keeps only entries of a website's search index (`index.json`)
+    /// pointing to pages `id` can read.
+    ///
+    /// NOTE: Entries are matched using their `relpermalink`, `permalink` or `url`.
+    ///   Entries without any of them (or if the file is invalid) are removed.
+    fn filter_index_json(
+        &self,
+        website_dir: &Path,
+        id: &WebsiteId,
+    ) -> Result<(), io::Error> {
+        let index_json_path = website_dir.join("index.json");
+        let entries: Vec<serde_json::Value> = match fs::read(&index_json_path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|err| {
+                error!("Invalid search index, emptying it: {err}");
+                vec![]
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => vec![],
+            Err(err) => return Err(err),
+        };
+
+        let profile_index = self.profile_index();
+        let entries: Vec<serde_json::Value> = (entries.into_iter())
+            .filter(|entry| {
+                search_entry_path(entry)
+                    .is_some_and(|path| profile_index.is_readable(&id.profiles, &path))
+            })
+            .collect();
+        trace!("Keeping {} search index entries for {id}", entries.len());
+
+        // Open the file in write mode, which will truncate the file if it already exists
+        let mut file = File::create(index_json_path)?;
+        serde_json::to_writer(&mut file, &entries)?;
+        Ok(())
+    }
+}
 
-    fs::remove_dir_all(TRASH_DIR.as_path())?;
+/// Path of the page a search index entry points to.
+fn search_entry_path(entry: &serde_json::Value) -> Option<PathBuf> {
+    let url =
+        (["relpermalink", "permalink", "url"].iter()).find_map(|key| entry.get(key)?.as_str())?;
+    // Remove the scheme and host of absolute URLs.
+    let path = match url.split_once("://") {
+        Some((_, rest)) => &rest[rest.find('/').unwrap_or(rest.len())..],
+        None => url,
+    };
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    Some(PathBuf::from(path))
+}
 
-    Ok(())
+pub struct State {
+    hugo_config_generated: bool,
+    data_files_generated: bool,
+    generated_websites: HashSet<PathBuf>,
+    profile_index: Option<Arc<ProfileIndex>>,
+    revision: Option<String>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -464,10 +676,12 @@ pub enum Error {
     },
     #[error("Could not generate website: {0}")]
     CannotGenerateWebsite(Box<Error>),
-    #[error("Could not empty <index.json> file: {0}")]
-    CannotEmptyIndexJson(io::Error),
+    #[error("Could not filter <index.json> file: {0}")]
+    CannotFilterIndexJson(io::Error),
     #[error("Could create hugo config file: {0}")]
     CannotCreateHugoConfigFile(io::Error),
+    #[error("No website repository configured (set `WEBSITE_REPOSITORY`)")]
+    MissingRepository,
     #[error("IO error: {0}")]
     IOError(#[from] io::Error),
     #[error("Could not read page metadata: JSON error: {0}")]
@@ -1,122 +1,167 @@
+pub mod auth_config;
 pub mod config;
 pub mod generate;
+pub mod page_index;
+pub mod profile_index;
 pub mod readers;
+pub mod static_files;
 pub mod website_id;
+pub mod workspace;
 
 use std::{
     collections::HashSet,
     fs::{self, File},
     io,
     ops::Deref,
-    path::PathBuf,
-    sync::{Arc, RwLock},
+    path::{Path, PathBuf},
 };
 
-use lazy_static::lazy_static;
 use serde::{de::DeserializeOwned, Deserialize};
 use serde_with::{serde_as, DefaultOnNull};
-use tracing::{debug, error, trace};
+use tracing::{error, trace};
 
-use crate::config::*;
+use crate::{
+    config::*,
+    workspace::{Workspace, WORKSPACE},
+};
 
-lazy_static! {
-    static ref USED_PROFILES: Arc<RwLock<Option<&'static HashSet<String>>>> = Arc::default();
+pub fn used_profiles() -> HashSet<String> {
+    WORKSPACE.used_profiles()
 }
 
-pub fn used_profiles<'a>() -> &'a HashSet<String> {
-    if let Some(profiles) = *USED_PROFILES.read().unwrap() {
-        trace!("Read used profiles from cache");
-        return profiles;
+pub fn find(
+    dir: &PathBuf,
+    extensions: &Vec<&str>,
+    files: &mut Vec<PathBuf>,
+) {
+    for entry in fs::read_dir(dir).unwrap().flatten() {
+        let path = entry.path();
+        if path.is_file() {
+            if path
+                .extension()
+                .map(|ext| extensions.contains(&ext.to_str().unwrap()))
+                .unwrap_or(false)
+            {
+                files.push(path);
+            }
+        } else if path.is_dir() {
+            find(&path, extensions, files);
+        }
     }
+}
 
-    debug!("Reading all used profiles…");
-    let acc: &'static mut HashSet<String> = Box::leak(Box::new(HashSet::new()));
+pub fn data_file_path(page_relpath: &Path) -> PathBuf {
+    WORKSPACE.data_file_path(page_relpath)
+}
 
-    for data_file in find_data_files() {
-        // trace!("Reading <{}>…", data_file.display());
+/// Reads the metadata of all pages, skipping (and logging) invalid data files.
+pub fn all_pages_metadata() -> Vec<PageMetadata> {
+    WORKSPACE.all_pages_metadata()
+}
 
-        let metadata: PageMetadata = match deser(&data_file) {
-            Ok(Some(metadata)) => metadata,
-            Ok(None) => {
-                error!(
-                    "Could not read page metadata at <{}>: File not found",
-                    data_file.display(),
-                );
-                continue;
-            },
-            Err(err) => {
-                error!(
-                    "Could not read page metadata at <{}>: {err}",
-                    data_file.display(),
-                );
-                continue;
-            },
+impl Workspace {
+    pub fn data_file_path(
+        &self,
+        page_relpath: &Path,
+    ) -> PathBuf {
+        let mut data_file_relpath = page_relpath.with_extension(DATA_FILE_EXTENSION);
+        data_file_relpath = match data_file_relpath.strip_prefix("/") {
+            Ok(trimmed) => trimmed.to_path_buf(),
+            Err(_) => data_file_relpath,
         };
-        let read_allowed = metadata.read_allowed;
-        // trace!("  read_allowed: {:?}", read_allowed);
-
-        // Store new profiles
-        read_allowed.into_iter().for_each(|p| {
-            acc.insert(p.to_owned());
-        });
+        self.website_data_dir.join(data_file_relpath)
     }
 
-    *USED_PROFILES.write().unwrap() = Some(acc);
-
-    acc
-}
+    /// Reads the metadata of all pages, skipping (and logging) invalid data files.
+    pub fn all_pages_metadata(&self) -> Vec<PageMetadata> {
+        (self.all_data_files().into_iter())
+            .map(|(_, metadata)| metadata)
+            .collect()
+    }
 
-pub fn find(
-    dir: &PathBuf,
-    extensions: &Vec<&str>,
-    files: &mut Vec<PathBuf>,
-) {
-    for entry in fs::read_dir(dir).unwrap() {
-        if let Ok(entry) = entry {
-            let path = entry.path();
-            if path.is_file() {
-                if path
-                    .extension()
-                    .map(|ext| extensions.contains(&ext.to_str().unwrap()))
-                    .unwrap_or(false)
-                {
-                    files.push(path);
-                }
-            } else if path.is_dir() {
-                find(&path, extensions, files);
+    /// Same as [`Workspace::all_pages_metadata`], with the path of data files.
+    pub(crate) fn all_data_files(&self) -> Vec<(PathBuf, PageMetadata)> {
+        let mut pages = Vec::new();
+
+        for data_file in self.find_data_files() {
+            // trace!("Reading <{}>…", data_file.display());
+
+            match deser(&data_file) {
+                Ok(Some(metadata)) => pages.push((data_file, metadata)),
+                Ok(None) => {
+                    error!(
+                        "Could not read page metadata at <{}>: File not found",
+                        data_file.display(),
+                    );
+                },
+                Err(err) => {
+                    error!(
+                        "Could not read page metadata at <{}>: {err}",
+                        data_file.display(),
+                    );
+                },
             }
         }
+
+        pages
     }
-}
 
-pub fn data_file_path(page_relpath: &PathBuf) -> PathBuf {
-    let mut data_file_relpath = page_relpath.with_extension(DATA_FILE_EXTENSION);
-    data_file_relpath = match data_file_relpath.strip_prefix("/") {
-        Ok(trimmed) => trimmed.to_path_buf(),
-        Err(_) => data_file_relpath,
-    };
-    WEBSITE_DATA_DIR.join(data_file_relpath)
-}
+    fn find_data_files(&self) -> Vec<PathBuf> {
+        let mut data_files: Vec<PathBuf> = Vec::new();
+        find(
+            &self.website_data_dir,
+            &vec![DATA_FILE_EXTENSION],
+            &mut data_files,
+        );
+        data_files
+    }
 
-fn find_data_files() -> Vec<PathBuf> {
-    let mut data_files: Vec<PathBuf> = Vec::new();
-    find(
-        &WEBSITE_DATA_DIR,
-        &vec![DATA_FILE_EXTENSION],
-        &mut data_files,
-    );
-    data_files
+    // `Ok(None)` if file not found.
+    // `Err(_)` if file found but deserialization error.
+    // `Ok(Some(_))` if file found.
+    //
+    // NOTE: Uses the in-memory [`PageIndex`](page_index::PageIndex) once data files
+    //   have been generated, data files are only read before.
+    pub fn page_metadata(
+        &self,
+        page_relpath: &Path,
+    ) -> Result<Option<PageMetadata>, serde_json::Error> {
+        if let Some(page_index) = self.page_index.read().unwrap().as_ref() {
+            return Ok(page_index.get(page_relpath).cloned());
+        }
+
+        trace!("Page index not built yet, reading data files…");
+        let page_relpath = PathBuf::from(page_index::decode_path(page_relpath));
+        let page_relpath = page_relpath.as_path();
+        let mut file_paths = vec![
+            self.data_file_path(page_relpath),
+            self.data_file_path(&page_relpath.join("index.html")),
+        ];
+        // Don't try parsing the exact path if it points to a directory.
+        if page_relpath.is_dir() {
+            file_paths.remove(0);
+        }
+        deser_first_match(file_paths)
+    }
 }
 
 #[serde_as]
-#[derive(Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct PageMetadata {
     // NOTE: Hugo taxonomy term pages contain `read_allowed": null`.
     // TODO: Investigate and fix this, to remove the `serde` default which could be in conflict with the user's preference.
     #[serde(default)]
     #[serde_as(deserialize_as = "DefaultOnNull")]
     pub read_allowed: ReadAllowed,
+    /// Profiles allowed to comment on the page (none by default).
+    #[serde(default)]
+    #[serde_as(deserialize_as = "DefaultOnNull")]
+    pub comment_allowed: Vec<String>,
     pub path: PathBuf,
+    /// Optional Datalog snippet evaluated with the token when authorizing access
+    /// (e.g. `check if time($time), $time >= 2025-01-01T00:00:00Z;` for an embargo).
+    #[serde(default)]
+    pub policy: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -170,19 +215,8 @@ fn deser_first_match<T: DeserializeOwned>(
     Ok(None)
 }
 
-// `Ok(None)` if file not found.
-// `Err(_)` if file found but deserialization error.
-// `Ok(Some(_))` if file found.
-pub fn page_metadata(page_relpath: &PathBuf) -> Result<Option<PageMetadata>, serde_json::Error> {
-    let mut file_paths = vec![
-        data_file_path(page_relpath),
-        data_file_path(&page_relpath.join("index.html")),
-    ];
-    // Don't try parsing the exact path if it points to a directory.
-    if page_relpath.is_dir() {
-        file_paths.remove(0);
-    }
-    deser_first_match(file_paths)
+pub fn page_metadata(page_relpath: &Path) -> Result<Option<PageMetadata>, serde_json::Error> {
+    WORKSPACE.page_metadata(page_relpath)
 }
 
 pub fn copy_directory(
@@ -0,0 +1,191 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use tracing::{debug, warn};
+
+use crate::{profile_index::ProfileIndex, workspace::Workspace, PageMetadata};
+
+/// Metadata of all pages, by path, so requests don't have to read data files.
+///
+/// Lookups are normalized: paths are percent-decoded and case-insensitive,
+/// and `/page`, `/page/` and `/page/index.html` all find the same page.
+#[derive(Debug, Default)]
+pub struct PageIndex {
+    /// Keys are data file paths relative to the data directory, lowercased
+    /// and without extension (e.g. `blog/post/index`).
+    pages: HashMap<PathBuf, PageMetadata>,
+}
+
+impl PageIndex {
+    /// `data_files` are paths relative to the data directory, with their metadata.
+    pub fn from_data_files(data_files: impl IntoIterator<Item = (PathBuf, PageMetadata)>) -> Self {
+        let mut pages = HashMap::new();
+        for (data_file_relpath, metadata) in data_files {
+            let key = PathBuf::from(data_file_relpath.to_string_lossy().to_lowercase())
+                .with_extension("");
+            if pages.contains_key(&key) {
+                warn!(
+                    "Data file <{}> conflicts with another one (paths are case-insensitive), ignoring it.",
+                    data_file_relpath.display(),
+                );
+                continue;
+            }
+            pages.insert(key, metadata);
+        }
+        Self { pages }
+    }
+
+    pub fn get(
+        &self,
+        page_relpath: &Path,
+    ) -> Option<&PageMetadata> {
+        let page_relpath = normalize(page_relpath);
+        // NOTE: Same order as when reading data files, see `Workspace::page_metadata`.
+        [
+            page_relpath.with_extension(""),
+            page_relpath.join("index"),
+        ]
+        .iter()
+        .find_map(|key| self.pages.get(key))
+    }
+
+    /// The closest page whose bundle contains `file_relpath`
+    /// (i.e. the closest parent directory which is a page).
+    pub fn bundle_page(
+        &self,
+        file_relpath: &Path,
+    ) -> Option<&PageMetadata> {
+        let file_relpath = normalize(file_relpath);
+        // NOTE: Files at the root don't belong to the home page.
+        (file_relpath.ancestors().skip(1))
+            .filter(|dir| dir.parent().is_some())
+            .find_map(|dir| self.pages.get(&dir.join("index")))
+    }
+}
+
+/// Decodes and lowercases `path`, relative to the website root.
+fn normalize(path: &Path) -> PathBuf {
+    let path = PathBuf::from(decode_path(path).to_lowercase());
+    match path.strip_prefix("/") {
+        Ok(relpath) => relpath.to_path_buf(),
+        Err(_) => path,
+    }
+}
+
+/// Percent-decodes a request path, keeping it as is if it's not valid UTF-8 once decoded.
+pub(crate) fn decode_path(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    match urlencoding::decode(&path) {
+        Ok(decoded) => decoded.into_owned(),
+        Err(_) => path.into_owned(),
+    }
+}
+
+impl Workspace {
+    /// Rebuilds the [`PageIndex`] and [`ProfileIndex`] from data files,
+    /// then swaps them with the previous ones.
+    pub fn reload_indexes(&self) {
+        debug!("Building page and profile indexes…");
+        let data_files = self.all_data_files();
+        let profile_index =
+            ProfileIndex::from_pages(data_files.iter().map(|(_, page)| page.clone()));
+        let page_index = PageIndex::from_data_files(data_files.into_iter().map(|(path, page)| {
+            let relpath = (path
+                .strip_prefix(&self.website_data_dir)
+                .map(Path::to_path_buf))
+            .unwrap_or(path);
+            (relpath, page)
+        }));
+
+        *self.profile_index.write().unwrap() = Some(Arc::new(profile_index));
+        *self.page_index.write().unwrap() = Some(Arc::new(page_index));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::PageIndex;
+
+    fn index() -> PageIndex {
+        PageIndex::from_data_files(
+            [
+                ("blog/index.orangutan", "/blog/"),
+                ("Famille/Été/index.orangutan", "/famille/ete/"),
+                ("famille/photo.orangutan", "/famille/photo/"),
+            ]
+            .map(|(data_file, path)| {
+                let page = format!(r#"{{ "read_allowed": ["famille"], "path": "{path}" }}"#);
+                (
+                    PathBuf::from(data_file),
+                    serde_json::from_str(&page).unwrap(),
+                )
+            }),
+        )
+    }
+
+    fn path(
+        index: &PageIndex,
+        page_relpath: &str,
+    ) -> Option<String> {
+        (index.get(Path::new(page_relpath))).map(|page| page.path.display().to_string())
+    }
+
+    #[test]
+    fn test_normalized_lookup() {
+        let index = index();
+        for page_relpath in [
+            "/blog/",
+            "/blog",
+            "/blog/index.html",
+            "/BLOG/",
+            "blog/",
+        ] {
+            assert_eq!(
+                path(&index, page_relpath).as_deref(),
+                Some("/blog/"),
+                "{page_relpath}"
+            );
+        }
+        for page_relpath in [
+            "/famille/Été/",
+            "/famille/%C3%A9t%C3%A9/",
+            "/FAMILLE/%C3%89T%C3%89/index.html",
+        ] {
+            assert_eq!(
+                path(&index, page_relpath).as_deref(),
+                Some("/famille/ete/"),
+                "{page_relpath}"
+            );
+        }
+        assert_eq!(
+            path(&index, "/famille/photo.html").as_deref(),
+            Some("/famille/photo/")
+        );
+        assert_eq!(path(&index, "/famille/"), None);
+        assert_eq!(path(&index, "/"), None);
+    }
+
+    #[test]
+    fn test_bundle_page() {
+        let index = index();
+        let bundle_page = |file_relpath: &str| {
+            (index.bundle_page(Path::new(file_relpath))).map(|page| page.path.display().to_string())
+        };
+        assert_eq!(bundle_page("/blog/cover.jpg").as_deref(), Some("/blog/"));
+        assert_eq!(
+            bundle_page("/blog/images/cover.jpg").as_deref(),
+            Some("/blog/")
+        );
+        assert_eq!(
+            bundle_page("/famille/%C3%A9t%C3%A9/photo.jpg").as_deref(),
+            Some("/famille/ete/"),
+        );
+        assert_eq!(bundle_page("/famille/other.jpg"), None);
+        assert_eq!(bundle_page("/style.css"), None);
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use tracing::{debug, trace};
+
+use crate::{config::DEFAULT_PROFILE, workspace::Workspace, PageMetadata};
+
+/// Profiles used by a website, and the pages each of them unlocks.
+///
+/// Built from data files, then shared using an [`Arc`] so it can be replaced
+/// when data files are regenerated without blocking readers.
+#[derive(Debug, Default)]
+pub struct ProfileIndex {
+    pages_by_profile: HashMap<String, BTreeSet<PathBuf>>,
+    /// Pages with a custom Datalog policy, which might be narrower
+    /// than what `read_allowed` says.
+    pages_with_policy: HashSet<PathBuf>,
+}
+
+impl ProfileIndex {
+    pub fn from_pages(pages: impl IntoIterator<Item = PageMetadata>) -> Self {
+        let mut index = Self::default();
+        for page in pages {
+            if page.policy.is_some() {
+                index.pages_with_policy.insert(page.path.clone());
+            }
+            for profile in page.read_allowed {
+                (index.pages_by_profile.entry(profile))
+                    .or_default()
+                    .insert(page.path.clone());
+            }
+        }
+        index
+    }
+
+    /// All profiles allowed to read at least one page.
+    pub fn profiles(&self) -> impl Iterator<Item = &String> {
+        self.pages_by_profile.keys()
+    }
+
+    pub fn contains(
+        &self,
+        profile: &str,
+    ) -> bool {
+        self.pages_by_profile.contains_key(profile)
+    }
+
+    /// Pages `profile` is allowed to read, sorted by path.
+    pub fn pages(
+        &self,
+        profile: &str,
+    ) -> impl Iterator<Item = &Path> {
+        (self.pages_by_profile.get(profile).into_iter())
+            .flatten()
+            .map(PathBuf::as_path)
+    }
+
+    /// Whether or not someone with `profiles` can always read the page at `path`.
+    ///
+    /// NOTE: Pages with a custom policy are never considered readable, as it cannot be
+    ///   evaluated without a request. Use this to filter what is listed (e.g. search
+    ///   indexes), not to authorize access.
+    pub fn is_readable(
+        &self,
+        profiles: &HashSet<String>,
+        path: &Path,
+    ) -> bool {
+        if self.pages_with_policy.contains(path) {
+            return false;
+        }
+        if profiles.contains("*") {
+            return self
+                .pages_by_profile
+                .values()
+                .any(|pages| pages.contains(path));
+        }
+        (profiles.iter().map(String::as_str))
+            .chain([DEFAULT_PROFILE])
+            .any(|profile| self.pages(profile).any(|page| page == path))
+    }
+}
+
+impl Workspace {
+    /// The [`ProfileIndex`] of the website, built from data files on first use.
+    pub fn profile_index(&self) -> Arc<ProfileIndex> {
+        if let Some(index) = self.profile_index.read().unwrap().as_ref() {
+            trace!("Read profile index from cache");
+            return index.clone();
+        }
+        self.reload_profile_index()
+    }
+
+    /// Rebuilds the [`ProfileIndex`] from data files.
+    ///
+    /// NOTE: Readers holding the previous index keep using it until they drop it.
+    pub fn reload_profile_index(&self) -> Arc<ProfileIndex> {
+        debug!("Building profile index…");
+        let index = Arc::new(ProfileIndex::from_pages(self.all_pages_metadata()));
+        *self.profile_index.write().unwrap() = Some(index.clone());
+        index
+    }
+
+    /// Profiles allowed to read at least one page.
+    pub fn used_profiles(&self) -> HashSet<String> {
+        self.profile_index().profiles().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, path::Path};
+
+    use super::ProfileIndex;
+    use crate::PageMetadata;
+
+    fn page(json: &str) -> PageMetadata {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn profiles(profiles: &[&str]) -> HashSet<String> {
+        profiles.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn test_is_readable() {
+        let index = ProfileIndex::from_pages([
+            page(r#"{ "path": "/blog/" }"#),
+            page(r#"{ "read_allowed": ["amis", "famille"], "path": "/photos/" }"#),
+            page(r#"{ "read_allowed": ["famille"], "path": "/famille/" }"#),
+            page(
+                r#"{ "read_allowed": ["famille"], "path": "/later/", "policy": "check if false;" }"#,
+            ),
+        ]);
+
+        let mut used_profiles: Vec<&String> = index.profiles().collect();
+        used_profiles.sort();
+        assert_eq!(used_profiles, vec!["_default", "amis", "famille"]);
+        assert_eq!(index.pages("famille").collect::<Vec<_>>(), vec![
+            Path::new("/famille/"),
+            Path::new("/later/"),
+            Path::new("/photos/"),
+        ]);
+
+        let amis = profiles(&["amis"]);
+        assert!(index.is_readable(&amis, Path::new("/blog/")));
+        assert!(index.is_readable(&amis, Path::new("/photos/")));
+        assert!(!index.is_readable(&amis, Path::new("/famille/")));
+        assert!(!index.is_readable(&amis, Path::new("/unknown/")));
+        assert!(index.is_readable(&profiles(&["*"]), Path::new("/famille/")));
+        assert!(!index.is_readable(&profiles(&["*"]), Path::new("/later/")));
+    }
+}
@@ -17,14 +17,23 @@ lazy_static! {
 }
 
 pub trait KeysReader {
-    fn get_root_biscuit_key(&self) -> Result<biscuit::KeyPair, Error>;
+    fn get_root_biscuit_key(&self) -> Result<biscuit::KeyPair, Error> {
+        self.get_biscuit_key(ROOT_KEY_NAME)
+    }
+
+    /// Reads (or creates, depending on the reader) the Biscuit key named `key_name`.
+    fn get_biscuit_key(
+        &self,
+        key_name: &str,
+    ) -> Result<biscuit::KeyPair, Error>;
 }
 
 impl dyn KeysReader {
     pub fn detect() -> Box<dyn KeysReader> {
         match KEYS_MODE.clone().unwrap_or("".to_string()).as_str() {
             "LOCAL" => Box::new(LocalKeysReader {}),
-            "ENV" | _ => Box::new(EnvKeysReader {}),
+            // "ENV" or anything else
+            _ => Box::new(EnvKeysReader {}),
         }
     }
 }
@@ -32,9 +41,10 @@ impl dyn KeysReader {
 struct EnvKeysReader {}
 
 impl KeysReader for EnvKeysReader {
-    fn get_root_biscuit_key(&self) -> Result<biscuit::KeyPair, Error> {
-        let key_name = ROOT_KEY_NAME;
-
+    fn get_biscuit_key(
+        &self,
+        key_name: &str,
+    ) -> Result<biscuit::KeyPair, Error> {
         let env_var_name = format!("KEY_{}", key_name);
         trace!(
             "Reading key '{}' from environment ({})…",
@@ -59,9 +69,10 @@ impl LocalKeysReader {
 }
 
 impl KeysReader for LocalKeysReader {
-    fn get_root_biscuit_key(&self) -> Result<biscuit::KeyPair, Error> {
-        let key_name = ROOT_KEY_NAME;
-
+    fn get_biscuit_key(
+        &self,
+        key_name: &str,
+    ) -> Result<biscuit::KeyPair, Error> {
         let key_file = self.key_file(key_name);
 
         if key_file.exists() {
@@ -0,0 +1,283 @@
+use std::{
+    fs::File,
+    io::{BufRead as _, BufReader},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use tracing::{error, info, trace};
+
+use crate::{
+    deser, generate::Error, page_index::decode_path, workspace::Workspace, PageMetadata,
+    ReadAllowed,
+};
+
+/// A rule giving access to static files (files which are not pages
+/// nor resources of a page bundle).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaticFileRule {
+    /// Lowercased glob pattern (e.g. `/css/**` or `/images/*.png`).
+    ///
+    /// `*` matches anything except `/`, `**` (as a whole path component)
+    /// matches anything and `?` matches any character except `/`.
+    pub pattern: String,
+    /// Empty means nobody can read matching files.
+    pub read_allowed: Vec<String>,
+}
+
+/// Static files rules, with their patterns compiled once
+/// so matching a path doesn't depend on the patterns' shape.
+#[derive(Debug, Clone)]
+pub struct StaticFileRules {
+    rules: Vec<StaticFileRule>,
+    globs: GlobSet,
+}
+
+impl StaticFileRules {
+    /// NOTE: Patterns are validated when parsing rules,
+    ///   [`parse_static_file_rule`] rejects invalid ones.
+    pub fn new(rules: Vec<StaticFileRule>) -> Result<Self, globset::Error> {
+        let mut globs = GlobSetBuilder::new();
+        for rule in rules.iter() {
+            globs.add(glob(&rule.pattern)?);
+        }
+        Ok(Self {
+            globs: globs.build()?,
+            rules,
+        })
+    }
+
+    /// The first rule matching `path`.
+    ///
+    /// NOTE: Paths are case-insensitive, like page paths.
+    pub fn find(
+        &self,
+        path: &str,
+    ) -> Option<&StaticFileRule> {
+        let index = self.globs.matches(path.to_lowercase()).into_iter().min()?;
+        self.rules.get(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+impl Default for StaticFileRules {
+    fn default() -> Self {
+        Self {
+            rules: Vec::new(),
+            globs: GlobSet::empty(),
+        }
+    }
+}
+
+fn glob(pattern: &str) -> Result<globset::Glob, globset::Error> {
+    GlobBuilder::new(pattern).literal_separator(true).build()
+}
+
+fn parse_static_file_rule(line: &str) -> Result<Option<StaticFileRule>, ()> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    // NOTE: Profiles never contain `:`, but paths might.
+    let (pattern, profiles) = line.rsplit_once(':').ok_or(())?;
+    let pattern = pattern.trim().to_lowercase();
+    if !pattern.starts_with('/') || glob(&pattern).is_err() {
+        return Err(());
+    }
+    Ok(Some(StaticFileRule {
+        pattern,
+        read_allowed: (profiles.split(','))
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(ToOwned::to_owned)
+            .collect(),
+    }))
+}
+
+impl Workspace {
+    /// Reads `static_files.txt` at the root of the website repository.
+    ///
+    /// Each line is `pattern:profile1,profile2` (e.g. `/css/**:_default`),
+    /// the first matching rule wins.
+    /// Empty lines and lines starting with `#` are ignored.
+    pub fn read_static_file_rules(&self) -> Result<StaticFileRules, Error> {
+        let rules_file_path = self.website_root.join("static_files.txt");
+        let Ok(rules_file) = File::open(&rules_file_path) else {
+            info!(
+                "Static files rules file not found at <{}>. Considering no rule.",
+                rules_file_path.display(),
+            );
+            return Ok(StaticFileRules::default());
+        };
+
+        let mut rules = Vec::new();
+        for line in BufReader::new(rules_file).lines() {
+            let line = line?;
+            match parse_static_file_rule(&line) {
+                Ok(Some(rule)) => rules.push(rule),
+                Ok(None) => {},
+                Err(()) => error!("Invalid line in <{}>, skipping.", rules_file_path.display()),
+            }
+        }
+        info!("Found {} static files rule(s).", rules.len());
+        // NOTE: Invalid patterns were skipped above.
+        Ok(StaticFileRules::new(rules).expect("Patterns should be valid"))
+    }
+
+    /// Static files rules, read on first use.
+    pub fn static_file_rules(&self) -> Result<Arc<StaticFileRules>, Error> {
+        if let Some(rules) = self.static_file_rules.read().unwrap().as_ref() {
+            return Ok(rules.clone());
+        }
+        let rules = Arc::new(self.read_static_file_rules()?);
+        *self.static_file_rules.write().unwrap() = Some(rules.clone());
+        Ok(rules)
+    }
+
+    /// Metadata deciding who can read the file at `file_relpath`, in order:
+    ///
+    /// 1. The metadata of the page itself.
+    /// 2. The metadata of the page bundle containing the file
+    ///    (e.g. `/famille/` for `/famille/photo.jpg`).
+    /// 3. The first matching static file rule.
+    ///
+    /// `None` if nothing matches.
+    pub fn file_metadata(
+        &self,
+        file_relpath: &Path,
+    ) -> Result<Option<PageMetadata>, Error> {
+        if let Some(page) =
+            (self.page_metadata(file_relpath)).map_err(Error::CannotReadPageMetadata)?
+        {
+            return Ok(Some(page));
+        }
+        if let Some(page) =
+            (self.bundle_page_metadata(file_relpath)).map_err(Error::CannotReadPageMetadata)?
+        {
+            trace!(
+                "<{}> belongs to page <{}>",
+                file_relpath.display(),
+                page.path.display()
+            );
+            return Ok(Some(page));
+        }
+
+        let path = decode_path(file_relpath);
+        let rules = self.static_file_rules()?;
+        let Some(rule) = rules.find(&path) else {
+            return Ok(None);
+        };
+        trace!("<{path}> matches static files rule '{}'", rule.pattern);
+        Ok(Some(PageMetadata {
+            read_allowed: ReadAllowed(rule.read_allowed.clone()),
+            comment_allowed: vec![],
+            path: PathBuf::from(path),
+            policy: None,
+        }))
+    }
+
+    /// Metadata of the closest page whose bundle contains `file_relpath`.
+    fn bundle_page_metadata(
+        &self,
+        file_relpath: &Path,
+    ) -> Result<Option<PageMetadata>, serde_json::Error> {
+        if let Some(page_index) = self.page_index.read().unwrap().as_ref() {
+            return Ok(page_index.bundle_page(file_relpath).cloned());
+        }
+
+        let file_relpath = PathBuf::from(decode_path(file_relpath));
+        // NOTE: Files at the root don't belong to the home page.
+        for dir in (file_relpath.ancestors().skip(1)).filter(|dir| dir.parent().is_some()) {
+            if let Some(page) = deser(&self.data_file_path(&dir.join("index.html")))? {
+                return Ok(Some(page));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_static_file_rule, StaticFileRule, StaticFileRules};
+
+    fn glob_matches(
+        pattern: &str,
+        path: &str,
+    ) -> bool {
+        let rule = StaticFileRule {
+            pattern: pattern.to_owned(),
+            read_allowed: vec![],
+        };
+        StaticFileRules::new(vec![rule])
+            .unwrap()
+            .find(path)
+            .is_some()
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("/css/**", "/css/"));
+        assert!(glob_matches("/css/**", "/css/fonts/a.woff2"));
+        assert!(!glob_matches("/css/**", "/cssx/a.css"));
+        assert!(glob_matches("/images/*.png", "/images/a.png"));
+        assert!(!glob_matches("/images/*.png", "/images/sub/a.png"));
+        assert!(!glob_matches("/images/*.png", "/images/a.jpg"));
+        assert!(glob_matches("/**/*.png", "/a.png"));
+        assert!(glob_matches("/**/*.png", "/a/b/c.png"));
+        assert!(glob_matches("/favicon.ic?", "/favicon.ico"));
+        assert!(glob_matches("/été/*", "/été/a"));
+        // NOTE: Used to take exponential time.
+        assert!(!glob_matches(
+            &format!("/{}b", "*a".repeat(30)),
+            &format!("/{}", "a".repeat(60))
+        ));
+    }
+
+    #[test]
+    fn test_first_matching_rule_wins() {
+        let rule = |pattern: &str, profile: &str| StaticFileRule {
+            pattern: pattern.to_owned(),
+            read_allowed: vec![profile.to_owned()],
+        };
+        let rules = StaticFileRules::new(vec![
+            rule("/private/**", "famille"),
+            rule("/**", "_default"),
+        ])
+        .unwrap();
+        assert_eq!(rules.find("/private/a.txt").unwrap().read_allowed, vec![
+            "famille"
+        ]);
+        assert_eq!(rules.find("/a.txt").unwrap().read_allowed, vec!["_default"]);
+    }
+
+    #[test]
+    fn test_parse_static_file_rule() {
+        assert_eq!(parse_static_file_rule("# Comment"), Ok(None));
+        assert_eq!(parse_static_file_rule("css/**:_default"), Err(()));
+        let rule = parse_static_file_rule(" /Private/** : famille, amis ")
+            .unwrap()
+            .unwrap();
+        assert_eq!(rule, StaticFileRule {
+            pattern: "/private/**".to_owned(),
+            read_allowed: vec!["famille".to_owned(), "amis".to_owned()],
+        });
+        let rules = StaticFileRules::new(vec![rule]).unwrap();
+        assert!(rules.find("/PRIVATE/notes.txt").is_some());
+        assert_eq!(parse_static_file_rule("/[:famille"), Err(()));
+        assert_eq!(
+            parse_static_file_rule("/secret/**:")
+                .unwrap()
+                .unwrap()
+                .read_allowed,
+            Vec::<String>::new()
+        );
+    }
+}
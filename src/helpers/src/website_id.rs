@@ -1,6 +1,9 @@
 use std::{collections::HashSet, fmt::Display, path::PathBuf};
 
-use crate::{config::*, used_profiles};
+use crate::{
+    config::*,
+    workspace::{Workspace, WORKSPACE},
+};
 
 pub struct WebsiteId {
     pub profiles: HashSet<String>,
@@ -39,29 +42,13 @@ impl Display for WebsiteId {
 impl Default for WebsiteId {
     fn default() -> Self {
         let profiles = vec![DEFAULT_PROFILE.to_string()].into_iter().collect();
-        return Self { profiles };
+        Self { profiles }
     }
 }
 
 impl From<&Vec<String>> for WebsiteId {
     fn from(value: &Vec<String>) -> Self {
-        if value.is_empty() {
-            return Self::default();
-        }
-
-        // Convert Vec<String> to HashSet<String> to get unique profiles
-        let mut profiles: HashSet<String> = value.to_owned().into_iter().collect();
-
-        // Keep only profiles used by the website
-        let mut used_profiles = used_profiles().clone();
-        // Insert special "*" profile so it is kept for website generation
-        used_profiles.insert("*".to_string());
-        profiles = profiles
-            .intersection(&used_profiles)
-            .map(|s| s.clone())
-            .collect();
-
-        return Self { profiles };
+        WORKSPACE.website_id(value)
     }
 }
 
@@ -71,5 +58,36 @@ impl From<&Vec<String>> for WebsiteId {
 /// Website directory is suffixed by "@<p>" where "p" is a list of profiles,
 /// sorted alphabetically and joined with ",".
 pub fn website_dir(id: &WebsiteId) -> PathBuf {
-    DEST_DIR.join(id.dir_name())
+    WORKSPACE.website_dir(id)
+}
+
+impl Workspace {
+    /// See [`website_dir`].
+    pub fn website_dir(
+        &self,
+        id: &WebsiteId,
+    ) -> PathBuf {
+        self.dest_dir.join(id.dir_name())
+    }
+
+    /// Returns the [`WebsiteId`] for a user's profiles,
+    /// keeping only profiles used by the website.
+    pub fn website_id(
+        &self,
+        profiles: &[String],
+    ) -> WebsiteId {
+        if profiles.is_empty() {
+            return WebsiteId::default();
+        }
+
+        // Keep only profiles used by the website
+        // NOTE: The special "*" profile is kept for website generation.
+        let profile_index = self.profile_index();
+        let profiles: HashSet<String> = (profiles.iter())
+            .filter(|profile| *profile == "*" || profile_index.contains(profile))
+            .cloned()
+            .collect();
+
+        WebsiteId { profiles }
+    }
 }
@@ -0,0 +1,149 @@
+use std::{
+    collections::HashSet,
+    env,
+    path::{Path, PathBuf},
+    sync::{atomic::AtomicBool, Arc, Mutex, RwLock},
+};
+
+use lazy_static::lazy_static;
+
+use crate::{
+    config::BASE_DIR, page_index::PageIndex, profile_index::ProfileIndex,
+    static_files::StaticFileRules,
+};
+
+lazy_static! {
+    /// The workspace in [`BASE_DIR`], used by global functions
+    /// (e.g. [`generate_website_if_needed`]) kept for compatibility.
+    ///
+    /// [`generate_website_if_needed`]: crate::generate::generate_website_if_needed
+    pub static ref WORKSPACE: Arc<Workspace> = Arc::new(Workspace::new(
+        BASE_DIR.as_path(),
+        env::var("WEBSITE_REPOSITORY").unwrap_or_default(),
+    ));
+}
+
+/// Paths and caches of one website (its repository, generated websites, data files…).
+///
+/// NOTE: Workspaces are independent from each other, so one process
+///   can manage multiple websites as long as their `base_dir` differ.
+#[derive(Debug)]
+pub struct Workspace {
+    /// URL (or path) of the website's Git repository.
+    pub repository: String,
+    pub base_dir: PathBuf,
+    pub tmp_dir: PathBuf,
+    /// Tokens revoked at runtime, in addition to the website's `revoked_tokens.txt`.
+    pub local_revoked_tokens_file: PathBuf,
+    /// Where the website repository is cloned.
+    pub website_root: PathBuf,
+    pub hugo_config_dir: PathBuf,
+    pub dest_dir: PathBuf,
+    pub website_data_dir: PathBuf,
+    pub(crate) trash_dir: PathBuf,
+    pub(crate) hugo_config_generated: AtomicBool,
+    pub(crate) data_files_generated: AtomicBool,
+    pub(crate) generated_websites: Mutex<HashSet<PathBuf>>,
+    pub(crate) profile_index: RwLock<Option<Arc<ProfileIndex>>>,
+    /// `None` until data files are generated.
+    pub(crate) page_index: RwLock<Option<Arc<PageIndex>>>,
+    pub(crate) static_file_rules: RwLock<Option<Arc<StaticFileRules>>>,
+    /// `None` until first needed, see [`Workspace::revision`].
+    pub(crate) revision: RwLock<Option<String>>,
+}
+
+impl Workspace {
+    pub fn new(
+        base_dir: &Path,
+        repository: String,
+    ) -> Self {
+        let tmp_dir = base_dir.join("tmp");
+        let dest_dir = base_dir.join("out");
+        Self {
+            repository,
+            base_dir: base_dir.to_path_buf(),
+            local_revoked_tokens_file: base_dir.join("revoked_tokens.txt"),
+            website_root: base_dir.join("website-src"),
+            hugo_config_dir: base_dir.join("hugo-config"),
+            website_data_dir: dest_dir.join("data"),
+            trash_dir: tmp_dir.join("trash"),
+            tmp_dir,
+            dest_dir,
+            hugo_config_generated: AtomicBool::new(false),
+            data_files_generated: AtomicBool::new(false),
+            generated_websites: Mutex::default(),
+            profile_index: RwLock::default(),
+            page_index: RwLock::default(),
+            static_file_rules: RwLock::default(),
+            revision: RwLock::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::Path, process};
+
+    use super::Workspace;
+
+    fn workspace(
+        name: &str,
+        profile: &str,
+    ) -> Workspace {
+        let base_dir =
+            env::temp_dir().join(format!("orangutan-workspace-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&base_dir);
+        let workspace = Workspace::new(&base_dir, String::new());
+        fs::create_dir_all(workspace.website_data_dir.join("page")).unwrap();
+        fs::write(
+            workspace.website_data_dir.join("page/index.orangutan"),
+            format!(r#"{{ "read_allowed": ["{profile}"], "path": "/page/" }}"#),
+        )
+        .unwrap();
+        workspace
+    }
+
+    #[test]
+    fn test_workspaces_are_independent() {
+        let a = workspace("a", "amis");
+        let b = workspace("b", "famille");
+
+        let page = Path::new("/page/");
+        let read_allowed = |workspace: &Workspace| {
+            (workspace.page_metadata(page).unwrap().unwrap().read_allowed).to_vec()
+        };
+        assert_eq!(read_allowed(&a), vec!["amis".to_owned()]);
+        assert_eq!(read_allowed(&b), vec!["famille".to_owned()]);
+        assert!(a.used_profiles().contains("amis"));
+        assert!(!b.used_profiles().contains("amis"));
+        assert_eq!(
+            a.website_id(&["amis".to_owned(), "famille".to_owned()])
+                .name(),
+            "amis",
+        );
+
+        fs::remove_dir_all(&a.base_dir).unwrap();
+        fs::remove_dir_all(&b.base_dir).unwrap();
+    }
+
+    #[test]
+    fn test_reload_profile_index() {
+        let workspace = workspace("reload", "amis");
+        let index = workspace.profile_index();
+        assert!(!index.contains("famille"));
+
+        fs::write(
+            workspace.website_data_dir.join("page/famille.orangutan"),
+            r#"{ "read_allowed": ["famille"], "path": "/page/famille/" }"#,
+        )
+        .unwrap();
+        // The index is cached until it is reloaded.
+        assert!(!workspace.profile_index().contains("famille"));
+        assert!(workspace.reload_profile_index().contains("famille"));
+        assert!(workspace.used_profiles().contains("famille"));
+        // Previous readers are not affected.
+        assert!(!index.contains("famille"));
+
+        fs::remove_dir_all(&workspace.base_dir).unwrap();
+    }
+}
@@ -25,13 +25,35 @@ lazy_static! {
     };
 }
 
+/// Context of the block which limits the lifetime of a refresh token.
+///
+/// When a refresh token is exchanged for an access token, this block is dropped
+/// but all other blocks are kept so attenuations are preserved.
+pub const EXPIRY_BLOCK_CONTEXT: &str = "refresh_token_expiry";
+
 pub struct RefreshToken(Biscuit);
 
 impl RefreshToken {
     /// Try to create a `RefreshToken` from strongly typed data.
+    ///
+    /// If `max_uses` is set, a random `nonce` fact and a `max_uses` fact
+    /// are added so the server can count how many times the token
+    /// has been redeemed.
     pub fn new(
         duration: std::time::Duration,
         profiles: impl Iterator<Item = String>,
+        max_uses: Option<u32>,
+    ) -> Result<Self, Error> {
+        Self::new_with_key(&ROOT_KEY, duration, profiles, max_uses)
+    }
+
+    /// Same as [`RefreshToken::new`], but signed with `root_key`
+    /// instead of the key read from the environment.
+    pub fn new_with_key(
+        root_key: &biscuit::KeyPair,
+        duration: std::time::Duration,
+        profiles: impl Iterator<Item = String>,
+        max_uses: Option<u32>,
     ) -> Result<Self, Error> {
         let mut builder = Biscuit::builder();
 
@@ -43,17 +65,41 @@ impl RefreshToken {
                 .map_err(|e| Error::CannotAddFact(fact, e))?;
         }
 
+        // Add usage limits to Biscuit
+        if let Some(max_uses) = max_uses {
+            let nonce = hex::encode(rand::random::<[u8; 16]>());
+            let max_uses = i64::from(max_uses);
+            for fact in [
+                fact!("nonce({nonce});"),
+                fact!("max_uses({max_uses});"),
+            ] {
+                builder
+                    .add_fact(fact.to_owned())
+                    .map_err(|e| Error::CannotAddFact(fact, e))?;
+            }
+        }
+
         // Create first Biscuit block
-        let mut biscuit = builder
-            .build(&ROOT_KEY)
-            .map_err(Error::CannotBuildBiscuit)?;
+        let biscuit = builder.build(root_key).map_err(Error::CannotBuildBiscuit)?;
+
+        Self::attenuate(&biscuit, duration)
+    }
 
+    /// Try to create a short-lived `RefreshToken` from an existing token.
+    ///
+    /// Since it only appends a block to `biscuit`, the resulting refresh token
+    /// cannot give more rights than `biscuit` itself.
+    pub fn attenuate(
+        biscuit: &Biscuit,
+        duration: std::time::Duration,
+    ) -> Result<Self, Error> {
         // Add expiry block to Biscuit
-        let expiry_block = block!(
+        let mut expiry_block = block!(
             "check if time($time), $time <= {expiry};",
             expiry = SystemTime::now() + duration,
         );
-        biscuit = biscuit
+        expiry_block.set_context(EXPIRY_BLOCK_CONTEXT.to_owned());
+        let biscuit = biscuit
             .append(expiry_block.to_owned())
             .map_err(|e| Error::CannotAddBlock(expiry_block, e))?;
 
@@ -64,12 +110,28 @@ impl RefreshToken {
     pub fn try_from(
         duration: String,
         profiles: impl Iterator<Item = String>,
+        max_uses: Option<u32>,
+    ) -> Result<Self, Error> {
+        Self::try_from_with_key(&ROOT_KEY, duration, profiles, max_uses)
+    }
+
+    /// Same as [`RefreshToken::try_from`], but signed with `root_key`
+    /// instead of the key read from the environment.
+    pub fn try_from_with_key(
+        root_key: &biscuit::KeyPair,
+        duration: String,
+        profiles: impl Iterator<Item = String>,
+        max_uses: Option<u32>,
     ) -> Result<Self, Error> {
         let duration = IsoDuration::parse(&duration)
             .map_err(|e| Error::MalformattedDuration(duration.clone(), e))?
             .to_std()
             .ok_or(Error::UnsupportedDuration(duration.clone()))?;
-        Self::new(duration, profiles)
+        Self::new_with_key(root_key, duration, profiles, max_uses)
+    }
+
+    pub fn revocation_identifiers(&self) -> Vec<Vec<u8>> {
+        self.0.revocation_identifiers()
     }
 
     pub fn as_base64(&self) -> Result<String, Error> {
@@ -85,7 +147,7 @@ impl RefreshToken {
     }
 }
 
-fn remove_padding<'a>(base64_string: &'a str) -> &'a str {
+fn remove_padding(base64_string: &str) -> &str {
     // Find the position of the first '=' character
     if let Some(index) = base64_string.find('=') {
         // Remove all characters from the first '=' character to the end
@@ -20,13 +20,17 @@ fn main() {
 fn main_() -> Result<(), Error> {
     // Parse arguments
     let duration = env::args()
-        .skip(1)
-        .next()
+        .nth(1)
         .expect("Missing first argument (duration)");
     let profiles = env::args().skip(2);
+    let max_uses = env::var("MAX_USES").ok().map(|max_uses| {
+        max_uses
+            .parse::<u32>()
+            .expect("`MAX_USES` should be a positive integer")
+    });
 
     // Create token
-    let refresh_token = RefreshToken::try_from(duration, profiles)?;
+    let refresh_token = RefreshToken::try_from(duration, profiles, max_uses)?;
     let token_base64 = refresh_token.as_base64()?;
 
     // Print token to `stdout`
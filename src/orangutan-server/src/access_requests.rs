@@ -0,0 +1,143 @@
+//! File-backed storage of access requests made by users who
+//! tried to open a page they are not allowed to see.
+
+use std::{fs::File, io, path::Path};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{info, trace};
+
+use crate::util::write_atomically;
+
+/// Maximum length of the message attached to a request, in characters.
+pub const MAX_MESSAGE_LENGTH: usize = 1000;
+/// Maximum number of pending requests, to avoid filling the disk.
+pub const MAX_PENDING_REQUESTS: usize = 100;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct AccessRequests(Vec<AccessRequest>);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccessRequest {
+    pub id: String,
+    pub timestamp: DateTime<Utc>,
+    /// Name given by the user.
+    pub name: String,
+    /// Profiles of the user when they made the request.
+    pub profiles: Vec<String>,
+    pub path: String,
+    pub message: String,
+    #[serde(default)]
+    pub status: AccessRequestStatus,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessRequestStatus {
+    #[default]
+    Pending,
+    Approved,
+    Rejected,
+}
+
+impl AccessRequest {
+    pub fn new(
+        name: String,
+        profiles: Vec<String>,
+        path: String,
+        message: String,
+    ) -> Self {
+        Self {
+            id: hex::encode(rand::random::<[u8; 8]>()),
+            timestamp: Utc::now(),
+            name,
+            profiles,
+            path,
+            message,
+            status: AccessRequestStatus::Pending,
+        }
+    }
+}
+
+impl AccessRequests {
+    pub fn read(file_path: &Path) -> Result<Self, Error> {
+        let Ok(file) = File::open(file_path) else {
+            info!(
+                "Access requests file not found at <{}>. Considering no request.",
+                file_path.display(),
+            );
+            return Ok(Self::default());
+        };
+        let requests: Self = serde_json::from_reader(file)?;
+        info!("Found {} access request(s).", requests.0.len());
+        Ok(requests)
+    }
+
+    pub fn save(
+        &self,
+        file_path: &Path,
+    ) -> Result<(), Error> {
+        trace!("Saving access requests to <{}>…", file_path.display());
+        write_atomically(file_path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn all(&self) -> &Vec<AccessRequest> {
+        &self.0
+    }
+
+    pub fn pending(&self) -> impl Iterator<Item = &AccessRequest> {
+        (self.0.iter()).filter(|request| request.status == AccessRequestStatus::Pending)
+    }
+
+    /// Returns `false` if there are too many pending requests already.
+    pub fn add(
+        &mut self,
+        request: AccessRequest,
+    ) -> bool {
+        if self.pending().count() >= MAX_PENDING_REQUESTS {
+            return false;
+        }
+        self.0.push(request);
+        true
+    }
+
+    pub fn get_mut(
+        &mut self,
+        id: &str,
+    ) -> Option<&mut AccessRequest> {
+        self.0.iter_mut().find(|request| request.id == id)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("IO error: {0}")]
+    IOError(#[from] io::Error),
+    #[error("JSON error: {0}")]
+    JsonError(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccessRequest, AccessRequestStatus, AccessRequests, MAX_PENDING_REQUESTS};
+
+    fn request() -> AccessRequest {
+        AccessRequest::new("Bob".to_owned(), vec![], "/a/".to_owned(), String::new())
+    }
+
+    #[test]
+    fn test_pending_requests_limit() {
+        let mut requests = AccessRequests::default();
+        for _ in 0..MAX_PENDING_REQUESTS {
+            assert!(requests.add(request()));
+        }
+        assert!(!requests.add(request()));
+
+        // Handling a request makes room for a new one.
+        let id = requests.all()[0].id.to_owned();
+        requests.get_mut(&id).unwrap().status = AccessRequestStatus::Rejected;
+        assert!(requests.add(request()));
+    }
+}
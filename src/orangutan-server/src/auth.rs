@@ -1,47 +1,283 @@
 use std::time::SystemTime;
 
-use biscuit_auth::macros::authorizer;
-use orangutan_helpers::{config::DEFAULT_PROFILE, ReadAllowed};
+use axum::http::{HeaderMap, Method};
+use biscuit_auth::{
+    macros::{authorizer, fact, policy},
+    Authorizer, Biscuit,
+};
+use orangutan_helpers::{config::DEFAULT_PROFILE, website_id::WebsiteId, PageMetadata};
 use tracing::trace;
 
-use crate::request_guards::Token;
+use crate::{request_guards::Token, util::error};
 
+/// Information about the request, provided as Datalog facts to authorizers
+/// so page policies and token checks can use them.
+#[derive(Debug, Clone)]
+pub struct RequestFacts {
+    pub method: Method,
+    pub website_id: String,
+    /// `Sec-CH-*` headers (lowercased name, unquoted value).
+    pub client_hints: Vec<(String, String)>,
+}
+
+impl RequestFacts {
+    pub fn new(
+        method: Method,
+        headers: &HeaderMap,
+        website_id: &WebsiteId,
+    ) -> Self {
+        let client_hints = headers
+            .iter()
+            .filter(|(name, _)| name.as_str().starts_with("sec-ch-"))
+            .filter_map(|(name, value)| {
+                let value = value.to_str().ok()?.trim().trim_matches('"');
+                Some((name.as_str().to_owned(), value.to_owned()))
+            })
+            .collect();
+
+        Self {
+            method,
+            website_id: website_id.name(),
+            client_hints,
+        }
+    }
+}
+
+/// Checks if `token` can read `page`.
+///
+/// The authorizer provides the following facts:
+///
+/// - `operation("read")`
+/// - `time($now)`
+/// - `path($path)` (so tokens attenuated with `check if path($p), $p == "/some/page/"`,
+///   e.g. share links, only work for that page)
+/// - `website_id($id)` (e.g. `"amis,famille"`)
+/// - `method($method)` (e.g. `"GET"`)
+/// - `client_hint($name, $value)` (e.g. `"sec-ch-ua-mobile", "?1"`)
+///
+/// If the page defines a `policy`, it is evaluated before the default `allow` policy,
+/// so it can contain `check if` or `deny if` rules (e.g. for an embargo).
 pub fn is_authorized(
     token: Option<Token>,
-    allowed_profiles: ReadAllowed,
+    page: &PageMetadata,
+    request: &RequestFacts,
+) -> bool {
+    is_allowed(token, "read", &page.read_allowed, page, request)
+}
+
+/// Checks if `token` can comment on `page` (which requires being able to read it).
+///
+/// Same as [`is_authorized`], but with `operation("comment")`.
+#[cfg(feature = "comments")]
+pub fn can_comment(
+    token: Option<Token>,
+    page: &PageMetadata,
+    request: &RequestFacts,
+) -> bool {
+    is_authorized(token.clone(), page, request)
+        && is_allowed(token, "comment", &page.comment_allowed, page, request)
+}
+
+fn is_allowed(
+    token: Option<Token>,
+    operation: &str,
+    allowed_profiles: &[String],
+    page: &PageMetadata,
+    request: &RequestFacts,
 ) -> bool {
     let mut profile: Option<String> = None;
-    let biscuit = token.map(|t| t.biscuit);
-    for allowed_profile in allowed_profiles {
+    // NOTE: Tokens are authorized separately, so the checks of one token
+    //   (e.g. a share link restricted to a page) never apply to another.
+    let biscuits: Vec<Biscuit> = (token.iter()).flat_map(|t| t.biscuits().cloned()).collect();
+    for allowed_profile in allowed_profiles.iter() {
         trace!("Checking if profile '{allowed_profile}' exists in token…");
+        let mut authorizer = match base_authorizer(operation, allowed_profile, page, request) {
+            Ok(authorizer) => authorizer,
+            Err(err) => {
+                error(format!(
+                    "Could not create authorizer for <{}>: {err}",
+                    page.path.display(),
+                ));
+                return false;
+            },
+        };
         if allowed_profile == DEFAULT_PROFILE {
-            profile = Some(allowed_profile);
-        } else if let Some(ref biscuit) = biscuit {
-            let authorizer = authorizer!(
+            // NOTE: Public pages don't need a token, but must still respect the page policy.
+            let allow = policy!("allow if true;");
+            if authorizer.add_policy(allow).is_ok() && authorizer.authorize().is_ok() {
+                profile = Some(allowed_profile.to_owned());
+            }
+        } else if !biscuits.is_empty() {
+            let allow = policy!(
                 r#"
-                operation("read");
-                time({now});
-                right({p}, "read");
-                right("*", "read");
-
                 allow if
                 operation($op),
                 profile($p),
                 right($p, $op);
-                "#,
-                p = allowed_profile.clone(),
-                now = SystemTime::now()
+                "#
             );
             // trace!(
             //     "Running authorizer '{}' on '{}'…",
             //     authorizer.dump_code(),
             //     biscuit.authorizer().unwrap().dump_code()
             // );
-            if biscuit.authorize(&authorizer).is_ok() {
-                profile = Some(allowed_profile);
+            if authorizer.add_policy(allow).is_ok()
+                && biscuits.iter().any(|b| b.authorize(&authorizer).is_ok())
+            {
+                profile = Some(allowed_profile.to_owned());
             }
         }
     }
 
     profile.is_some()
 }
+
+fn base_authorizer(
+    operation: &str,
+    allowed_profile: &str,
+    page: &PageMetadata,
+    request: &RequestFacts,
+) -> Result<Authorizer, biscuit_auth::error::Token> {
+    let mut authorizer = authorizer!(
+        r#"
+        operation({op});
+        time({now});
+        path({path});
+        website_id({website_id});
+        method({method});
+        right({p}, {op});
+        right("*", {op});
+        "#,
+        op = operation.to_owned(),
+        p = allowed_profile.to_owned(),
+        now = SystemTime::now(),
+        path = page.path.display().to_string(),
+        website_id = request.website_id.clone(),
+        method = request.method.to_string(),
+    );
+
+    for (name, value) in request.client_hints.iter() {
+        authorizer.add_fact(fact!(
+            "client_hint({name}, {value});",
+            name = name.to_owned(),
+            value = value.to_owned(),
+        ))?;
+    }
+
+    if let Some(ref policy) = page.policy {
+        authorizer.add_code(policy)?;
+    }
+
+    Ok(authorizer)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderMap, Method};
+    use biscuit_auth::{
+        macros::{biscuit, block},
+        KeyPair,
+    };
+    use orangutan_helpers::{website_id::WebsiteId, PageMetadata};
+
+    use super::{is_authorized, RequestFacts};
+    use crate::request_guards::Token;
+
+    fn page(json: &str) -> PageMetadata {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn request() -> RequestFacts {
+        RequestFacts::new(Method::GET, &HeaderMap::new(), &WebsiteId::default())
+    }
+
+    #[test]
+    fn test_path_restricted_token() {
+        let biscuit = biscuit!(r#"profile("amis");"#)
+            .build(&KeyPair::new())
+            .unwrap()
+            .append(block!(r#"check if path($path), $path == "/shared/";"#))
+            .unwrap();
+        let token = Token::from(biscuit);
+
+        assert!(is_authorized(
+            Some(token.clone()),
+            &page(r#"{ "read_allowed": ["amis"], "path": "/shared/" }"#),
+            &request(),
+        ));
+        assert!(!is_authorized(
+            Some(token),
+            &page(r#"{ "read_allowed": ["amis"], "path": "/other/" }"#),
+            &request(),
+        ));
+    }
+
+    #[test]
+    fn test_merged_tokens_keep_their_checks() {
+        let root = KeyPair::new();
+        let shared = biscuit!(r#"profile("famille");"#)
+            .build(&root)
+            .unwrap()
+            .append(block!(r#"check if path($path), $path == "/shared/";"#))
+            .unwrap();
+        let own = biscuit!(r#"profile("amis");"#).build(&root).unwrap();
+        let mut token = Token::from(own);
+        token.others.push(shared);
+
+        assert!(is_authorized(
+            Some(token.clone()),
+            &page(r#"{ "read_allowed": ["famille"], "path": "/shared/" }"#),
+            &request(),
+        ));
+        assert!(is_authorized(
+            Some(token.clone()),
+            &page(r#"{ "read_allowed": ["amis"], "path": "/other/" }"#),
+            &request(),
+        ));
+        // The share link doesn't give access to other pages.
+        assert!(!is_authorized(
+            Some(token),
+            &page(r#"{ "read_allowed": ["famille"], "path": "/other/" }"#),
+            &request(),
+        ));
+    }
+
+    #[test]
+    fn test_page_policy() {
+        let embargoed = page(
+            r#"{
+                "read_allowed": ["_default"],
+                "path": "/embargoed/",
+                "policy": "check if time($time), $time >= 2999-01-01T00:00:00Z;"
+            }"#,
+        );
+        assert!(!is_authorized(None, &embargoed, &request()));
+
+        let published = page(
+            r#"{
+                "read_allowed": ["_default"],
+                "path": "/published/",
+                "policy": "check if time($time), $time >= 2000-01-01T00:00:00Z;"
+            }"#,
+        );
+        assert!(is_authorized(None, &published, &request()));
+    }
+
+    #[cfg(feature = "comments")]
+    #[test]
+    fn test_comment_right() {
+        use super::can_comment;
+
+        let biscuit = biscuit!(r#"profile("amis");"#)
+            .build(&KeyPair::new())
+            .unwrap();
+        let token = Token::from(biscuit);
+
+        let open =
+            page(r#"{ "read_allowed": ["amis"], "comment_allowed": ["amis"], "path": "/a/" }"#);
+        assert!(can_comment(Some(token.clone()), &open, &request()));
+
+        let closed = page(r#"{ "read_allowed": ["amis"], "path": "/b/" }"#);
+        assert!(!can_comment(Some(token), &closed, &request()));
+    }
+}
@@ -0,0 +1,236 @@
+//! HTTP Basic authentication, for scripts and old devices.
+//!
+//! Credentials are read from each site's repository
+//! (see [`Workspace::read_basic_auth_credentials`]) and mapped to profiles.
+//! Successful logins produce an in-memory Biscuit with those profiles,
+//! which then goes through the usual authorization path.
+//!
+//! [`Workspace::read_basic_auth_credentials`]: orangutan_helpers::workspace::Workspace::read_basic_auth_credentials
+
+use std::{
+    collections::HashMap,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use argon2::{password_hash, Argon2, PasswordHash, PasswordVerifier as _};
+use base64::{prelude::BASE64_STANDARD, Engine as _};
+use biscuit_auth::Biscuit;
+use lazy_static::lazy_static;
+use orangutan_helpers::auth_config::BasicAuthCredentials;
+use sha2::{Digest as _, Sha256};
+use tokio::sync::Semaphore;
+use tracing::{debug, trace};
+
+use crate::util::{error, in_memory_biscuit};
+
+/// `WWW-Authenticate` header value sent on protected pages.
+pub const CHALLENGE: &str =
+    r#"Basic realm="This page is protected. Please log in.", charset="UTF-8""#;
+
+/// How long failed credentials are rejected without being verified again.
+const FAILURE_TTL: Duration = Duration::from_secs(60);
+/// Maximum number of remembered failures, so they can't fill the memory.
+const MAX_FAILURES: usize = 1000;
+
+lazy_static! {
+    /// Random salt of cache keys, so credentials can't be recovered from memory.
+    static ref CACHE_KEY_SALT: [u8; 32] = rand::random();
+}
+
+/// Limits concurrent password verifications, as Argon2 is slow on purpose
+/// and blocking threads are shared with other tasks (e.g. serving files).
+static VERIFICATIONS: Semaphore = Semaphore::const_new(2);
+
+/// SHA-256 of an `Authorization` header value.
+type CacheKey = [u8; 32];
+
+/// Basic authentication credentials of a site.
+#[derive(Debug, Default)]
+pub struct BasicAuth {
+    /// Credentials, by username.
+    credentials: RwLock<HashMap<String, BasicAuthCredentials>>,
+    /// Profiles of already verified `Authorization` header values.
+    ///
+    /// NOTE: Argon2 is slow on purpose, we don't want to hash passwords
+    ///   on every request (a page often loads tens of files).
+    verified: RwLock<HashMap<CacheKey, Vec<String>>>,
+    /// When `Authorization` header values failed verification,
+    /// so clients resending bad credentials don't keep us hashing.
+    failed: RwLock<HashMap<CacheKey, Instant>>,
+}
+
+fn cache_key(credentials: &str) -> CacheKey {
+    (Sha256::new())
+        .chain_update(*CACHE_KEY_SALT)
+        .chain_update(credentials)
+        .finalize()
+        .into()
+}
+
+impl BasicAuth {
+    /// Replaces known credentials (e.g. after the website repository was updated).
+    pub fn set_credentials(
+        &self,
+        credentials: Vec<BasicAuthCredentials>,
+    ) {
+        *self.credentials.write().unwrap() = (credentials.into_iter())
+            .map(|credentials| (credentials.username.to_owned(), credentials))
+            .collect();
+        self.verified.write().unwrap().clear();
+        self.failed.write().unwrap().clear();
+    }
+
+    /// Basic authentication is enabled if at least one credential exists.
+    pub fn is_enabled(&self) -> bool {
+        !self.credentials.read().unwrap().is_empty()
+    }
+
+    /// Returns a Biscuit with the user's profiles if `credentials`
+    /// (Base64-encoded `username:password`) are valid.
+    pub async fn authenticate(
+        &self,
+        credentials: &str,
+    ) -> Option<Biscuit> {
+        let credentials = credentials.trim();
+        let key = cache_key(credentials);
+        let verified = self.verified.read().unwrap().get(&key).cloned();
+        let profiles = match verified {
+            Some(profiles) => {
+                trace!("Basic authentication credentials already verified");
+                profiles
+            },
+            None if self.failed_recently(&key) => {
+                debug!("Basic authentication credentials failed recently, not verifying them");
+                return None;
+            },
+            None => match self.verify(credentials).await {
+                Some(profiles) => {
+                    (self.verified.write().unwrap()).insert(key, profiles.clone());
+                    profiles
+                },
+                None => {
+                    self.record_failure(key);
+                    return None;
+                },
+            },
+        };
+
+        in_memory_biscuit(profiles)
+            .inspect_err(|err| {
+                error(format!(
+                    "Could not create Basic authentication token: {err}"
+                ))
+            })
+            .ok()
+    }
+
+    fn failed_recently(
+        &self,
+        key: &CacheKey,
+    ) -> bool {
+        (self.failed.read().unwrap().get(key)).is_some_and(|at| at.elapsed() < FAILURE_TTL)
+    }
+
+    fn record_failure(
+        &self,
+        key: CacheKey,
+    ) {
+        let mut failed = self.failed.write().unwrap();
+        if failed.len() >= MAX_FAILURES {
+            failed.retain(|_, at| at.elapsed() < FAILURE_TTL);
+        }
+        // NOTE: Concurrent verifications are limited anyway.
+        if failed.len() < MAX_FAILURES {
+            failed.insert(key, Instant::now());
+        }
+    }
+
+    async fn verify(
+        &self,
+        credentials: &str,
+    ) -> Option<Vec<String>> {
+        let decoded = BASE64_STANDARD
+            .decode(credentials)
+            .inspect_err(|err| debug!("Invalid Basic authentication credentials: {err}"))
+            .ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+        let (username, password) = decoded.split_once(':')?;
+
+        let Some(known) = self.credentials.read().unwrap().get(username).cloned() else {
+            debug!("Unknown Basic authentication user '{username}'");
+            return None;
+        };
+        let password = password.to_owned();
+        let profiles = known.profiles.clone();
+
+        let _permit = VERIFICATIONS.acquire().await.ok()?;
+        // NOTE: Hashing takes tens of milliseconds, it must not block the runtime.
+        // NOTE: Errors are reported outside of the blocking task,
+        //   as it doesn't know which site is being served.
+        let verification =
+            tokio::task::spawn_blocking(move || -> Result<_, password_hash::Error> {
+                let password_hash = PasswordHash::new(&known.password_hash)?;
+                Ok(Argon2::default().verify_password(password.as_bytes(), &password_hash))
+            })
+            .await
+            .inspect_err(|err| error(format!("Could not verify Basic authentication: {err}")))
+            .ok()?
+            .inspect_err(|err| error(format!("Invalid password hash for '{username}': {err}")))
+            .ok()?;
+
+        match verification {
+            Ok(()) => {
+                debug!("Basic authentication succeeded for '{username}'");
+                Some(profiles)
+            },
+            Err(err) => {
+                debug!("Basic authentication failed for '{username}': {err}");
+                None
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use argon2::{
+        password_hash::{rand_core::OsRng, SaltString},
+        Argon2, PasswordHasher as _,
+    };
+    use base64::{prelude::BASE64_STANDARD, Engine as _};
+    use orangutan_helpers::auth_config::BasicAuthCredentials;
+
+    use super::{cache_key, BasicAuth};
+
+    #[tokio::test]
+    async fn test_authenticate() {
+        let salt = SaltString::generate(&mut OsRng);
+        let password_hash = Argon2::default()
+            .hash_password(b"s3cr3t", &salt)
+            .unwrap()
+            .to_string();
+        let basic_auth = BasicAuth::default();
+        basic_auth.set_credentials(vec![BasicAuthCredentials {
+            username: "printer".to_owned(),
+            password_hash,
+            profiles: vec!["famille".to_owned()],
+        }]);
+
+        let valid = BASE64_STANDARD.encode("printer:s3cr3t");
+        assert_eq!(
+            basic_auth.verify(&valid).await,
+            Some(vec!["famille".to_owned()])
+        );
+        let invalid = BASE64_STANDARD.encode("printer:wrong");
+        assert_eq!(basic_auth.verify(&invalid).await, None);
+        let unknown = BASE64_STANDARD.encode("scanner:s3cr3t");
+        assert_eq!(basic_auth.verify(&unknown).await, None);
+
+        // Failures are remembered, and credentials are not stored as is.
+        assert!(basic_auth.authenticate(&invalid).await.is_none());
+        assert!(basic_auth.failed_recently(&cache_key(&invalid)));
+        assert!(!basic_auth.failed_recently(&cache_key(&valid)));
+        assert_ne!(cache_key(&valid).as_slice(), valid.as_bytes());
+    }
+}
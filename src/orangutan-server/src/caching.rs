@@ -0,0 +1,183 @@
+//! HTTP caching headers of website files, so shared caches (e.g. CDNs)
+//! never serve a page to someone who cannot read it.
+
+use axum::{
+    http::{
+        header::{CACHE_CONTROL, ETAG, IF_NONE_MATCH, LAST_MODIFIED, VARY},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse as _, Response},
+};
+use orangutan_helpers::{config::DEFAULT_PROFILE, website_id::WebsiteId, PageMetadata};
+use sha2::{Digest as _, Sha256};
+
+/// Files depend on the token, which can be sent in a cookie or using Basic authentication.
+const VARY_VALUE: &str = "Cookie, Authorization";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachePolicy {
+    /// Public files whose name contains a hash of their content
+    /// (e.g. `style.<sha256>.css`), which never change.
+    Immutable,
+    /// Public files served from the default website.
+    Public,
+    /// Public files served from a website generated for the user's profiles
+    /// (e.g. with links to private pages).
+    Personalized,
+    /// Files not everyone can read.
+    Private,
+}
+
+impl CachePolicy {
+    /// `page` is `None` for static files anyone can read.
+    pub fn new(
+        page: Option<&PageMetadata>,
+        website_id: &WebsiteId,
+        path: &str,
+    ) -> Self {
+        let is_public = page.is_none_or(|page| {
+            // NOTE: Policies can depend on the request (e.g. the time).
+            page.read_allowed.iter().any(|p| p == DEFAULT_PROFILE) && page.policy.is_none()
+        });
+        if !is_public {
+            Self::Private
+        } else if is_fingerprinted(path) {
+            Self::Immutable
+        } else if website_id.name() == DEFAULT_PROFILE {
+            Self::Public
+        } else {
+            Self::Personalized
+        }
+    }
+
+    /// NOTE: Only files which exist are immutable, error pages (e.g. a 404
+    ///   for a file which will be added later) can change.
+    fn cache_control(
+        self,
+        status: StatusCode,
+    ) -> &'static str {
+        let is_success = matches!(status, StatusCode::OK | StatusCode::NOT_MODIFIED);
+        match self {
+            Self::Immutable if !is_success => Self::Personalized.cache_control(status),
+            Self::Immutable => "public, max-age=31536000, immutable",
+            Self::Public => "public, no-cache",
+            Self::Personalized => "private, no-cache",
+            Self::Private => "private, no-store",
+        }
+    }
+}
+
+/// Whether or not the file name contains a hash (e.g. from Hugo's `fingerprint`).
+fn is_fingerprinted(path: &str) -> bool {
+    let file_name = path.rsplit('/').next().unwrap_or_default();
+    let parts: Vec<&str> = file_name.split('.').collect();
+    // NOTE: The hash is neither the name nor the extension.
+    (parts.len() > 2)
+        && parts[1..parts.len() - 1]
+            .iter()
+            .any(|part| part.len() >= 32 && part.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Entity tag of every file of a generated website.
+///
+/// NOTE: Websites are regenerated when content changes, so the revision
+///   of the repository is enough to identify a file's content.
+pub fn etag(
+    revision: &str,
+    website_id: &WebsiteId,
+) -> HeaderValue {
+    // NOTE: `ETag`s must not change when Orangutan is rebuilt, hence SHA-256.
+    let hash = (Sha256::new())
+        .chain_update(revision)
+        .chain_update([0])
+        .chain_update(website_id.name())
+        .finalize();
+    HeaderValue::from_str(&format!("\"{}\"", hex::encode(&hash[..16]))).unwrap()
+}
+
+/// Whether or not the request's `If-None-Match` contains `etag`.
+///
+/// NOTE: `*` is not supported, it would answer `304 Not Modified`
+///   without comparing anything.
+pub fn is_not_modified(
+    headers: &HeaderMap,
+    etag: &HeaderValue,
+) -> bool {
+    let Ok(etag) = etag.to_str() else {
+        return false;
+    };
+    (headers.get_all(IF_NONE_MATCH).iter())
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        // NOTE: `If-None-Match` uses the weak comparison.
+        .any(|tag| tag.strip_prefix("W/").unwrap_or(tag) == etag)
+}
+
+pub fn not_modified(
+    policy: CachePolicy,
+    etag: HeaderValue,
+) -> Response {
+    let mut response = StatusCode::NOT_MODIFIED.into_response();
+    set_headers(&mut response, policy, Some(etag));
+    response
+}
+
+/// NOTE: The `ETag` is only sent with successful responses,
+///   as error pages (e.g. 404) don't change with the revision.
+pub fn set_headers(
+    response: &mut Response,
+    policy: CachePolicy,
+    etag: Option<HeaderValue>,
+) {
+    let status = response.status();
+    let headers = response.headers_mut();
+    headers.insert(
+        CACHE_CONTROL,
+        HeaderValue::from_static(policy.cache_control(status)),
+    );
+    headers.insert(VARY, HeaderValue::from_static(VARY_VALUE));
+
+    let Some(etag) = etag else { return };
+    if !matches!(status, StatusCode::OK | StatusCode::NOT_MODIFIED) {
+        return;
+    }
+    let headers = response.headers_mut();
+    // NOTE: Modification dates differ between generated websites, and clients
+    //   would send `If-Modified-Since` which `ServeDir` handles on its own.
+    headers.remove(LAST_MODIFIED);
+    headers.insert(ETAG, etag);
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header::IF_NONE_MATCH, HeaderMap, HeaderValue};
+
+    use super::{is_fingerprinted, is_not_modified};
+
+    #[test]
+    fn test_is_fingerprinted() {
+        let sha256 = "5ad0e4ba8e2a6b4b4e3c0c4e3e6b25f7ef0d3c4b1b8f4a0ac0b6b3e2c1d0e9f8";
+        assert!(is_fingerprinted(&format!("/css/style.min.{sha256}.css")));
+        assert!(is_fingerprinted(&format!("/js/main.{sha256}.js")));
+        assert!(!is_fingerprinted("/css/style.min.css"));
+        assert!(!is_fingerprinted(&format!("/{sha256}.css")));
+        assert!(!is_fingerprinted(&format!("/{sha256}/style.css")));
+        assert!(!is_fingerprinted("/blog/"));
+    }
+
+    #[test]
+    fn test_is_not_modified() {
+        let etag = HeaderValue::from_static("\"abc\"");
+        let headers = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(IF_NONE_MATCH, HeaderValue::from_static(value));
+            headers
+        };
+        assert!(is_not_modified(&headers("\"abc\""), &etag));
+        assert!(is_not_modified(&headers("\"xyz\", W/\"abc\""), &etag));
+        assert!(!is_not_modified(&headers("*"), &etag));
+        assert!(!is_not_modified(&headers("\"xyz\""), &etag));
+        assert!(!is_not_modified(&HeaderMap::new(), &etag));
+    }
+}
@@ -0,0 +1,168 @@
+//! File-backed storage of comments and reactions.
+//!
+//! Each site stores comments in its own directory (see [`COMMENTS_DIR`]),
+//! with one JSON file per page named after the URL-encoded page path.
+//!
+//! [`COMMENTS_DIR`]: crate::config::COMMENTS_DIR
+
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::trace;
+
+use crate::util::write_atomically;
+
+/// Maximum length of a comment, in characters.
+pub const MAX_COMMENT_LENGTH: usize = 5000;
+/// Maximum length of a reaction, in characters (some emojis use multiple).
+pub const MAX_REACTION_LENGTH: usize = 16;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Comment {
+    pub id: String,
+    pub timestamp: DateTime<Utc>,
+    /// Profiles of the author.
+    pub author: Vec<String>,
+    #[serde(flatten)]
+    pub content: CommentContent,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CommentContent {
+    Comment { text: String },
+    Reaction { emoji: String },
+}
+
+impl Comment {
+    pub fn new(
+        author: Vec<String>,
+        content: CommentContent,
+    ) -> Self {
+        Self {
+            id: hex::encode(rand::random::<[u8; 8]>()),
+            timestamp: Utc::now(),
+            author,
+            content,
+        }
+    }
+}
+
+/// Comments of a site.
+#[derive(Debug)]
+pub struct Comments {
+    dir: PathBuf,
+    /// Prevents concurrent writes from overwriting each other.
+    write_lock: Mutex<()>,
+}
+
+fn read_file(file_path: &Path) -> Result<Vec<Comment>, Error> {
+    let Ok(file) = File::open(file_path) else {
+        return Ok(Vec::new());
+    };
+    Ok(serde_json::from_reader(file)?)
+}
+
+impl Comments {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            write_lock: Mutex::default(),
+        }
+    }
+
+    fn page_file(
+        &self,
+        page_path: &Path,
+    ) -> PathBuf {
+        let file_name = urlencoding::encode(&page_path.display().to_string()).into_owned();
+        self.dir.join(format!("{file_name}.json"))
+    }
+
+    fn write_file(
+        &self,
+        file_path: &Path,
+        comments: &Vec<Comment>,
+    ) -> Result<(), Error> {
+        trace!("Saving comments to <{}>…", file_path.display());
+        fs::create_dir_all(&self.dir)?;
+        write_atomically(file_path, serde_json::to_vec(comments)?)?;
+        Ok(())
+    }
+
+    pub fn read(
+        &self,
+        page_path: &Path,
+    ) -> Result<Vec<Comment>, Error> {
+        read_file(&self.page_file(page_path))
+    }
+
+    pub fn add(
+        &self,
+        page_path: &Path,
+        comment: Comment,
+    ) -> Result<(), Error> {
+        let _lock = self.write_lock.lock().unwrap();
+        let file_path = self.page_file(page_path);
+        let mut comments = read_file(&file_path)?;
+        comments.push(comment);
+        self.write_file(&file_path, &comments)
+    }
+
+    /// Returns `false` if the comment did not exist.
+    pub fn delete(
+        &self,
+        page_path: &Path,
+        id: &str,
+    ) -> Result<bool, Error> {
+        let _lock = self.write_lock.lock().unwrap();
+        let file_path = self.page_file(page_path);
+        let mut comments = read_file(&file_path)?;
+        let count = comments.len();
+        comments.retain(|comment| comment.id != id);
+        if comments.len() == count {
+            return Ok(false);
+        }
+        self.write_file(&file_path, &comments)?;
+        Ok(true)
+    }
+
+    /// Returns all comments, grouped by page path.
+    pub fn all(&self) -> Result<Vec<(PathBuf, Vec<Comment>)>, Error> {
+        if !self.dir.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut res = Vec::new();
+        for entry in fs::read_dir(&self.dir)?.flatten() {
+            let file_path = entry.path();
+            let Some(file_stem) = file_path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            let Ok(page_path) = urlencoding::decode(file_stem) else {
+                continue;
+            };
+            let comments = read_file(&file_path)?;
+            if !comments.is_empty() {
+                res.push((PathBuf::from(page_path.into_owned()), comments));
+            }
+        }
+        res.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        Ok(res)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("IO error: {0}")]
+    IOError(#[from] io::Error),
+    #[error("JSON error: {0}")]
+    JsonError(#[from] serde_json::Error),
+}
@@ -1,16 +1,40 @@
 use std::process::exit;
 
 use lazy_static::lazy_static;
-use orangutan_helpers::readers::keys_reader::KeysReader;
 use tracing::error;
 
-pub(super) const TOKEN_COOKIE_NAME: &'static str = "token";
-pub(super) const TOKEN_QUERY_PARAM_NAME: &'static str = "token";
-pub(super) const REFRESH_TOKEN_QUERY_PARAM_NAME: &'static str = "refresh_token";
-pub(super) const NOT_FOUND_FILE: &'static str = "404.html";
+use crate::{request_guards::TokenMergePolicy, util::CookiePolicy};
 
+pub(super) const TOKEN_QUERY_PARAM_NAME: &str = "token";
+pub(super) const REFRESH_TOKEN_QUERY_PARAM_NAME: &str = "refresh_token";
+pub(super) const NOT_FOUND_FILE: &str = "404.html";
+/// Page shown to authenticated users who cannot see a page, if the website defines one.
+pub(super) const FORBIDDEN_FILE: &str = "403.html";
+#[cfg(feature = "impersonation")]
+pub(super) const IMPERSONATION_COOKIE_NAME: &str = "impersonate";
+/// Remembers which OpenID Connect login the browser started.
+#[cfg(feature = "oidc")]
+pub(super) const OIDC_STATE_COOKIE_NAME: &str = "oidc_state";
+/// Data files, stored in each site's `base_dir` (see [`crate::sites::Site::data_file`]).
+pub(super) const REDEMPTIONS_FILE: &str = "redemptions.json";
+#[cfg(feature = "comments")]
+pub(super) const COMMENTS_DIR: &str = "comments";
+#[cfg(feature = "token-generator")]
+pub(super) const ISSUED_LINKS_FILE: &str = "issued-links.json";
+#[cfg(feature = "request-access")]
+pub(super) const ACCESS_REQUESTS_FILE: &str = "access-requests.json";
+
+#[cfg(test)]
+lazy_static! {
+    /// NOTE: Tests don't need a stable key, and must not depend on the environment.
+    pub(super) static ref ROOT_KEY: biscuit_auth::KeyPair = biscuit_auth::KeyPair::new();
+}
+
+#[cfg(not(test))]
 lazy_static! {
     pub(super) static ref ROOT_KEY: biscuit_auth::KeyPair = {
+        use orangutan_helpers::readers::keys_reader::KeysReader;
+
         let keys_reader = <dyn KeysReader>::detect();
         match keys_reader.get_root_biscuit_key() {
             Ok(public_key) => public_key,
@@ -21,3 +45,33 @@ lazy_static! {
         }
     };
 }
+
+lazy_static! {
+    pub(super) static ref TOKEN_COOKIE: CookiePolicy = match CookiePolicy::try_from_env() {
+        Ok(policy) => policy,
+        Err(err) => {
+            error!("Invalid token cookie configuration: {err}");
+            exit(1);
+        },
+    };
+    pub(super) static ref TOKEN_MERGE_POLICY: TokenMergePolicy =
+        match std::env::var("TOKEN_MERGE_POLICY") {
+            Ok(policy) => policy.parse().unwrap_or_else(|err| {
+                error!("{err}");
+                exit(1);
+            }),
+            Err(_) => TokenMergePolicy::default(),
+        };
+    /// If `true`, pages are disguised as "not found" even for authenticated users
+    /// who cannot see them (instead of explaining they don't have access).
+    pub(super) static ref DISGUISE_FORBIDDEN_PAGES: bool =
+        std::env::var("DISGUISE_FORBIDDEN_PAGES").is_ok_and(|value| value == "true");
+    /// If `true`, files which are not pages, not in a page bundle and not matched
+    /// by a rule in the website's `static_files.txt` are denied instead of served
+    /// to everyone. Sites can override it (see [`crate::sites`]).
+    ///
+    /// NOTE: The home page and the search index (`/index.json`) need a rule
+    ///   (e.g. `/:_default`) in strict mode, as they have no data file.
+    pub(super) static ref STRICT_STATIC_FILES: bool =
+        std::env::var("STRICT_STATIC_FILES").is_ok_and(|value| value == "true");
+}
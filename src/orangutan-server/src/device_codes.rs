@@ -0,0 +1,227 @@
+//! Short codes allowing users to log in their other devices
+//! without having to copy a link.
+//!
+//! Redeeming codes doesn't require authentication, so failed attempts
+//! are limited per client and for all clients (see [`MAX_FAILURES_PER_CLIENT`]
+//! and [`MAX_FAILURES`]), to prevent guessing codes.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    sync::{Mutex, RwLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use rand::{distributions::Uniform, Rng as _};
+use tracing::{debug, trace};
+
+/// How long a device link (or code) stays valid.
+pub const DEVICE_LINK_TTL: Duration = Duration::from_secs(5 * 60);
+pub const CODE_LENGTH: usize = 8;
+/// Characters used in codes, without those which are easily confused
+/// (`0`/`O`, `1`/`I`).
+const CODE_ALPHABET: &[u8] = b"23456789ABCDEFGHJKLMNPQRSTUVWXYZ";
+/// Maximum number of failed attempts of a client in [`FAILURE_WINDOW`].
+pub const MAX_FAILURES_PER_CLIENT: usize = 5;
+/// Maximum number of failed attempts of all clients in [`FAILURE_WINDOW`],
+/// so guessing codes from many addresses doesn't work either.
+pub const MAX_FAILURES: usize = 100;
+pub const FAILURE_WINDOW: Duration = Duration::from_secs(15 * 60);
+/// Maximum number of clients whose failures are remembered,
+/// so they can't fill the memory.
+const MAX_CLIENTS: usize = 1000;
+
+/// Device codes of a site.
+///
+/// NOTE: Codes are short-lived and single-use, there is no need to persist them.
+#[derive(Debug, Default)]
+pub struct DeviceCodes {
+    /// Refresh tokens waiting to be redeemed, by code.
+    codes: RwLock<HashMap<String, DeviceCode>>,
+    failures: Mutex<Failures>,
+}
+
+#[derive(Debug)]
+struct DeviceCode {
+    refresh_token: String,
+    expires_at: SystemTime,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Redemption {
+    /// The code was valid, here is its refresh token.
+    Redeemed(String),
+    Invalid,
+    /// The code was not checked, as too many attempts failed recently.
+    TooManyFailures,
+}
+
+/// Failed attempts to redeem a code.
+#[derive(Debug, Default)]
+struct Failures {
+    /// All recent failures, oldest first.
+    all: VecDeque<Instant>,
+    /// Failures by client (`None` if the client's address is unknown).
+    by_client: HashMap<Option<IpAddr>, Vec<Instant>>,
+}
+
+fn random_code() -> String {
+    (rand::thread_rng().sample_iter(Uniform::from(0..CODE_ALPHABET.len())))
+        .take(CODE_LENGTH)
+        .map(|i| CODE_ALPHABET[i] as char)
+        .collect()
+}
+
+impl DeviceCodes {
+    /// Stores `refresh_token` and returns a new code to redeem it.
+    pub fn add(
+        &self,
+        refresh_token: String,
+    ) -> String {
+        let mut codes = self.codes.write().unwrap();
+
+        // Remove expired codes
+        let now = SystemTime::now();
+        codes.retain(|_, code| code.expires_at > now);
+
+        let code = loop {
+            let code = random_code();
+            if !codes.contains_key(&code) {
+                break code;
+            }
+        };
+        trace!("Created device code");
+        codes.insert(code.clone(), DeviceCode {
+            refresh_token,
+            expires_at: now + DEVICE_LINK_TTL,
+        });
+
+        code
+    }
+
+    /// Redeems `code` (case-insensitive) for `client`.
+    pub fn redeem(
+        &self,
+        code: &str,
+        client: Option<IpAddr>,
+    ) -> Redemption {
+        let mut failures = self.failures.lock().unwrap();
+        if failures.too_many(client) {
+            debug!("Too many failed device code attempts, not checking code");
+            return Redemption::TooManyFailures;
+        }
+
+        // NOTE: Codes are single-use, remove it even if it has expired.
+        let device_code = (self.codes.write().unwrap()).remove(&code.trim().to_uppercase());
+        match device_code {
+            Some(DeviceCode {
+                refresh_token,
+                expires_at,
+            }) if expires_at > SystemTime::now() => Redemption::Redeemed(refresh_token),
+            _ => {
+                failures.record(client);
+                Redemption::Invalid
+            },
+        }
+    }
+}
+
+fn is_recent(at: &Instant) -> bool {
+    at.elapsed() < FAILURE_WINDOW
+}
+
+impl Failures {
+    fn too_many(
+        &mut self,
+        client: Option<IpAddr>,
+    ) -> bool {
+        while self.all.front().is_some_and(|at| !is_recent(at)) {
+            self.all.pop_front();
+        }
+        if self.all.len() >= MAX_FAILURES {
+            return true;
+        }
+        (self.by_client.get(&client)).is_some_and(|failures| {
+            failures.iter().filter(|at| is_recent(at)).count() >= MAX_FAILURES_PER_CLIENT
+        })
+    }
+
+    fn record(
+        &mut self,
+        client: Option<IpAddr>,
+    ) {
+        let now = Instant::now();
+        self.all.push_back(now);
+        if self.by_client.len() >= MAX_CLIENTS && !self.by_client.contains_key(&client) {
+            self.by_client.retain(|_, failures| {
+                failures.retain(is_recent);
+                !failures.is_empty()
+            });
+        }
+        // NOTE: Recent failures are limited by `MAX_FAILURES`,
+        //   which is lower than `MAX_CLIENTS`.
+        let failures = self.by_client.entry(client).or_default();
+        failures.retain(is_recent);
+        failures.push(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::{
+        DeviceCodes, Redemption, CODE_ALPHABET, CODE_LENGTH, MAX_FAILURES, MAX_FAILURES_PER_CLIENT,
+    };
+
+    fn client(n: u32) -> Option<IpAddr> {
+        Some(IpAddr::from(n.to_be_bytes()))
+    }
+
+    #[test]
+    fn test_codes_are_single_use() {
+        let device_codes = DeviceCodes::default();
+        let code = device_codes.add("token".to_owned());
+        assert_eq!(code.len(), CODE_LENGTH);
+        assert!(code.bytes().all(|c| CODE_ALPHABET.contains(&c)), "{code}");
+
+        assert_eq!(
+            device_codes.redeem(&format!(" {} ", code.to_lowercase()), client(1)),
+            Redemption::Redeemed("token".to_owned()),
+        );
+        assert_eq!(device_codes.redeem(&code, client(1)), Redemption::Invalid);
+    }
+
+    #[test]
+    fn test_failures_are_limited_per_client() {
+        let device_codes = DeviceCodes::default();
+        let code = device_codes.add("token".to_owned());
+
+        for _ in 0..MAX_FAILURES_PER_CLIENT {
+            assert_eq!(device_codes.redeem("wrong", client(1)), Redemption::Invalid);
+        }
+        // Valid codes are not even checked anymore.
+        assert_eq!(
+            device_codes.redeem(&code, client(1)),
+            Redemption::TooManyFailures
+        );
+        assert_eq!(
+            device_codes.redeem(&code, client(2)),
+            Redemption::Redeemed("token".to_owned()),
+        );
+    }
+
+    #[test]
+    fn test_failures_are_limited_globally() {
+        let device_codes = DeviceCodes::default();
+        let code = device_codes.add("token".to_owned());
+
+        for n in 0..MAX_FAILURES as u32 {
+            assert_eq!(device_codes.redeem("wrong", client(n)), Redemption::Invalid);
+        }
+        assert_eq!(
+            device_codes.redeem(&code, client(u32::MAX)),
+            Redemption::TooManyFailures
+        );
+    }
+}
@@ -0,0 +1,817 @@
+//! End-to-end tests, running the whole app in-process against a fixture
+//! website (see `tests/fixtures/`).
+//!
+//! The website repository is a local git repository, and Hugo is replaced
+//! by a fake generator copying pre-built pages and data files
+//! (`tests/fixtures/bin/hugo`), so tests only need `git`.
+//!
+//! The same repository is also served as a second site on [`OTHER_HOST`],
+//! with its own root key and strict static files.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{self, Command},
+    sync::OnceLock,
+};
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{
+        header::{
+            ACCEPT, AUTHORIZATION, CACHE_CONTROL, CONTENT_TYPE, COOKIE, ETAG, HOST, IF_NONE_MATCH,
+            LOCATION, SET_COOKIE, VARY,
+        },
+        HeaderMap, HeaderValue, Method, Request, StatusCode,
+    },
+};
+use biscuit_auth::{macros::biscuit, Biscuit, KeyPair};
+use orangutan_helpers::copy_directory;
+use tokio::sync::{Mutex, MutexGuard};
+use tower::Service as _;
+
+use crate::{config::ROOT_KEY, AppState};
+
+/// Host of the second site (see `SITES_FILE`).
+const OTHER_HOST: &str = "other.example.org";
+/// Hash in the name of `tests/fixtures/website/public/css/main.<hash>.css`.
+const FINGERPRINT: &str = "4f6b0c2e9d1a3b5c7e8f0a1b2c3d4e5f60718293a4b5c6d7e8f9012345678abc";
+
+static HARNESS: OnceLock<Harness> = OnceLock::new();
+/// NOTE: Tests share global state (generated websites, revoked tokens…),
+///   so they must not run concurrently.
+static LOCK: Mutex<()> = Mutex::const_new(());
+
+pub(crate) struct Harness {
+    /// The website repository, which can be updated during tests.
+    repository: PathBuf,
+    /// Root key of the site on [`OTHER_HOST`].
+    other_key: KeyPair,
+    app_state: AppState,
+}
+
+/// Prepares the fixture website and starts the app, once for all tests.
+///
+/// WARN: Must be called before anything reads Orangutan's working directory
+///   (e.g. by generating a website), as it changes the current directory.
+pub(crate) fn harness() -> &'static Harness {
+    HARNESS.get_or_init(Harness::new)
+}
+
+impl Harness {
+    fn new() -> Self {
+        let root = env::temp_dir().join(format!("orangutan-e2e-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+
+        let repository = root.join("website");
+        copy_directory(&fixtures.join("website"), &repository).unwrap();
+        git(&repository, &["init", "--quiet"]);
+        git(&repository, &["add", "--all"]);
+        git(&repository, &[
+            "commit",
+            "--quiet",
+            "--message",
+            "Initial commit",
+        ]);
+
+        let work_dir = root.join("work");
+        fs::create_dir_all(&work_dir).unwrap();
+        let path = env::var("PATH").unwrap_or_default();
+        env::set_var("PATH", format!("{}:{path}", fixtures.join("bin").display()));
+        env::set_var("WEBSITE_REPOSITORY", &repository);
+        env::set_var("WEBSITE_ROOT", "http://localhost:8080");
+
+        let other_key = KeyPair::new();
+        let sites_file = root.join("sites.json");
+        let sites = serde_json::json!([{
+            "host": OTHER_HOST,
+            "repository": repository,
+            "key": "other",
+            "strict_static_files": true,
+        }]);
+        fs::write(&sites_file, sites.to_string()).unwrap();
+        env::set_var("SITES_FILE", &sites_file);
+        env::set_var("KEY_other", other_key.private().to_bytes_hex());
+        env::set_current_dir(&work_dir).unwrap();
+
+        let app_state = crate::app_state().unwrap();
+        crate::liftoff(&app_state.sites).unwrap();
+
+        Self {
+            repository,
+            other_key,
+            app_state,
+        }
+    }
+
+    /// Commits `content` at `path` in the website repository.
+    pub(crate) fn commit(
+        &self,
+        path: &str,
+        content: &str,
+    ) {
+        let file = self.repository.join(path);
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(file, content).unwrap();
+        git(&self.repository, &["add", "--all"]);
+        git(&self.repository, &[
+            "commit",
+            "--quiet",
+            "--message",
+            &format!("Update {path}"),
+        ]);
+    }
+
+    pub(crate) async fn lock(&self) -> MutexGuard<'static, ()> {
+        LOCK.lock().await
+    }
+
+    pub(crate) async fn request(
+        &self,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+    ) -> TestResponse {
+        self.request_to(None, method, uri, token).await
+    }
+
+    /// Sends a request with the given `Host` header.
+    pub(crate) async fn request_to(
+        &self,
+        host: Option<&str>,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+    ) -> TestResponse {
+        self.request_with_headers(host, method, uri, token, HeaderMap::new())
+            .await
+    }
+
+    pub(crate) async fn request_with_headers(
+        &self,
+        host: Option<&str>,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        headers: HeaderMap,
+    ) -> TestResponse {
+        self.request_with_body(host, method, uri, token, headers, Body::empty())
+            .await
+    }
+
+    pub(crate) async fn request_with_body(
+        &self,
+        host: Option<&str>,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        headers: HeaderMap,
+        body: Body,
+    ) -> TestResponse {
+        let mut app = crate::app(self.app_state.clone());
+        let mut request = Request::builder().method(method).uri(uri);
+        request.headers_mut().unwrap().extend(headers);
+        if let Some(host) = host {
+            request = request.header(HOST, host);
+        }
+        if let Some(token) = token {
+            request = request.header(COOKIE, format!("token={token}"));
+        }
+        let response = app.call(request.body(body).unwrap()).await.unwrap();
+
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        TestResponse {
+            status,
+            headers,
+            body: String::from_utf8_lossy(&body).into_owned(),
+        }
+    }
+
+    pub(crate) async fn get(
+        &self,
+        uri: &str,
+        token: Option<&str>,
+    ) -> TestResponse {
+        self.request(Method::GET, uri, token).await
+    }
+
+    /// Submits a form (`form` is URL-encoded) to the default site.
+    #[allow(dead_code)]
+    pub(crate) async fn post_form(
+        &self,
+        uri: &str,
+        token: Option<&str>,
+        form: &str,
+    ) -> TestResponse {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded"),
+        );
+        self.request_with_body(
+            None,
+            Method::POST,
+            uri,
+            token,
+            headers,
+            Body::from(form.to_owned()),
+        )
+        .await
+    }
+}
+
+pub(crate) struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+impl TestResponse {
+    pub(crate) fn location(&self) -> Option<&str> {
+        self.headers.get(LOCATION)?.to_str().ok()
+    }
+
+    /// `Set-Cookie` headers of the response.
+    pub(crate) fn set_cookies(&self) -> Vec<&str> {
+        (self.headers.get_all(SET_COOKIE).iter())
+            .filter_map(|value| value.to_str().ok())
+            .collect()
+    }
+
+    /// Value of the token cookie set by the response, if any.
+    pub(crate) fn token_cookie(&self) -> Option<String> {
+        (self.headers.get_all(SET_COOKIE).iter())
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(|value| value.strip_prefix("token="))
+            .filter_map(|value| value.split(';').next())
+            .find(|value| !value.is_empty())
+            .map(ToOwned::to_owned)
+    }
+}
+
+fn git(
+    repository: &Path,
+    args: &[&str],
+) {
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(repository)
+        .args([
+            "-c",
+            "user.name=Orangutan",
+            "-c",
+            "user.email=orangutan@example.org",
+        ])
+        .args(args)
+        .status()
+        .unwrap();
+    assert!(status.success(), "`git {}` failed", args.join(" "));
+}
+
+fn token(profile: &str) -> Biscuit {
+    token_for(&ROOT_KEY, profile)
+}
+
+fn token_for(
+    root_key: &KeyPair,
+    profile: &str,
+) -> Biscuit {
+    biscuit!("profile({profile});", profile = profile.to_owned())
+        .build(root_key)
+        .unwrap()
+}
+
+fn cookie(profile: &str) -> String {
+    token(profile).to_base64().unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_anonymous_access() {
+    let harness = harness();
+    let _lock = harness.lock().await;
+
+    let response = harness.get("/blog/", None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.body.contains("Blog"), "{}", response.body);
+
+    // Private pages are disguised as "not found".
+    let response = harness.get("/famille/", None).await;
+    assert_ne!(response.status, StatusCode::OK);
+    assert!(response.body.contains("Not found"), "{}", response.body);
+    assert!(!response.body.contains("Family photos"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_profile_access() {
+    let harness = harness();
+    let _lock = harness.lock().await;
+
+    let response = harness.get("/famille/", Some(&cookie("famille"))).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.body.contains("Family photos"), "{}", response.body);
+
+    // Authenticated users are told they don't have access.
+    let response = harness.get("/famille/", Some(&cookie("amis"))).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    assert!(!response.body.contains("Family photos"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_equivalent_paths_are_protected() {
+    let harness = harness();
+    let _lock = harness.lock().await;
+
+    // NOTE: `%69` is `i`, files are served after percent-decoding.
+    for path in [
+        "/famille",
+        "/famille/index.html",
+        "/famille/%69ndex.html",
+    ] {
+        let response = harness.get(path, None).await;
+        assert_ne!(response.status, StatusCode::OK, "{path}");
+        assert!(!response.body.contains("Family photos"), "{path}");
+    }
+
+    let response = harness
+        .get("/famille/%69ndex.html", Some(&cookie("famille")))
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.body.contains("Family photos"), "{}", response.body);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_static_files() {
+    let harness = harness();
+    let _lock = harness.lock().await;
+    let is_served = |host: Option<&'static str>, path: &'static str, profile: Option<&str>| {
+        let token = profile.map(cookie);
+        async move {
+            let response = (harness.request_to(host, Method::GET, path, token.as_deref())).await;
+            response.status == StatusCode::OK && !response.body.contains("Not found")
+        }
+    };
+
+    // Resources of a page bundle inherit the page's permissions.
+    assert!(!is_served(None, "/famille/photo.jpg", None).await);
+    assert!(is_served(None, "/famille/photo.jpg", Some("famille")).await);
+
+    // Other files follow `static_files.txt`.
+    assert!(!is_served(None, "/private/notes.txt", None).await);
+    assert!(!is_served(None, "/private/notes.txt", Some("amis")).await);
+    assert!(is_served(None, "/private/notes.txt", Some("famille")).await);
+    assert!(is_served(None, "/css/style.css", None).await);
+
+    // Files matching no rule are public, unless in strict mode.
+    assert!(is_served(None, "/robots.txt", None).await);
+    assert!(!is_served(Some(OTHER_HOST), "/robots.txt", None).await);
+    assert!(is_served(Some(OTHER_HOST), "/css/style.css", None).await);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_caching_headers() {
+    let harness = harness();
+    let _lock = harness.lock().await;
+    let conditional_get = |path: &str, token: Option<String>, etag: HeaderValue| {
+        let path = path.to_owned();
+        async move {
+            let headers = HeaderMap::from_iter([(IF_NONE_MATCH, etag)]);
+            (harness.request_with_headers(None, Method::GET, &path, token.as_deref(), headers))
+                .await
+        }
+    };
+
+    let response = harness.get("/blog/", None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.headers[CACHE_CONTROL], "public, no-cache");
+    assert_eq!(response.headers[VARY], "Cookie, Authorization");
+    let etag = response.headers[ETAG].clone();
+
+    let response = conditional_get("/blog/", None, etag.clone()).await;
+    assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers[ETAG], etag);
+    assert!(response.body.is_empty());
+
+    // Websites generated for other profiles have other entity tags.
+    let famille = cookie("famille");
+    let response = conditional_get("/blog/", Some(famille.clone()), etag.clone()).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.headers[CACHE_CONTROL], "private, no-cache");
+    assert_ne!(response.headers[ETAG], etag);
+
+    let response = harness.get("/famille/", Some(&famille)).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.headers[CACHE_CONTROL], "private, no-store");
+    assert_eq!(response.headers[VARY], "Cookie, Authorization");
+    assert!(!response.headers.contains_key(ETAG));
+
+    // Forbidden pages disguised as "not found" depend on the token too.
+    let response = harness.get("/famille/", None).await;
+    assert_ne!(response.status, StatusCode::OK);
+    assert_eq!(response.headers[CACHE_CONTROL], "private, no-store");
+    assert_eq!(response.headers[VARY], "Cookie, Authorization");
+
+    let response = harness
+        .get(&format!("/css/main.{FINGERPRINT}.css"), None)
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        response.headers[CACHE_CONTROL],
+        "public, max-age=31536000, immutable"
+    );
+
+    // Missing files are never "not modified", nor immutable.
+    let missing = format!("/css/missing.{FINGERPRINT}.css");
+    let response = conditional_get(&missing, None, etag.clone()).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert_eq!(response.headers[CACHE_CONTROL], "private, no-cache");
+    assert!(!response.headers.contains_key(ETAG));
+    let response = conditional_get("/blog/", None, HeaderValue::from_static("*")).await;
+    assert_eq!(response.status, StatusCode::OK);
+
+    // Entity tags change with the content.
+    harness.commit("public/blog/index.html", "<h1>Blog</h1>\n<p>Updated</p>\n");
+    let response = harness
+        .request(Method::POST, "/update-content/github", None)
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let response = conditional_get("/blog/", None, etag.clone()).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.body.contains("Updated"), "{}", response.body);
+    assert_ne!(response.headers[ETAG], etag);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_refresh_token_flow() {
+    let harness = harness();
+    let _lock = harness.lock().await;
+
+    let refresh_token = cookie("famille");
+    let response = harness
+        .get(&format!("/famille/?refresh_token={refresh_token}"), None)
+        .await;
+    assert_eq!(response.status, StatusCode::SEE_OTHER);
+    assert_eq!(response.location(), Some("/famille/"));
+    let token = response.token_cookie().expect("token cookie should be set");
+
+    let response = harness.get("/famille/", Some(&token)).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.body.contains("Family photos"), "{}", response.body);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_logout_removes_query_token_cookies() {
+    let harness = harness();
+    let _lock = harness.lock().await;
+
+    let response = harness
+        .get(&format!("/famille/?token={}", cookie("famille")), None)
+        .await;
+    assert_eq!(response.status, StatusCode::SEE_OTHER);
+    let set_cookies = response.set_cookies();
+    assert!(
+        (set_cookies.iter())
+            .any(|c| c.starts_with("token_shared=") && c.contains("Path=/famille/")),
+        "{set_cookies:?}"
+    );
+    let paths_cookie = (set_cookies.iter())
+        .find(|c| c.starts_with("token_shared_paths="))
+        .and_then(|c| c.split(';').next())
+        .expect("query token paths should be remembered");
+
+    // NOTE: Query token cookies are not sent to `/_logout`, only their paths.
+    let mut headers = HeaderMap::new();
+    headers.insert(COOKIE, HeaderValue::from_str(paths_cookie).unwrap());
+    let response = harness
+        .request_with_headers(None, Method::GET, "/_logout", None, headers)
+        .await;
+    let set_cookies = response.set_cookies();
+    assert!(
+        (set_cookies.iter()).any(|c| c.starts_with("token_shared=;")
+            && c.contains("Path=/famille/")
+            && c.contains("Max-Age=0")),
+        "{set_cookies:?}"
+    );
+    assert!(
+        (set_cookies.iter()).any(|c| c.starts_with("token_shared_paths=;")),
+        "{set_cookies:?}"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_search_index_is_filtered() {
+    let harness = harness();
+    let _lock = harness.lock().await;
+
+    let response = harness.get("/index.json", None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.body.contains("Blog"), "{}", response.body);
+    assert!(
+        !response.body.contains("Family photos"),
+        "{}",
+        response.body
+    );
+    // Entries which don't point to a page are removed.
+    assert!(!response.body.contains("Home"), "{}", response.body);
+
+    let response = harness.get("/index.json", Some(&cookie("famille"))).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.body.contains("Blog"), "{}", response.body);
+    assert!(response.body.contains("Family photos"), "{}", response.body);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_content_update_and_revocation() {
+    let harness = harness();
+    let _lock = harness.lock().await;
+
+    let revoked = token("famille");
+    let valid = cookie("famille");
+    assert_eq!(
+        (harness
+            .get("/famille/", Some(&revoked.to_base64().unwrap()))
+            .await)
+            .status,
+        StatusCode::OK,
+    );
+    assert_ne!(
+        (harness.get("/nouveau/", Some(&valid)).await).status,
+        StatusCode::OK,
+    );
+
+    harness.commit("public/nouveau/index.html", "<h1>New page</h1>\n");
+    harness.commit(
+        "data/nouveau/index.orangutan",
+        r#"{ "read_allowed": ["famille"], "path": "/nouveau/" }"#,
+    );
+    let revocation_ids: Vec<String> = (revoked.revocation_identifiers().iter())
+        .map(hex::encode)
+        .collect();
+    harness.commit("revoked_tokens.txt", &revocation_ids.join("\n"));
+
+    let response = harness
+        .request(Method::POST, "/update-content/github", None)
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+
+    let response = harness.get("/nouveau/", Some(&valid)).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.body.contains("New page"), "{}", response.body);
+
+    let response = harness
+        .get("/famille/", Some(&revoked.to_base64().unwrap()))
+        .await;
+    assert_ne!(response.status, StatusCode::OK);
+    assert!(!response.body.contains("Family photos"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_sites_have_their_own_keys() {
+    let harness = harness();
+    let _lock = harness.lock().await;
+
+    let default_token = cookie("famille");
+    let other_token = token_for(&harness.other_key, "famille")
+        .to_base64()
+        .unwrap();
+    let get = |host: Option<&'static str>, token: String| async move {
+        harness
+            .request_to(host, Method::GET, "/famille/", Some(&token))
+            .await
+    };
+
+    let response = get(Some(OTHER_HOST), other_token.clone()).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.body.contains("Family photos"), "{}", response.body);
+    // Ports and case are ignored.
+    let response = get(Some("Other.Example.org:8080"), other_token.clone()).await;
+    assert_eq!(response.status, StatusCode::OK);
+
+    // Tokens are only valid for the site which issued them.
+    let response = get(Some(OTHER_HOST), default_token.clone()).await;
+    assert_ne!(response.status, StatusCode::OK);
+    assert!(!response.body.contains("Family photos"));
+    let response = get(None, other_token).await;
+    assert_ne!(response.status, StatusCode::OK);
+    assert!(!response.body.contains("Family photos"));
+
+    // Unknown hosts are served by the default site.
+    let response = get(Some("unknown.example.org"), default_token).await;
+    assert_eq!(response.status, StatusCode::OK);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_sites_have_their_own_access_logs() {
+    let harness = harness();
+    let _lock = harness.lock().await;
+
+    let visitor = token_for(&harness.other_key, "isolation-check")
+        .to_base64()
+        .unwrap();
+    let mut headers = HeaderMap::new();
+    headers.insert(ACCEPT, HeaderValue::from_static("text/html"));
+    harness
+        .request_with_headers(
+            Some(OTHER_HOST),
+            Method::GET,
+            "/blog/",
+            Some(&visitor),
+            headers,
+        )
+        .await;
+
+    let other_admin = token_for(&harness.other_key, "*").to_base64().unwrap();
+    let response = harness
+        .request_to(
+            Some(OTHER_HOST),
+            Method::GET,
+            "/_access-logs",
+            Some(&other_admin),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(
+        response.body.contains("isolation-check"),
+        "{}",
+        response.body
+    );
+
+    let response = harness.get("/_access-logs", Some(&cookie("*"))).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(
+        !response.body.contains("isolation-check"),
+        "{}",
+        response.body
+    );
+}
+
+#[cfg(feature = "request-access")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_access_requests_only_accept_local_pages() {
+    let harness = harness();
+    let _lock = harness.lock().await;
+
+    let request_access = |path: &'static str| async move {
+        let form = format!("name=Alice&path={}", urlencoding::encode(path));
+        harness
+            .post_form("/_request-access", Some(&cookie("amis")), &form)
+            .await
+    };
+
+    // NOTE: Approving such a request would send a link to another website.
+    for path in [
+        "@evil.example/",
+        "//evil.example/",
+        "/\\evil.example/",
+    ] {
+        let response = request_access(path).await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST, "{path}");
+    }
+    let response = request_access("/not-a-page/").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+    let response = request_access("/famille/").await;
+    assert_eq!(response.status, StatusCode::ACCEPTED);
+}
+
+#[cfg(feature = "share-link")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_share_links_only_give_access_to_the_shared_page() {
+    let harness = harness();
+    let _lock = harness.lock().await;
+
+    let response = harness
+        .post_form("/_share", Some(&cookie("famille")), "path=/blog/&ttl=")
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let share_token: String = (response.body.split("?token=").nth(1))
+        .expect("No share link")
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+        .collect();
+
+    let response = harness.get("/blog/", Some(&share_token)).await;
+    assert_eq!(response.status, StatusCode::OK);
+
+    // The sharer's other profiles must not choose the generated website.
+    let response = harness.get("/index.json", Some(&share_token)).await;
+    assert!(
+        !response.body.contains("Family photos"),
+        "{}",
+        response.body
+    );
+}
+
+#[cfg(feature = "token-generator")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_revoking_a_link_revokes_sessions_opened_with_it() {
+    use crate::issued_links::{issue_link, LinkRequest};
+
+    let harness = harness();
+    let _lock = harness.lock().await;
+
+    // NOTE: Link names are not unique.
+    let request = LinkRequest {
+        name: "revocation-check".to_owned(),
+        profiles: vec!["famille".to_owned()],
+        ttl: "P1D".to_owned(),
+        url: "/famille/".to_owned(),
+        max_uses: None,
+    };
+    let site = crate::sites::current();
+    let (link, issued_link) = issue_link(&site, request.clone(), vec!["*".to_owned()]).unwrap();
+    issue_link(&site, request, vec!["*".to_owned()]).unwrap();
+
+    let response = harness.get(&link, None).await;
+    assert_eq!(response.status, StatusCode::SEE_OTHER);
+    let token = response.token_cookie().expect("token cookie should be set");
+    let mut headers = HeaderMap::new();
+    headers.insert(ACCEPT, HeaderValue::from_static("text/html"));
+    let response = harness
+        .request_with_headers(None, Method::GET, "/famille/", Some(&token), headers)
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+
+    // Only the link which was used is marked as used.
+    let response = harness
+        .get("/_issued-links?name=revocation-check", Some(&cookie("*")))
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        response.body.matches("Never").count(),
+        2,
+        "{}",
+        response.body
+    );
+
+    let response = harness
+        .post_form(
+            "/_issued-links/revoke",
+            Some(&cookie("*")),
+            &format!("id={}", issued_link.id),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::SEE_OTHER, "{}", response.body);
+
+    // The access token baked from the link is revoked too.
+    let response = harness.get("/famille/", Some(&token)).await;
+    assert_ne!(response.status, StatusCode::OK);
+    assert!(!response.body.contains("Family photos"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_api_requires_an_admin_access_token() {
+    use orangutan_refresh_token::RefreshToken;
+
+    let harness = harness();
+    let _lock = harness.lock().await;
+    let api_request = |method: Method, uri: &'static str, bearer: String, body: &'static str| async move {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {bearer}")).unwrap(),
+        );
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        (harness.request_with_body(None, method, uri, None, headers, Body::from(body))).await
+    };
+
+    let response = api_request(Method::GET, "/_api/v1/info", cookie("*"), "").await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+
+    #[cfg(feature = "jwt")]
+    {
+        use crate::jwt::{encode, JwtClaims};
+
+        let jwt = encode(&JwtClaims::new("admin".to_owned(), vec!["*".to_owned()])).unwrap();
+        let response = api_request(Method::GET, "/_api/v1/info", jwt, "").await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    }
+
+    // Cookies are ignored.
+    let response = harness.get("/_api/v1/info", Some(&cookie("*"))).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+
+    // Refresh tokens must be exchanged first.
+    let refresh_token = RefreshToken::new_with_key(
+        &ROOT_KEY,
+        std::time::Duration::from_secs(60),
+        ["*".to_owned()].into_iter(),
+        None,
+    )
+    .unwrap()
+    .as_base64()
+    .unwrap();
+    let response = api_request(Method::GET, "/_api/v1/info", refresh_token, "").await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+
+    // Invalid bodies get JSON errors too.
+    #[cfg(feature = "token-generator")]
+    {
+        let response = api_request(Method::POST, "/_api/v1/links", cookie("*"), "{").await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(body["error"], "bad_request", "{body}");
+    }
+}
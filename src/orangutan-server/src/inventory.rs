@@ -0,0 +1,140 @@
+//! Summaries of generated websites and page permissions, for admins.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    path::Path,
+};
+
+use chrono::{DateTime, Utc};
+use orangutan_helpers::workspace::Workspace;
+use serde::Serialize;
+
+use crate::sites::Site;
+
+#[derive(Debug, Serialize)]
+pub struct WebsiteSummary {
+    /// Profiles joined with `,` (e.g. `"amis,famille"`).
+    pub id: String,
+    /// Size on disk, in bytes.
+    pub size: u64,
+    pub modified_at: Option<DateTime<Utc>>,
+    pub last_accessed_at: Option<DateTime<Utc>>,
+}
+
+/// Websites of `site` generated since the last content update.
+pub fn website_summaries(site: &Site) -> Vec<WebsiteSummary> {
+    let workspace = &site.workspace;
+    let mut last_accesses: HashMap<String, DateTime<Utc>> = HashMap::new();
+    for log in site.state.access_logs.read().unwrap().iter() {
+        // NOTE: Logs are sorted chronologically, so the last one wins.
+        last_accesses.insert(workspace.website_id(&log.user).name(), log.timestamp);
+    }
+
+    (workspace.generated_websites())
+        .into_iter()
+        .map(|dir| {
+            let id = (dir.file_name().and_then(|name| name.to_str()))
+                .and_then(|name| name.split_once('@'))
+                .map(|(_, id)| id.to_owned())
+                .unwrap_or_else(|| dir.display().to_string());
+            let modified_at = (dir.metadata().and_then(|m| m.modified()).ok()).map(DateTime::from);
+            WebsiteSummary {
+                last_accessed_at: last_accesses.get(&id).cloned(),
+                id,
+                size: dir_size(&dir),
+                modified_at,
+            }
+        })
+        .collect()
+}
+
+fn dir_size(dir: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.file_type() {
+            Ok(t) if t.is_dir() => dir_size(&entry.path()),
+            Ok(_) => entry.metadata().map(|m| m.len()).unwrap_or_default(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+#[derive(Debug, Serialize)]
+pub struct PermissionMatrix {
+    /// Sorted alphabetically.
+    pub profiles: Vec<String>,
+    /// Number of pages each profile can read, in the same order as `profiles`.
+    pub page_counts: Vec<usize>,
+    /// Sorted by path.
+    pub pages: Vec<PagePermissions>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PagePermissions {
+    pub path: String,
+    /// Permissions of each profile, in the same order as [`PermissionMatrix::profiles`].
+    pub permissions: Vec<Permission>,
+    /// Whether or not the page has a custom Datalog policy,
+    /// in which case effective permissions might be narrower.
+    pub has_policy: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    None,
+    Read,
+    ReadAndComment,
+}
+
+/// Builds a page × profile matrix from the `.orangutan` data files
+/// (profiles come from the website's profile index).
+///
+/// NOTE: This only reflects `read_allowed` and `comment_allowed`,
+///   token attenuations (e.g. share links) are not taken into account.
+pub fn permission_matrix(workspace: &Workspace) -> PermissionMatrix {
+    let mut pages = workspace.all_pages_metadata();
+    pages.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let profile_index = workspace.profile_index();
+    let profiles: Vec<String> = (profile_index.profiles().cloned())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let page_counts = (profiles.iter())
+        .map(|profile| profile_index.pages(profile).count())
+        .collect();
+
+    let pages = pages
+        .into_iter()
+        .map(|page| {
+            let permissions = (profiles.iter())
+                .map(|profile| {
+                    if !page.read_allowed.contains(profile) {
+                        return Permission::None;
+                    }
+                    if page.comment_allowed.contains(profile) {
+                        Permission::ReadAndComment
+                    } else {
+                        Permission::Read
+                    }
+                })
+                .collect();
+            PagePermissions {
+                path: page.path.display().to_string(),
+                permissions,
+                has_policy: page.policy.is_some(),
+            }
+        })
+        .collect();
+
+    PermissionMatrix {
+        profiles,
+        page_counts,
+        pages,
+    }
+}
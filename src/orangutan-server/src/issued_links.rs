@@ -0,0 +1,200 @@
+//! File-backed registry of links generated with the token generator,
+//! so admins can audit and revoke them.
+
+use std::{fs::File, io, path::Path};
+
+use chrono::{DateTime, Utc};
+use iso8601_duration::Duration as IsoDuration;
+use orangutan_refresh_token::RefreshToken;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, trace};
+
+use crate::{
+    config::{ISSUED_LINKS_FILE, REFRESH_TOKEN_QUERY_PARAM_NAME},
+    sites::Site,
+    util::write_atomically,
+};
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct IssuedLinks(Vec<IssuedLink>);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IssuedLink {
+    pub id: String,
+    pub name: String,
+    pub profiles: Vec<String>,
+    /// ISO 8601 duration.
+    pub ttl: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Landing page.
+    pub url: String,
+    #[serde(default)]
+    pub max_uses: Option<u32>,
+    /// Hex-encoded Biscuit revocation identifiers.
+    pub revocation_ids: Vec<String>,
+    /// Profiles of the admin who generated the link.
+    pub issuer: Vec<String>,
+    pub issued_at: DateTime<Utc>,
+    #[serde(default)]
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl IssuedLinks {
+    pub fn read(file_path: &Path) -> Result<Self, Error> {
+        let Ok(file) = File::open(file_path) else {
+            info!(
+                "Issued links file not found at <{}>. Considering no link issued.",
+                file_path.display(),
+            );
+            return Ok(Self::default());
+        };
+        let links: Self = serde_json::from_reader(file)?;
+        info!("Found {} issued link(s).", links.0.len());
+        Ok(links)
+    }
+
+    pub fn save(
+        &self,
+        file_path: &Path,
+    ) -> Result<(), Error> {
+        trace!("Saving issued links to <{}>…", file_path.display());
+        write_atomically(file_path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn all(&self) -> &Vec<IssuedLink> {
+        &self.0
+    }
+
+    pub fn get(
+        &self,
+        id: &str,
+    ) -> Option<&IssuedLink> {
+        self.0.iter().find(|link| link.id == id)
+    }
+}
+
+/// What is needed to (re-)issue a link.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LinkRequest {
+    pub name: String,
+    pub profiles: Vec<String>,
+    pub ttl: String,
+    pub url: String,
+    #[serde(default)]
+    pub max_uses: Option<u32>,
+}
+
+impl From<&IssuedLink> for LinkRequest {
+    fn from(link: &IssuedLink) -> Self {
+        Self {
+            name: link.name.clone(),
+            profiles: link.profiles.clone(),
+            ttl: link.ttl.clone(),
+            url: link.url.clone(),
+            max_uses: link.max_uses,
+        }
+    }
+}
+
+/// Generates a refresh token link for `site`
+/// and records it in the site's issued links.
+pub fn issue_link(
+    site: &Site,
+    request: LinkRequest,
+    issuer: Vec<String>,
+) -> Result<(String, IssuedLink), crate::Error> {
+    let mut profiles = vec![request.name.to_owned()];
+    profiles.append(&mut request.profiles.clone());
+    if profiles.contains(&"*".to_string()) {
+        Err(crate::Error::ClientError(format!(
+            "Profiles cannot contain '*' (got {profiles:?})."
+        )))?
+    }
+
+    let token = RefreshToken::try_from_with_key(
+        &site.root_key,
+        request.ttl.to_owned(),
+        profiles.into_iter(),
+        request.max_uses,
+    )?;
+    let token_base64 = token.as_base64()?;
+    let link = format!(
+        "{}?{REFRESH_TOKEN_QUERY_PARAM_NAME}={token_base64}",
+        request.url
+    );
+
+    let issued_at = Utc::now();
+    let expires_at = IsoDuration::parse(&request.ttl)
+        .ok()
+        .and_then(|d| d.to_std())
+        .and_then(|d| chrono::Duration::from_std(d).ok())
+        .map(|d| issued_at + d);
+    let issued_link = IssuedLink {
+        id: hex::encode(rand::random::<[u8; 8]>()),
+        name: request.name,
+        profiles: request.profiles,
+        ttl: request.ttl,
+        expires_at,
+        url: request.url,
+        max_uses: request.max_uses,
+        revocation_ids: token
+            .revocation_identifiers()
+            .iter()
+            .map(hex::encode)
+            .collect(),
+        issuer,
+        issued_at,
+        revoked_at: None,
+    };
+    debug!("Issued link {} ({})", issued_link.id, issued_link.name);
+
+    let mut issued_links = site.state.issued_links.write().unwrap();
+    issued_links.0.push(issued_link.clone());
+    issued_links.save(&site.data_file(ISSUED_LINKS_FILE))?;
+
+    Ok((link, issued_link))
+}
+
+/// Revokes a link, both in memory and on disk.
+///
+/// Returns `false` if the link does not exist.
+pub fn revoke_link(
+    site: &Site,
+    id: &str,
+) -> Result<bool, crate::Error> {
+    let mut issued_links = site.state.issued_links.write().unwrap();
+    let Some(link) = issued_links.0.iter_mut().find(|link| link.id == id) else {
+        return Ok(false);
+    };
+    if link.revoked_at.is_some() {
+        return Ok(true);
+    }
+
+    let revocation_ids: Vec<Vec<u8>> = link
+        .revocation_ids
+        .iter()
+        .filter_map(|id| hex::decode(id).ok())
+        .collect();
+    site.workspace.revoke_tokens(&revocation_ids)?;
+    site.state
+        .revoked_tokens
+        .write()
+        .unwrap()
+        .extend(revocation_ids);
+
+    link.revoked_at = Some(Utc::now());
+    debug!("Revoked link {} ({})", link.id, link.name);
+    issued_links.save(&site.data_file(ISSUED_LINKS_FILE))?;
+
+    Ok(true)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("IO error: {0}")]
+    IOError(#[from] io::Error),
+    #[error("JSON error: {0}")]
+    JsonError(#[from] serde_json::Error),
+}
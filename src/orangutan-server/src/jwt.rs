@@ -0,0 +1,426 @@
+//! Encrypted JWTs (see design v3), accepted alongside Biscuits.
+//!
+//! Tokens are [JWE]s in compact serialization, using direct encryption (`dir`)
+//! with AES-256-GCM (`A256GCM`). The key is derived from the root Biscuit key
+//! of the current site, so no new key needs to be managed. Since only Orangutan can encrypt tokens,
+//! the authentication tag also proves Orangutan issued them.
+//!
+//! JWTs don't support attenuation, so they are turned into in-memory Biscuits
+//! containing their profiles before authorization.
+//!
+//! [JWE]: https://www.rfc-editor.org/rfc/rfc7516
+
+// NOTE: Without `migrate-biscuits`, JWTs are accepted but never issued.
+#![cfg_attr(not(feature = "migrate-biscuits"), allow(dead_code))]
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::{prelude::BASE64_URL_SAFE_NO_PAD, Engine as _};
+use biscuit_auth::{
+    macros::{check, fact},
+    Biscuit,
+};
+use chrono::{DateTime, TimeDelta, Utc};
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    hkdf,
+};
+use serde::{Deserialize, Serialize};
+use tracing::trace;
+
+use crate::{config::TOKEN_COOKIE, sites};
+
+/// Value of the `iss` claim.
+pub const JWT_ISSUER: &str = "orangutan";
+/// Protected header of all JWTs issued by Orangutan.
+const JWE_HEADER: &str = r#"{"alg":"dir","enc":"A256GCM","typ":"JWT"}"#;
+/// Maximum lifetime of JWTs, so a leaked token doesn't work for years.
+const MAX_TTL: TimeDelta = TimeDelta::days(30);
+
+fn current_key() -> LessSafeKey {
+    derive_key(&sites::current().root_key.private().to_bytes())
+}
+
+fn derive_key(private_key: &[u8]) -> LessSafeKey {
+    let salt = hkdf::Salt::new(hkdf::HKDF_SHA256, b"orangutan-jwt");
+    let key: UnboundKey = salt
+        .extract(private_key)
+        .expand(&[b"A256GCM"], &AES_256_GCM)
+        .expect("AES-256-GCM key length is valid for HKDF-SHA256")
+        .into();
+    LessSafeKey::new(key)
+}
+
+/// Claims of Orangutan JWTs.
+///
+/// Uses [IANA-registered claims] where possible.
+///
+/// [IANA-registered claims]: https://www.iana.org/assignments/jwt/jwt.xhtml
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JwtClaims {
+    pub iss: String,
+    /// Identifies the user (or the token they migrated from,
+    /// as a hex-encoded revocation identifier).
+    pub sub: String,
+    /// Issued at (seconds since the Unix epoch).
+    pub iat: i64,
+    /// Expires at (seconds since the Unix epoch).
+    pub exp: i64,
+    /// Hex-encoded, allows revoking tokens like Biscuits.
+    pub jti: String,
+    pub profiles: Vec<String>,
+    /// Hex-encoded revocation identifiers of the tokens this JWT was derived from
+    /// (e.g. the link a user logged in with), revoking it too.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parents: Vec<String>,
+}
+
+impl JwtClaims {
+    pub fn new(
+        sub: String,
+        profiles: Vec<String>,
+    ) -> Self {
+        let now = Utc::now();
+        // NOTE: JWTs live as long as the token cookie, up to `MAX_TTL`.
+        let ttl = TimeDelta::seconds(TOKEN_COOKIE.max_age.whole_seconds()).min(MAX_TTL);
+        Self {
+            iss: JWT_ISSUER.to_owned(),
+            sub,
+            iat: now.timestamp(),
+            exp: (now + ttl).timestamp(),
+            jti: hex::encode(rand::random::<[u8; 16]>()),
+            profiles,
+            parents: vec![],
+        }
+    }
+
+    /// Turns claims into a Biscuit, so authorization works the same for all tokens.
+    ///
+    /// NOTE: Share and device links are derived from this Biscuit, so it expires
+    ///   with the JWT and is revoked with it (see [`crate::request_guards::is_revoked`]).
+    pub fn to_biscuit(&self) -> Result<Biscuit, biscuit_auth::error::Token> {
+        let mut builder = Biscuit::builder();
+        for profile in self.profiles.iter().cloned() {
+            builder.add_fact(fact!("profile({profile});"))?;
+        }
+        for parent in [&self.jti, &self.sub]
+            .into_iter()
+            .chain(&self.parents)
+            .cloned()
+        {
+            builder.add_fact(fact!("parent({parent});"))?;
+        }
+        let exp = DateTime::from_timestamp(self.exp, 0).map_or(UNIX_EPOCH, SystemTime::from);
+        builder.add_check(check!("check if time($time), $time <= {exp};"))?;
+        builder.build(&sites::current().root_key)
+    }
+}
+
+/// Encrypts `claims` into a compact JWE, for the current site.
+pub fn encode(claims: &JwtClaims) -> Result<String, Error> {
+    encode_(&current_key(), claims)
+}
+
+fn encode_(
+    key: &LessSafeKey,
+    claims: &JwtClaims,
+) -> Result<String, Error> {
+    let header = BASE64_URL_SAFE_NO_PAD.encode(JWE_HEADER);
+    let iv: [u8; NONCE_LEN] = rand::random();
+    let mut data = serde_json::to_vec(claims)?;
+    let tag = key
+        .seal_in_place_separate_tag(
+            Nonce::assume_unique_for_key(iv),
+            Aad::from(header.as_bytes()),
+            &mut data,
+        )
+        .map_err(|_| Error::CannotEncrypt)?;
+
+    // NOTE: The encrypted key is empty when using direct encryption.
+    Ok(format!(
+        "{header}..{}.{}.{}",
+        BASE64_URL_SAFE_NO_PAD.encode(iv),
+        BASE64_URL_SAFE_NO_PAD.encode(data),
+        BASE64_URL_SAFE_NO_PAD.encode(tag),
+    ))
+}
+
+/// Returns `true` if `token` looks like a compact JWE (it might still be invalid).
+pub fn is_jwt(token: &str) -> bool {
+    token.split('.').count() == 5
+}
+
+/// Decrypts and validates a compact JWE, issued for the current site.
+pub fn decode(token: &str) -> Result<JwtClaims, Error> {
+    decode_(&current_key(), token)
+}
+
+fn decode_(
+    key: &LessSafeKey,
+    token: &str,
+) -> Result<JwtClaims, Error> {
+    let [header, encrypted_key, iv, ciphertext, tag] = token
+        .split('.')
+        .collect::<Vec<_>>()
+        .try_into()
+        .map_err(|_| Error::Malformed)?;
+    if !encrypted_key.is_empty() {
+        return Err(Error::Malformed);
+    }
+    let decoded_header = BASE64_URL_SAFE_NO_PAD.decode(header)?;
+    let header_json: serde_json::Value = serde_json::from_slice(&decoded_header)?;
+    if header_json["alg"] != "dir" || header_json["enc"] != "A256GCM" {
+        return Err(Error::UnsupportedAlgorithm);
+    }
+
+    let iv: [u8; NONCE_LEN] = (BASE64_URL_SAFE_NO_PAD.decode(iv)?)
+        .try_into()
+        .map_err(|_| Error::Malformed)?;
+    let mut data = BASE64_URL_SAFE_NO_PAD.decode(ciphertext)?;
+    data.extend(BASE64_URL_SAFE_NO_PAD.decode(tag)?);
+    let plaintext = key
+        .open_in_place(
+            Nonce::assume_unique_for_key(iv),
+            Aad::from(header.as_bytes()),
+            &mut data,
+        )
+        .map_err(|_| Error::CannotDecrypt)?;
+    let claims: JwtClaims = serde_json::from_slice(plaintext)?;
+
+    if claims.iss != JWT_ISSUER {
+        return Err(Error::InvalidIssuer(claims.iss));
+    }
+    if claims.exp <= Utc::now().timestamp() {
+        return Err(Error::Expired);
+    }
+    // NOTE: Revoking a Biscuit also revokes the JWTs it was migrated to.
+    let ids = [&claims.jti, &claims.sub]
+        .into_iter()
+        .chain(&claims.parents);
+    if is_revoked(ids.map(String::as_str)) {
+        return Err(Error::Revoked);
+    }
+    trace!("Decoded JWT for '{}'", claims.sub);
+
+    Ok(claims)
+}
+
+fn is_revoked<'a>(ids: impl IntoIterator<Item = &'a str>) -> bool {
+    let site = sites::current();
+    let revoked_tokens = site.state.revoked_tokens.read().unwrap();
+    (ids.into_iter()).any(|id| hex::decode(id).is_ok_and(|id| revoked_tokens.contains(&id)))
+}
+
+/// Converts a v2 Biscuit into JWT claims, if it can be done without
+/// giving more rights.
+///
+/// Only Biscuits with a single block and no check can be converted,
+/// as JWTs cannot express attenuations (e.g. share links restricted to a page).
+/// Revoked Biscuits are never converted.
+#[cfg(feature = "migrate-biscuits")]
+pub fn claims_from_biscuit(biscuit: &Biscuit) -> Option<JwtClaims> {
+    if biscuit.block_count() != 1 {
+        return None;
+    }
+    if crate::request_guards::is_revoked(biscuit) {
+        trace!("Not converting revoked Biscuit to a JWT");
+        return None;
+    }
+    let source = biscuit.print_block_source(0).ok()?;
+    if source.contains("check if") || source.contains("check all") || source.contains("reject if") {
+        return None;
+    }
+
+    // NOTE: Biscuits don't identify users, so we identify the original token.
+    let sub = hex::encode(biscuit.revocation_identifiers().first()?);
+    Some(JwtClaims {
+        parents: crate::request_guards::parents(biscuit),
+        ..JwtClaims::new(sub, crate::util::profiles(biscuit))
+    })
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Malformed JWE")]
+    Malformed,
+    #[error("Unsupported JWE algorithm")]
+    UnsupportedAlgorithm,
+    #[error("Invalid Base64: {0}")]
+    Base64Error(#[from] base64::DecodeError),
+    #[error("Invalid JSON: {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("Could not encrypt JWT")]
+    CannotEncrypt,
+    #[error("Could not decrypt JWT")]
+    CannotDecrypt,
+    #[error("Invalid issuer '{0}'")]
+    InvalidIssuer(String),
+    #[error("JWT expired")]
+    Expired,
+    #[error("JWT revoked")]
+    Revoked,
+}
+
+#[cfg(test)]
+mod tests {
+    use biscuit_auth::KeyPair;
+
+    use super::{decode_, derive_key, encode_, Error, JwtClaims, MAX_TTL};
+    use crate::sites;
+
+    #[test]
+    fn test_encode_decode() {
+        let key = derive_key(&KeyPair::new().private().to_bytes());
+        let claims = JwtClaims::new("alice".to_owned(), vec!["famille".to_owned()]);
+
+        let token = encode_(&key, &claims).unwrap();
+        assert_eq!(decode_(&key, &token).unwrap(), claims);
+
+        // Payloads are encrypted.
+        assert!(!token.contains(&claims.jti));
+
+        // Tokens cannot be tampered with.
+        let other_key = derive_key(&KeyPair::new().private().to_bytes());
+        assert!(matches!(
+            decode_(&other_key, &token),
+            Err(Error::CannotDecrypt)
+        ));
+        let mut parts: Vec<&str> = token.split('.').collect();
+        parts[3] = "AAAA";
+        assert!(decode_(&key, &parts.join(".")).is_err());
+    }
+
+    #[test]
+    fn test_expired_and_revoked() {
+        let key = derive_key(&KeyPair::new().private().to_bytes());
+
+        let mut expired = JwtClaims::new("bob".to_owned(), vec![]);
+        expired.exp = expired.iat - 1;
+        let token = encode_(&key, &expired).unwrap();
+        assert!(matches!(decode_(&key, &token), Err(Error::Expired)));
+
+        let revoked = JwtClaims::new("carol".to_owned(), vec![]);
+        let token = encode_(&key, &revoked).unwrap();
+        let jti = hex::decode(&revoked.jti).unwrap();
+        sites::current()
+            .state
+            .revoked_tokens
+            .write()
+            .unwrap()
+            .insert(jti.clone());
+        assert!(matches!(decode_(&key, &token), Err(Error::Revoked)));
+        sites::current()
+            .state
+            .revoked_tokens
+            .write()
+            .unwrap()
+            .remove(&jti);
+
+        // Revoking the token a JWT was migrated from revokes the JWT.
+        let source_id = rand::random::<[u8; 16]>().to_vec();
+        let migrated = JwtClaims::new(hex::encode(&source_id), vec![]);
+        let token = encode_(&key, &migrated).unwrap();
+        assert!(decode_(&key, &token).is_ok());
+        sites::current()
+            .state
+            .revoked_tokens
+            .write()
+            .unwrap()
+            .insert(source_id.clone());
+        assert!(matches!(decode_(&key, &token), Err(Error::Revoked)));
+        sites::current()
+            .state
+            .revoked_tokens
+            .write()
+            .unwrap()
+            .remove(&source_id);
+    }
+
+    /// Share and device links attenuate the Biscuit derived from a JWT,
+    /// they must not outlive it.
+    #[test]
+    fn test_derived_biscuits_expire_and_are_revoked_with_the_jwt() {
+        use axum::http::{HeaderMap, Method};
+        use biscuit_auth::macros::block;
+        use orangutan_helpers::{website_id::WebsiteId, PageMetadata};
+
+        use crate::{
+            auth::{is_authorized, RequestFacts},
+            request_guards::{is_revoked, Token},
+        };
+
+        let page: PageMetadata =
+            serde_json::from_str(r#"{ "read_allowed": ["famille"], "path": "/shared/" }"#).unwrap();
+        let request = RequestFacts::new(Method::GET, &HeaderMap::new(), &WebsiteId::default());
+        let share_link = |claims: &JwtClaims| {
+            let biscuit = (claims.to_biscuit().unwrap())
+                .append(block!(r#"check if path($path), $path == "/shared/";"#))
+                .unwrap();
+            Token::from(biscuit)
+        };
+
+        let claims = JwtClaims::new("erin".to_owned(), vec!["famille".to_owned()]);
+        let link = share_link(&claims);
+        assert!(is_authorized(Some(link.clone()), &page, &request));
+        assert!(!is_revoked(&link));
+
+        let mut expired = claims.clone();
+        expired.exp = expired.iat - 1;
+        assert!(!is_authorized(Some(share_link(&expired)), &page, &request));
+
+        let jti = hex::decode(&claims.jti).unwrap();
+        sites::current()
+            .state
+            .revoked_tokens
+            .write()
+            .unwrap()
+            .insert(jti.clone());
+        assert!(is_revoked(&link));
+        sites::current()
+            .state
+            .revoked_tokens
+            .write()
+            .unwrap()
+            .remove(&jti);
+    }
+
+    #[test]
+    fn test_lifetime_is_capped() {
+        let claims = JwtClaims::new("dave".to_owned(), vec![]);
+        assert!(claims.exp - claims.iat <= MAX_TTL.num_seconds());
+    }
+
+    #[cfg(feature = "migrate-biscuits")]
+    #[test]
+    fn test_only_unattenuated_biscuits_are_migrated() {
+        use biscuit_auth::macros::{biscuit, block};
+
+        use super::claims_from_biscuit;
+
+        let biscuit = biscuit!(r#"profile("amis");"#)
+            .build(&KeyPair::new())
+            .unwrap();
+        let claims = claims_from_biscuit(&biscuit).unwrap();
+        assert_eq!(claims.profiles, vec!["amis"]);
+
+        let shared = biscuit
+            .append(block!(r#"check if path($path), $path == "/shared/";"#))
+            .unwrap();
+        assert_eq!(claims_from_biscuit(&shared), None);
+
+        let revocation_id = biscuit.revocation_identifiers().remove(0);
+        sites::current()
+            .state
+            .revoked_tokens
+            .write()
+            .unwrap()
+            .insert(revocation_id.clone());
+        assert_eq!(claims_from_biscuit(&biscuit), None);
+        sites::current()
+            .state
+            .revoked_tokens
+            .write()
+            .unwrap()
+            .remove(&revocation_id);
+    }
+}
@@ -0,0 +1,326 @@
+//! Passwordless login: users listed in a site's repository
+//! (see [`Workspace::read_email_users`]) receive a short-lived login link by email.
+//!
+//! Emails are sent using SMTP, configured with environment variables:
+//!
+//! - `SMTP_HOST` (required to enable email login)
+//! - `SMTP_PORT` (defaults depend on `SMTP_TLS`)
+//! - `SMTP_TLS`: `starttls` (default), `tls` or `none`
+//!   (`none` is meant for local mock servers like Mailpit)
+//! - `SMTP_USERNAME` and `SMTP_PASSWORD` (optional)
+//! - `SMTP_FROM` (e.g. `Orangutan <orangutan@example.org>`)
+//!
+//! [`Workspace::read_email_users`]: orangutan_helpers::workspace::Workspace::read_email_users
+
+use std::{
+    collections::{HashMap, VecDeque},
+    env,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use lazy_static::lazy_static;
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport as _, Message, Tokio1Executor,
+};
+use orangutan_helpers::auth_config::EmailUser;
+use tracing::{debug, info};
+
+use crate::util::error;
+
+/// How long a login link stays valid.
+pub const MAGIC_LINK_TTL: Duration = Duration::from_secs(15 * 60);
+/// Maximum number of emails sent to the same address in [`RATE_LIMIT_WINDOW`].
+pub const MAX_EMAILS_PER_ADDRESS: usize = 3;
+/// Maximum number of emails sent to all addresses in [`RATE_LIMIT_WINDOW`],
+/// so requesting emails for many addresses doesn't flood the SMTP server.
+pub const MAX_EMAILS: usize = 100;
+pub const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60 * 60);
+/// Maximum number of addresses whose attempts are remembered,
+/// so they can't fill the memory.
+const MAX_ADDRESSES: usize = 1000;
+
+lazy_static! {
+    pub static ref SMTP_CONFIG: Option<SmtpConfig> = match SmtpConfig::from_env() {
+        Ok(config) => config,
+        Err(err) => {
+            error(format!(
+                "Invalid SMTP configuration, email login disabled: {err}"
+            ));
+            None
+        },
+    };
+}
+
+/// Users of a site allowed to log in by email.
+#[derive(Debug, Default)]
+pub struct EmailUsers(
+    /// Profiles, by lowercased email address.
+    RwLock<HashMap<String, Vec<String>>>,
+);
+
+impl EmailUsers {
+    /// Replaces known users (e.g. after the website repository was updated).
+    pub fn set(
+        &self,
+        users: Vec<EmailUser>,
+    ) {
+        *self.0.write().unwrap() = (users.into_iter())
+            .map(|user| (user.email, user.profiles))
+            .collect();
+    }
+
+    pub fn profiles(
+        &self,
+        email: &str,
+    ) -> Option<Vec<String>> {
+        self.0.read().unwrap().get(&email.to_lowercase()).cloned()
+    }
+}
+
+/// When login emails were requested on a site.
+#[derive(Debug, Default)]
+pub struct LoginAttempts {
+    /// All recent attempts, oldest first.
+    all: VecDeque<Instant>,
+    /// Attempts by lowercased email address.
+    by_address: HashMap<String, Vec<Instant>>,
+}
+
+fn is_recent(at: &Instant) -> bool {
+    at.elapsed() < RATE_LIMIT_WINDOW
+}
+
+impl LoginAttempts {
+    /// Records a login attempt for `email`.
+    ///
+    /// Returns `false` if too many emails were requested recently.
+    /// This is checked for all addresses, known or not,
+    /// so it doesn't tell which addresses are known.
+    pub fn record(
+        &mut self,
+        email: &str,
+    ) -> bool {
+        while self.all.front().is_some_and(|at| !is_recent(at)) {
+            self.all.pop_front();
+        }
+        if self.all.len() >= MAX_EMAILS {
+            return false;
+        }
+
+        let email = email.to_lowercase();
+        if self.by_address.len() >= MAX_ADDRESSES && !self.by_address.contains_key(&email) {
+            self.by_address.retain(|_, attempts| {
+                attempts.retain(is_recent);
+                !attempts.is_empty()
+            });
+        }
+        // NOTE: Recent attempts are limited by `MAX_EMAILS`,
+        //   which is lower than `MAX_ADDRESSES`.
+        let attempts = self.by_address.entry(email).or_default();
+        attempts.retain(is_recent);
+        if attempts.len() >= MAX_EMAILS_PER_ADDRESS {
+            return false;
+        }
+
+        let now = Instant::now();
+        attempts.push(now);
+        self.all.push_back(now);
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpTls {
+    None,
+    StartTls,
+    Tls,
+}
+
+#[derive(Debug, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: Option<u16>,
+    pub tls: SmtpTls,
+    pub credentials: Option<(String, String)>,
+    pub from: Mailbox,
+}
+
+impl SmtpConfig {
+    /// Returns `None` if `SMTP_HOST` is not set.
+    pub fn from_env() -> Result<Option<Self>, Error> {
+        let Ok(host) = env::var("SMTP_HOST") else {
+            info!("SMTP_HOST not set, email login disabled.");
+            return Ok(None);
+        };
+        let port = match env::var("SMTP_PORT") {
+            Ok(port) => Some(port.parse().map_err(|_| Error::InvalidPort(port))?),
+            Err(_) => None,
+        };
+        let tls = match env::var("SMTP_TLS").as_deref() {
+            Ok("none") => SmtpTls::None,
+            Ok("starttls") | Err(_) => SmtpTls::StartTls,
+            Ok("tls") => SmtpTls::Tls,
+            Ok(tls) => Err(Error::InvalidTls(tls.to_owned()))?,
+        };
+        let credentials = match (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+            (Ok(username), Ok(password)) => Some((username, password)),
+            _ => None,
+        };
+        let from = env::var("SMTP_FROM").map_err(|_| Error::MissingFrom)?;
+
+        Ok(Some(Self {
+            host,
+            port,
+            tls,
+            credentials,
+            from: from.parse()?,
+        }))
+    }
+
+    fn transport(&self) -> Result<AsyncSmtpTransport<Tokio1Executor>, Error> {
+        let mut builder = match self.tls {
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&self.host),
+            SmtpTls::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&self.host)?,
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&self.host)?,
+        };
+        if let Some(port) = self.port {
+            builder = builder.port(port);
+        }
+        if let Some((username, password)) = self.credentials.clone() {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+        Ok(builder.build())
+    }
+}
+
+/// Sends `link` to `email`.
+pub async fn send_magic_link(
+    config: &SmtpConfig,
+    email: &str,
+    link: &str,
+) -> Result<(), Error> {
+    let ttl_minutes = MAGIC_LINK_TTL.as_secs() / 60;
+    let message = Message::builder()
+        .from(config.from.clone())
+        .to(email.parse()?)
+        .subject("Your login link")
+        .header(ContentType::TEXT_PLAIN)
+        .body(format!(
+            "Hello,\n\n\
+            Open this link to log in (it expires in {ttl_minutes} minutes and works only once):\n\n\
+            {link}\n\n\
+            If you did not ask to log in, you can safely ignore this email.\n"
+        ))?;
+
+    config.transport()?.send(message).await?;
+    debug!("Sent login link by email");
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Invalid SMTP_PORT '{0}'")]
+    InvalidPort(String),
+    #[error("Invalid SMTP_TLS '{0}' (expected 'none', 'starttls' or 'tls')")]
+    InvalidTls(String),
+    #[error("SMTP_FROM is required")]
+    MissingFrom,
+    #[error("Invalid email address: {0}")]
+    AddressError(#[from] lettre::address::AddressError),
+    #[error("Could not build email: {0}")]
+    EmailError(#[from] lettre::error::Error),
+    #[error("SMTP error: {0}")]
+    SmtpError(#[from] lettre::transport::smtp::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncBufReadExt as _, AsyncWriteExt as _, BufReader},
+        net::TcpListener,
+    };
+
+    use super::{
+        send_magic_link, LoginAttempts, SmtpConfig, SmtpTls, MAX_EMAILS, MAX_EMAILS_PER_ADDRESS,
+    };
+
+    /// Minimal SMTP server accepting one email, returning the email's content.
+    async fn mock_smtp_server(listener: TcpListener) -> String {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+
+        let mut data = String::new();
+        let mut in_data = false;
+        while let Some(line) = lines.next_line().await.unwrap() {
+            if in_data {
+                if line == "." {
+                    in_data = false;
+                    writer.write_all(b"250 OK\r\n").await.unwrap();
+                } else {
+                    data.push_str(&line);
+                    data.push('\n');
+                }
+                continue;
+            }
+            let command = line.to_uppercase();
+            let reply: &[u8] = if command.starts_with("DATA") {
+                in_data = true;
+                b"354 Go ahead\r\n"
+            } else if command.starts_with("QUIT") {
+                writer.write_all(b"221 Bye\r\n").await.unwrap();
+                break;
+            } else {
+                b"250 OK\r\n"
+            };
+            writer.write_all(reply).await.unwrap();
+        }
+        data
+    }
+
+    #[tokio::test]
+    async fn test_send_magic_link() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(mock_smtp_server(listener));
+
+        let config = SmtpConfig {
+            host: "127.0.0.1".to_owned(),
+            port: Some(port),
+            tls: SmtpTls::None,
+            credentials: None,
+            from: "Orangutan <orangutan@example.org>".parse().unwrap(),
+        };
+        let link = "https://example.org/?refresh_token=abc";
+        send_magic_link(&config, "alice@example.org", link)
+            .await
+            .unwrap();
+
+        let email = server.await.unwrap();
+        assert!(email.contains("To: alice@example.org"), "{email}");
+        assert!(email.contains(link), "{email}");
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut attempts = LoginAttempts::default();
+        for _ in 0..MAX_EMAILS_PER_ADDRESS {
+            assert!(attempts.record("bob@example.org"));
+        }
+        assert!(!attempts.record("BOB@example.org"));
+        assert!(attempts.record("carol@example.org"));
+    }
+
+    #[test]
+    fn test_global_rate_limit() {
+        let mut attempts = LoginAttempts::default();
+        for n in 0..MAX_EMAILS {
+            assert!(attempts.record(&format!("user{n}@example.org")));
+        }
+        assert!(!attempts.record("dave@example.org"));
+    }
+}
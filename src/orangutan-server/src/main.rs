@@ -1,23 +1,42 @@
+#[cfg(feature = "request-access")]
+mod access_requests;
 mod auth;
+#[cfg(feature = "basic-auth")]
+mod basic_auth;
+mod caching;
+#[cfg(feature = "comments")]
+mod comments;
 mod config;
+#[cfg(feature = "link-device")]
+mod device_codes;
+#[cfg(test)]
+mod e2e;
+mod inventory;
+#[cfg(feature = "token-generator")]
+mod issued_links;
+#[cfg(feature = "jwt")]
+mod jwt;
+#[cfg(feature = "magic-link")]
+mod magic_link;
 mod middlewares;
+#[cfg(feature = "oidc")]
+mod oidc;
+mod redemptions;
 mod request_guards;
 mod routes;
+mod sites;
 mod util;
 
-use std::{fs, process::ExitCode};
+use std::{fs, process::ExitCode, sync::Arc};
 
 use axum::{
     body::Body,
     http::{Request, StatusCode},
     middleware,
-    response::{IntoResponse, Response},
-};
-use orangutan_helpers::{
-    generate::{self, *},
-    website_id::WebsiteId,
+    response::{Html, IntoResponse, Response},
 };
-use request_guards::{handle_refresh_token, REVOKED_TOKENS};
+use orangutan_helpers::{generate, website_id::WebsiteId};
+use request_guards::{handle_query_token, handle_refresh_token};
 use tokio::runtime::Handle;
 use tower::Service;
 use tower_http::{
@@ -26,22 +45,20 @@ use tower_http::{
 };
 use tracing::{debug, info, warn, Level};
 use tracing_subscriber::EnvFilter;
-#[cfg(feature = "token-generator")]
-use util::WebsiteRoot;
 
 #[cfg(feature = "templating")]
 use crate::util::templating;
 use crate::{
     config::NOT_FOUND_FILE,
-    middlewares::{request_id_middleware, tracing_middleware},
-    routes::update_content_routes,
+    middlewares::{referrer_policy_middleware, request_id_middleware, tracing_middleware},
+    routes::update_content_routes::{self, update_auth_config},
+    sites::{site_middleware, Sites},
     util::error,
 };
 
 #[derive(Clone)]
 struct AppState {
-    #[cfg(feature = "token-generator")]
-    website_root: WebsiteRoot,
+    sites: Arc<Sites>,
     #[cfg(feature = "templating")]
     tera: tera::Tera,
 }
@@ -55,18 +72,42 @@ async fn main() -> ExitCode {
         .pretty()
         .init();
 
-    #[cfg(feature = "token-generator")]
-    let website_root = match WebsiteRoot::try_from_env() {
-        Ok(r) => r,
+    // NOTE: Fail early if tokens are misconfigured.
+    lazy_static::initialize(&config::TOKEN_COOKIE);
+    lazy_static::initialize(&config::TOKEN_MERGE_POLICY);
+
+    let app_state = match app_state() {
+        Ok(app_state) => app_state,
         Err(err) => {
             tracing::error!("{err}");
             return ExitCode::FAILURE;
         },
     };
+    let sites = app_state.sites.clone();
+    let app = app(app_state);
+
+    info!("Generating websites");
+    if let Err(err) = liftoff(&sites) {
+        tracing::error!("{err}");
+        return ExitCode::FAILURE;
+    }
+
+    // Run our app with hyper, listening globally on port 8080.
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await.unwrap();
+    // NOTE: Client addresses are used to limit failed attempts (e.g. device codes).
+    let app = app.into_make_service_with_connect_info::<std::net::SocketAddr>();
+    axum::serve(listener, app).await.unwrap();
+
+    return ExitCode::SUCCESS;
+}
+
+fn app_state() -> Result<AppState, String> {
+    // NOTE: The default site needs a `WEBSITE_ROOT`, even if other sites are configured.
+    #[cfg(feature = "website-root")]
+    util::WebsiteRoot::try_from_env()?;
 
     let app_state = AppState {
-        #[cfg(feature = "token-generator")]
-        website_root,
+        sites: Arc::new(Sites::try_from_env()?),
         #[cfg(feature = "templating")]
         tera: Default::default(),
     };
@@ -77,48 +118,59 @@ async fn main() -> ExitCode {
     #[cfg(feature = "templating")]
     {
         info!("Initializing templating engine…");
-        if let Err(err) = app_state.tera.add_raw_templates(routes::templates()) {
-            tracing::error!("{err}");
-            return ExitCode::FAILURE;
-        }
+        (app_state.tera)
+            .add_raw_templates(routes::templates())
+            .map_err(|err| err.to_string())?;
     }
 
+    Ok(app_state)
+}
+
+fn app(app_state: AppState) -> axum::Router {
     let app = routes::router()
         .layer(middleware::from_fn(request_id_middleware))
         .layer(middleware::from_fn(tracing_middleware))
         .route_layer(middleware::from_fn(handle_refresh_token))
-        .layer(
-            TraceLayer::new_for_http()
-                .make_span_with(trace::DefaultMakeSpan::new().level(Level::INFO))
-                .on_response(trace::DefaultOnResponse::new().level(Level::INFO)),
-        )
-        .with_state(app_state);
+        .route_layer(middleware::from_fn(handle_query_token))
+        .layer(middleware::from_fn(referrer_policy_middleware));
+    #[cfg(feature = "migrate-biscuits")]
+    let app = app.layer(middleware::from_fn(middlewares::migrate_biscuit_cookie));
+    let app = app.layer(middleware::from_fn_with_state(
+        app_state.clone(),
+        site_middleware,
+    ));
+    app.layer(
+        TraceLayer::new_for_http()
+            // NOTE: Same as `DefaultMakeSpan`, but hiding tokens passed in URLs.
+            .make_span_with(|req: &Request<Body>| {
+                tracing::info_span!(
+                    "request",
+                    method = %req.method(),
+                    uri = %util::redact_uri(req.uri()),
+                    version = ?req.version(),
+                )
+            })
+            .on_response(trace::DefaultOnResponse::new().level(Level::INFO)),
+    )
+    .with_state(app_state)
     // .register("/", catchers![unauthorized, forbidden, not_found])
-
-    info!("Generating default website");
-    if let Err(err) = liftoff() {
-        tracing::error!("{err}");
-        return ExitCode::FAILURE;
-    }
-
-    // Run our app with hyper, listening globally on port 8080.
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
-
-    return ExitCode::SUCCESS;
 }
 
-fn liftoff() -> Result<(), Error> {
-    create_tmp_dir()?;
-    clone_repository()?;
-    // NOTE: This is just a hotfix. I had to quickly revoke a token. I'll improve this one day.
-    *REVOKED_TOKENS.write().unwrap() = read_revoked_tokens()?;
-    generate_default_website()?;
+fn liftoff(sites: &Sites) -> Result<(), Error> {
+    for site in sites.all() {
+        site.workspace.create_tmp_dir()?;
+        site.workspace.clone_repository()?;
+    }
+    for site in sites.all() {
+        // NOTE: This is just a hotfix. I had to quickly revoke a token. I'll improve this one day.
+        site.read_revoked_tokens()?;
+        site.read_data_files()?;
+        update_auth_config(site)?;
+        site.workspace.generate_default_website()?;
+    }
     Ok(())
 }
 
-/// TODO: Re-enable Basic authentication
-///   (`.raw_header("WWW-Authenticate", "Basic realm=\"This page is protected. Please log in.\"")`).
 fn not_found() -> Result<Response<ServeFileSystemResponseBody>, Response> {
     fn fallback() -> Response {
         let mut fallback =
@@ -128,10 +180,12 @@ fn not_found() -> Result<Response<ServeFileSystemResponseBody>, Response> {
     }
 
     let website_id = WebsiteId::default();
-    let website_dir = generate_website_if_needed(&website_id).map_err(|err| {
-        error(format!("Could not get default website directory: {err}"));
-        fallback()
-    })?;
+    let website_dir = (sites::current().workspace)
+        .generate_website_if_needed(&website_id)
+        .map_err(|err| {
+            error(format!("Could not get default website directory: {err}"));
+            fallback()
+        })?;
 
     let file_path = website_dir.join(NOT_FOUND_FILE);
     match fs::metadata(&file_path) {
@@ -169,6 +223,22 @@ enum Error {
     Forbidden,
     #[error("Token revoked")]
     TokenRevoked,
+    #[error("Refresh token already used")]
+    RefreshTokenAlreadyUsed,
+    #[error("Could not read or save refresh token redemptions: {0}")]
+    RedemptionsError(#[from] redemptions::Error),
+    #[cfg(feature = "token-generator")]
+    #[error("Could not read or save issued links: {0}")]
+    IssuedLinksError(#[from] issued_links::Error),
+    #[cfg(feature = "request-access")]
+    #[error("Could not read or save access requests: {0}")]
+    AccessRequestsError(#[from] access_requests::Error),
+    #[cfg(feature = "comments")]
+    #[error("Could not read or save comments: {0}")]
+    CommentsError(#[from] comments::Error),
+    #[cfg(feature = "oidc")]
+    #[error("OpenID Connect error: {0}")]
+    OidcError(#[from] oidc::Error),
     #[cfg(feature = "templating")]
     #[error("Templating error: {0}")]
     TemplatingError(#[from] templating::Error),
@@ -178,12 +248,33 @@ enum Error {
     ClientError(String),
 }
 
+impl Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::Forbidden | Self::TokenRevoked => StatusCode::FORBIDDEN,
+            Self::RefreshTokenAlreadyUsed => StatusCode::GONE,
+            Self::ClientError(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
-        match self {
+        let mut response = match self {
             Self::Unauthorized => {
                 warn!("{self}");
-                (StatusCode::UNAUTHORIZED, not_found()).into_response()
+                #[allow(unused_mut)]
+                let mut response = (StatusCode::UNAUTHORIZED, not_found()).into_response();
+                #[cfg(feature = "basic-auth")]
+                if sites::current().state.basic_auth.is_enabled() {
+                    response.headers_mut().insert(
+                        axum::http::header::WWW_AUTHENTICATE,
+                        axum::http::HeaderValue::from_static(basic_auth::CHALLENGE),
+                    );
+                }
+                response
             },
             Self::Forbidden => {
                 warn!("{self}");
@@ -193,6 +284,14 @@ impl IntoResponse for Error {
                 warn!("{self}");
                 (StatusCode::FORBIDDEN, "403 Forbidden. Token revoked.").into_response()
             },
+            Self::RefreshTokenAlreadyUsed => {
+                warn!("{self}");
+                (
+                    StatusCode::GONE,
+                    Html(include_str!("routes/templates/refresh-token-used.html")),
+                )
+                    .into_response()
+            },
             Self::ClientError(_) => {
                 debug!("{self}");
                 (StatusCode::BAD_REQUEST, not_found()).into_response()
@@ -201,7 +300,11 @@ impl IntoResponse for Error {
                 error(format!("{self}"));
                 (StatusCode::INTERNAL_SERVER_ERROR, "I messed up. My bad 🙊").into_response()
             },
-        }
+        };
+        // NOTE: Error pages depend on the token (e.g. forbidden pages disguised
+        //   as "not found"), so caches must not serve them to other users.
+        caching::set_headers(&mut response, caching::CachePolicy::Private, None);
+        response
     }
 }
 
@@ -21,7 +21,7 @@ pub async fn request_id_middleware(
     tracing::Span::current().record("request_id", request_id);
 
     // Insert into request extensions so handlers can access it
-    req.extensions_mut().insert(request_id.clone());
+    req.extensions_mut().insert(request_id);
 
     // Continue down the stack
     let mut response = next.run(req).await;
@@ -49,3 +49,77 @@ pub async fn tracing_middleware(
 
     next.run(req).await
 }
+
+/// Sets a `Referrer-Policy` header if none was set, so URLs (which might contain
+/// tokens, see [`handle_query_token`]) don't leak to other websites.
+///
+/// [`handle_query_token`]: crate::request_guards::handle_query_token
+pub async fn referrer_policy_middleware(
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    use axum::http::{header::REFERRER_POLICY, HeaderValue};
+
+    let mut response = next.run(req).await;
+    (response.headers_mut())
+        .entry(REFERRER_POLICY)
+        .or_insert(HeaderValue::from_static("same-origin"));
+    response
+}
+
+/// Replaces Orangutan v2 Biscuit token cookies by JWT cookies (see design v3).
+///
+/// Biscuits which cannot be converted without losing attenuations are kept as is.
+#[cfg(feature = "migrate-biscuits")]
+pub async fn migrate_biscuit_cookie(
+    cookies: axum_extra::extract::CookieJar,
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    use axum::{http::header::SET_COOKIE, response::IntoResponse as _};
+    use biscuit_auth::Biscuit;
+    use tracing::{debug, trace};
+
+    use crate::{
+        config::TOKEN_COOKIE,
+        jwt, sites,
+        util::{add_cookie, add_padding, error},
+    };
+
+    let biscuit = (cookies.get(&TOKEN_COOKIE.name))
+        .filter(|cookie| !jwt::is_jwt(cookie.value()))
+        .and_then(|cookie| {
+            Biscuit::from_base64(
+                add_padding(cookie.value()),
+                sites::current().root_key.public(),
+            )
+            .ok()
+        })
+        .filter(|biscuit| jwt::claims_from_biscuit(biscuit).is_some());
+
+    let response = next.run(req).await;
+    let Some(biscuit) = biscuit else {
+        return response;
+    };
+
+    // NOTE: Don't override a token set while handling the request
+    //   (e.g. after using a refresh token).
+    let sets_token = (response.headers().get_all(SET_COOKIE).iter())
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.starts_with(&format!("{}=", TOKEN_COOKIE.name)));
+    if sets_token {
+        trace!("Token cookie already set, not migrating it.");
+        return response;
+    }
+
+    match add_cookie(&biscuit, cookies) {
+        Ok(cookies) => {
+            debug!("Migrated Biscuit token cookie to a JWT");
+            (cookies, response).into_response()
+        },
+        Err(err) => {
+            error(format!("Could not migrate token cookie: {err}"));
+            response
+        },
+    }
+}
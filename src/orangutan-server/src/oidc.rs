@@ -0,0 +1,566 @@
+//! OpenID Connect login (authorization code flow with PKCE).
+//!
+//! ID token claims are mapped to profiles using rules from each site's
+//! repository (see [`Workspace::read_oidc_rules`]). The identity provider is configured
+//! with environment variables:
+//!
+//! - `OIDC_ISSUER_URL` (required to enable OpenID Connect login)
+//! - `OIDC_CLIENT_ID` (required)
+//! - `OIDC_CLIENT_SECRET` (optional, for confidential clients)
+//! - `OIDC_SCOPES`: space-separated, in addition to `openid` (defaults to `email`)
+//!
+//! The redirect URL to register in the identity provider is
+//! `<WEBSITE_ROOT>/_login/oidc/callback`.
+//!
+//! [`Workspace::read_oidc_rules`]: orangutan_helpers::workspace::Workspace::read_oidc_rules
+
+use std::{
+    collections::HashMap,
+    env,
+    sync::RwLock,
+    time::{Duration, Instant, SystemTime},
+};
+
+use lazy_static::lazy_static;
+use openidconnect::{
+    core::{
+        CoreAuthDisplay, CoreAuthPrompt, CoreErrorResponseType, CoreGenderClaim, CoreJsonWebKey,
+        CoreJweContentEncryptionAlgorithm, CoreJwsSigningAlgorithm, CoreProviderMetadata,
+        CoreResponseType, CoreRevocableToken, CoreRevocationErrorResponse,
+        CoreTokenIntrospectionResponse, CoreTokenType,
+    },
+    reqwest, AdditionalClaims, AuthenticationFlow, AuthorizationCode, Client, ClientId,
+    ClientSecret, CsrfToken, EmptyExtraTokenFields, EndpointMaybeSet, EndpointNotSet, EndpointSet,
+    IdTokenClaims, IdTokenFields, IssuerUrl, Nonce, PkceCodeChallenge, PkceCodeVerifier,
+    RedirectUrl, Scope, StandardErrorResponse, StandardTokenResponse, TokenResponse as _,
+};
+use orangutan_helpers::auth_config::OidcRule;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, trace};
+
+use crate::util::error;
+
+/// Path of the redirect URL, relative to the website root.
+pub const CALLBACK_PATH: &str = "/_login/oidc/callback";
+/// How long users have to log in with the identity provider.
+pub const PENDING_LOGIN_TTL: Duration = Duration::from_secs(10 * 60);
+/// Maximum number of pending logins, so anonymous requests can't fill the memory.
+const MAX_PENDING_LOGINS: usize = 1000;
+/// How long discovered provider metadata (including signing keys) is reused.
+const PROVIDER_METADATA_TTL: Duration = Duration::from_secs(60 * 60);
+
+lazy_static! {
+    pub static ref OIDC_CONFIG: Option<OidcConfig> = match OidcConfig::from_env() {
+        Ok(config) => config,
+        Err(err) => {
+            error(format!(
+                "Invalid OpenID Connect configuration, OpenID Connect login disabled: {err}"
+            ));
+            None
+        },
+    };
+}
+
+/// Non-standard claims we read from ID tokens.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GroupsClaims {
+    #[serde(default)]
+    groups: Vec<String>,
+}
+
+impl AdditionalClaims for GroupsClaims {}
+
+type OidcTokenResponse = StandardTokenResponse<
+    IdTokenFields<
+        GroupsClaims,
+        EmptyExtraTokenFields,
+        CoreGenderClaim,
+        CoreJweContentEncryptionAlgorithm,
+        CoreJwsSigningAlgorithm,
+    >,
+    CoreTokenType,
+>;
+
+/// Same as [`openidconnect::core::CoreClient`], with [`GroupsClaims`].
+type OidcClient = Client<
+    GroupsClaims,
+    CoreAuthDisplay,
+    CoreGenderClaim,
+    CoreJweContentEncryptionAlgorithm,
+    CoreJsonWebKey,
+    CoreAuthPrompt,
+    StandardErrorResponse<CoreErrorResponseType>,
+    OidcTokenResponse,
+    CoreTokenIntrospectionResponse,
+    CoreRevocableToken,
+    CoreRevocationErrorResponse,
+    EndpointSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointMaybeSet,
+    EndpointMaybeSet,
+>;
+
+#[derive(Debug)]
+struct PendingLogin {
+    pkce_verifier: PkceCodeVerifier,
+    nonce: Nonce,
+    /// Where to redirect the user after they logged in.
+    path: String,
+    expires_at: SystemTime,
+}
+
+#[derive(Debug)]
+struct CachedProviderMetadata {
+    issuer_url: IssuerUrl,
+    metadata: CoreProviderMetadata,
+    fetched_at: Instant,
+}
+
+/// OpenID Connect logins of a site.
+///
+/// NOTE: Logins are short-lived, there is no need to persist them.
+#[derive(Debug, Default)]
+pub struct OidcLogins {
+    /// Logins started but not finished yet, by CSRF state.
+    pending: RwLock<HashMap<String, PendingLogin>>,
+    /// NOTE: Discovery makes HTTP requests, we don't want to do it on every login.
+    provider_metadata: RwLock<Option<CachedProviderMetadata>>,
+}
+
+/// OpenID Connect rules of a site.
+#[derive(Debug, Default)]
+pub struct OidcRules(RwLock<Vec<OidcRule>>);
+
+impl OidcRules {
+    /// Replaces known rules (e.g. after the website repository was updated).
+    pub fn set(
+        &self,
+        rules: Vec<OidcRule>,
+    ) {
+        *self.0.write().unwrap() = rules;
+    }
+
+    fn profiles(
+        &self,
+        claims: &IdTokenClaims<GroupsClaims, CoreGenderClaim>,
+    ) -> Vec<String> {
+        let mut values: Vec<(&str, &str)> = vec![("sub", claims.subject().as_str())];
+        // NOTE: Providers which let users change their email address
+        //   without verifying it would allow anyone to get a listed address,
+        //   so addresses not explicitly verified are ignored.
+        if let Some(email) = claims
+            .email()
+            .filter(|_| claims.email_verified() == Some(true))
+        {
+            values.push(("email", email.as_str()));
+        }
+        for group in claims.additional_claims().groups.iter() {
+            values.push(("groups", group.as_str()));
+        }
+
+        let mut profiles: Vec<String> = Vec::new();
+        for rule in self.0.read().unwrap().iter() {
+            let matches =
+                (values.iter()).any(|(claim, value)| rule.claim == *claim && rule.matches(value));
+            if matches {
+                for profile in rule.profiles.iter() {
+                    if !profiles.contains(profile) {
+                        profiles.push(profile.to_owned());
+                    }
+                }
+            }
+        }
+        profiles
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    pub issuer_url: IssuerUrl,
+    pub client_id: ClientId,
+    pub client_secret: Option<ClientSecret>,
+    pub scopes: Vec<Scope>,
+}
+
+impl OidcConfig {
+    /// Returns `None` if `OIDC_ISSUER_URL` is not set.
+    pub fn from_env() -> Result<Option<Self>, Error> {
+        let Ok(issuer_url) = env::var("OIDC_ISSUER_URL") else {
+            info!("OIDC_ISSUER_URL not set, OpenID Connect login disabled.");
+            return Ok(None);
+        };
+        let client_id = env::var("OIDC_CLIENT_ID").map_err(|_| Error::MissingClientId)?;
+        let scopes = env::var("OIDC_SCOPES").unwrap_or("email".to_owned());
+
+        Ok(Some(Self {
+            issuer_url: IssuerUrl::new(issuer_url)?,
+            client_id: ClientId::new(client_id),
+            client_secret: env::var("OIDC_CLIENT_SECRET").ok().map(ClientSecret::new),
+            scopes: (scopes.split_whitespace())
+                .map(|scope| Scope::new(scope.to_owned()))
+                .collect(),
+        }))
+    }
+
+    async fn client(
+        &self,
+        logins: &OidcLogins,
+        website_root: &str,
+        http_client: &reqwest::Client,
+    ) -> Result<OidcClient, Error> {
+        let provider_metadata = self.provider_metadata(logins, http_client).await?;
+        let redirect_url = format!("{}{CALLBACK_PATH}", website_root.trim_end_matches('/'));
+
+        Ok(OidcClient::from_provider_metadata(
+            provider_metadata,
+            self.client_id.clone(),
+            self.client_secret.clone(),
+        )
+        .set_redirect_uri(RedirectUrl::new(redirect_url)?))
+    }
+
+    async fn provider_metadata(
+        &self,
+        logins: &OidcLogins,
+        http_client: &reqwest::Client,
+    ) -> Result<CoreProviderMetadata, Error> {
+        if let Some(cached) = logins.provider_metadata.read().unwrap().as_ref() {
+            if cached.issuer_url == self.issuer_url
+                && cached.fetched_at.elapsed() < PROVIDER_METADATA_TTL
+            {
+                return Ok(cached.metadata.clone());
+            }
+        }
+
+        trace!("Discovering OpenID Connect provider…");
+        let metadata = CoreProviderMetadata::discover_async(self.issuer_url.clone(), http_client)
+            .await
+            .map_err(|err| Error::DiscoveryError(format!("{err}")))?;
+        *logins.provider_metadata.write().unwrap() = Some(CachedProviderMetadata {
+            issuer_url: self.issuer_url.clone(),
+            metadata: metadata.clone(),
+            fetched_at: Instant::now(),
+        });
+        Ok(metadata)
+    }
+}
+
+/// Forgets discovered provider metadata (e.g. when the provider
+/// might have rotated its signing keys).
+fn forget_provider_metadata(logins: &OidcLogins) {
+    *logins.provider_metadata.write().unwrap() = None;
+}
+
+fn http_client() -> Result<reqwest::Client, Error> {
+    reqwest::ClientBuilder::new()
+        // NOTE: Following redirects opens the client up to SSRF vulnerabilities.
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .map_err(Error::HttpClientError)
+}
+
+/// Returns the identity provider URL to redirect the user to,
+/// and the CSRF state to remember in their browser.
+pub async fn start_login(
+    config: &OidcConfig,
+    logins: &OidcLogins,
+    website_root: &str,
+    path: String,
+) -> Result<(String, String), Error> {
+    let client = config.client(logins, website_root, &http_client()?).await?;
+    let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+    let (url, csrf_state, nonce) = client
+        .authorize_url(
+            AuthenticationFlow::<CoreResponseType>::AuthorizationCode,
+            CsrfToken::new_random,
+            Nonce::new_random,
+        )
+        .add_scopes(config.scopes.clone())
+        .set_pkce_challenge(pkce_challenge)
+        .url();
+
+    let mut pending_logins = logins.pending.write().unwrap();
+    // Remove expired logins
+    let now = SystemTime::now();
+    pending_logins.retain(|_, login| login.expires_at > now);
+    // Evict the oldest login if there are too many
+    if pending_logins.len() >= MAX_PENDING_LOGINS {
+        let oldest = (pending_logins.iter())
+            .min_by_key(|(_, login)| login.expires_at)
+            .map(|(state, _)| state.to_owned());
+        if let Some(oldest) = oldest {
+            debug!("Too many pending OpenID Connect logins, evicting the oldest one");
+            pending_logins.remove(&oldest);
+        }
+    }
+    pending_logins.insert(csrf_state.secret().to_owned(), PendingLogin {
+        pkce_verifier,
+        nonce,
+        path,
+        expires_at: now + PENDING_LOGIN_TTL,
+    });
+
+    Ok((url.to_string(), csrf_state.secret().to_owned()))
+}
+
+/// Exchanges `code` for an ID token and returns the profiles it gives,
+/// along with where to redirect the user.
+///
+/// `browser_state` is the state remembered in the user's browser when the
+/// login started, so nobody can make someone else finish their login.
+pub async fn finish_login(
+    config: &OidcConfig,
+    rules: &OidcRules,
+    logins: &OidcLogins,
+    website_root: &str,
+    code: String,
+    state: &str,
+    browser_state: Option<&str>,
+) -> Result<(Vec<String>, String), Error> {
+    if browser_state != Some(state) {
+        return Err(Error::StateMismatch);
+    }
+
+    // NOTE: Logins are single-use, remove it even if it has expired.
+    let pending_login = logins.pending.write().unwrap().remove(state);
+    let Some(pending_login) = pending_login.filter(|l| l.expires_at > SystemTime::now()) else {
+        return Err(Error::UnknownState);
+    };
+
+    let http_client = http_client()?;
+    let client = config.client(logins, website_root, &http_client).await?;
+    let token_response = client
+        .exchange_code(AuthorizationCode::new(code))?
+        .set_pkce_verifier(pending_login.pkce_verifier)
+        .request_async(&http_client)
+        .await
+        .map_err(|err| Error::TokenExchangeError(format!("{err}")))?;
+
+    let id_token = token_response.id_token().ok_or(Error::MissingIdToken)?;
+    let claims = id_token
+        .claims(&client.id_token_verifier(), &pending_login.nonce)
+        .map_err(|err| {
+            forget_provider_metadata(logins);
+            Error::InvalidIdToken(format!("{err}"))
+        })?;
+    debug!("OpenID Connect login for '{}'", claims.subject().as_str());
+
+    Ok((rules.profiles(claims), pending_login.path))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("OIDC_CLIENT_ID is required")]
+    MissingClientId,
+    #[error("Invalid URL: {0}")]
+    InvalidUrl(#[from] openidconnect::url::ParseError),
+    #[error("Could not create HTTP client: {0}")]
+    HttpClientError(reqwest::Error),
+    #[error("Could not discover OpenID Connect provider: {0}")]
+    DiscoveryError(String),
+    #[error("OpenID Connect provider misconfigured: {0}")]
+    ConfigurationError(#[from] openidconnect::ConfigurationError),
+    #[error("Unknown or expired login state")]
+    UnknownState,
+    #[error("Login state does not match the one remembered by the browser")]
+    StateMismatch,
+    #[error("Could not exchange authorization code: {0}")]
+    TokenExchangeError(String),
+    #[error("No ID token returned by the OpenID Connect provider")]
+    MissingIdToken,
+    #[error("Invalid ID token: {0}")]
+    InvalidIdToken(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use axum::{
+        extract::State,
+        routing::{get, post},
+        Json, Router,
+    };
+    use base64::{prelude::BASE64_URL_SAFE_NO_PAD, Engine as _};
+    use hmac::{Hmac, Mac as _};
+    use openidconnect::{ClientId, ClientSecret, IssuerUrl, Scope};
+    use orangutan_helpers::auth_config::OidcRule;
+    use serde_json::{json, Value};
+    use sha2::Sha256;
+    use tokio::net::TcpListener;
+
+    use super::{finish_login, start_login, Error, OidcConfig, OidcLogins, OidcRules};
+
+    const CLIENT_ID: &str = "orangutan";
+    const CLIENT_SECRET: &str = "a-client-secret-long-enough-for-hs256";
+
+    /// Signs `claims` with the client secret (HS256), so we don't need keys.
+    fn id_token(claims: Value) -> String {
+        let header = BASE64_URL_SAFE_NO_PAD.encode(r#"{"alg":"HS256","typ":"JWT"}"#);
+        let payload = BASE64_URL_SAFE_NO_PAD.encode(claims.to_string());
+        let mut mac = Hmac::<Sha256>::new_from_slice(CLIENT_SECRET.as_bytes()).unwrap();
+        mac.update(format!("{header}.{payload}").as_bytes());
+        let signature = BASE64_URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+        format!("{header}.{payload}.{signature}")
+    }
+
+    /// Minimal OpenID Connect provider, returning its issuer URL.
+    ///
+    /// The nonce the ID token must contain is set after the login started.
+    async fn mock_oidc_provider(nonce: tokio::sync::watch::Receiver<String>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+
+        let discovery = json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{issuer}/authorize"),
+            "token_endpoint": format!("{issuer}/token"),
+            "jwks_uri": format!("{issuer}/jwks"),
+            "response_types_supported": ["code"],
+            "subject_types_supported": ["public"],
+            "id_token_signing_alg_values_supported": ["HS256"],
+        });
+        let token = |State((issuer, nonce)): State<(
+            String,
+            tokio::sync::watch::Receiver<String>,
+        )>| async move {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            Json(json!({
+                "access_token": "access-token",
+                "token_type": "Bearer",
+                "id_token": id_token(json!({
+                    "iss": issuer,
+                    "aud": CLIENT_ID,
+                    "sub": "alice",
+                    "iat": now,
+                    "exp": now + 60,
+                    "nonce": *nonce.borrow(),
+                    "email": "alice@example.org",
+                    "email_verified": true,
+                    "groups": ["family"],
+                })),
+            }))
+        };
+        let app = Router::new()
+            .route(
+                "/.well-known/openid-configuration",
+                get(move || async move { Json(discovery) }),
+            )
+            .route("/jwks", get(|| async { Json(json!({ "keys": [] })) }))
+            .route("/token", post(token))
+            .with_state((issuer.clone(), nonce));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        issuer
+    }
+
+    #[tokio::test]
+    async fn test_login_with_mock_provider() {
+        let (nonce_tx, nonce_rx) = tokio::sync::watch::channel(String::new());
+        let issuer = mock_oidc_provider(nonce_rx).await;
+        let rules = OidcRules::default();
+        rules.set(vec![
+            OidcRule {
+                claim: "email".to_owned(),
+                value: "*@example.org".to_owned(),
+                profiles: vec!["amis".to_owned()],
+            },
+            OidcRule {
+                claim: "groups".to_owned(),
+                value: "family".to_owned(),
+                profiles: vec!["famille".to_owned(), "amis".to_owned()],
+            },
+            OidcRule {
+                claim: "groups".to_owned(),
+                value: "work".to_owned(),
+                profiles: vec!["travail".to_owned()],
+            },
+        ]);
+        let config = OidcConfig {
+            issuer_url: IssuerUrl::new(issuer).unwrap(),
+            client_id: ClientId::new(CLIENT_ID.to_owned()),
+            client_secret: Some(ClientSecret::new(CLIENT_SECRET.to_owned())),
+            scopes: vec![Scope::new("email".to_owned())],
+        };
+        let website_root = "https://example.org";
+
+        let logins = OidcLogins::default();
+        let (url, state) = start_login(&config, &logins, website_root, "/blog/".to_owned())
+            .await
+            .unwrap();
+        let url = openidconnect::url::Url::parse(&url).unwrap();
+        let query = |name: &str| {
+            (url.query_pairs())
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+                .unwrap()
+        };
+        assert!(url.as_str().contains("code_challenge_method=S256"), "{url}");
+        assert_eq!(
+            query("redirect_uri"),
+            "https://example.org/_login/oidc/callback"
+        );
+        nonce_tx.send(query("nonce")).unwrap();
+        assert_eq!(query("state"), state);
+
+        // Logins must be finished in the browser which started them.
+        assert!(matches!(
+            finish_login(
+                &config,
+                &rules,
+                &logins,
+                website_root,
+                "code".to_owned(),
+                &state,
+                None
+            )
+            .await,
+            Err(Error::StateMismatch)
+        ));
+        assert!(matches!(
+            finish_login(
+                &config,
+                &rules,
+                &logins,
+                website_root,
+                "code".to_owned(),
+                &state,
+                Some("other")
+            )
+            .await,
+            Err(Error::StateMismatch)
+        ));
+
+        let (profiles, path) = finish_login(
+            &config,
+            &rules,
+            &logins,
+            website_root,
+            "code".to_owned(),
+            &state,
+            Some(&state),
+        )
+        .await
+        .unwrap();
+        assert_eq!(profiles, vec!["amis", "famille"]);
+        assert_eq!(path, "/blog/");
+
+        // States are single-use.
+        assert!(finish_login(
+            &config,
+            &rules,
+            &logins,
+            website_root,
+            "code".to_owned(),
+            &state,
+            Some(&state)
+        )
+        .await
+        .is_err());
+    }
+}
@@ -0,0 +1,84 @@
+use std::{collections::HashMap, fs::File, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, trace};
+
+use crate::util::write_atomically;
+
+/// Number of uses per refresh token `nonce`.
+///
+/// Only refresh tokens created with a `max_uses` limit have a nonce,
+/// others are never recorded here.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Redemptions(HashMap<String, u32>);
+
+impl Redemptions {
+    pub fn read(file_path: &Path) -> Result<Self, Error> {
+        let Ok(file) = File::open(file_path) else {
+            info!(
+                "Redemptions file not found at <{}>. Considering no refresh token used.",
+                file_path.display(),
+            );
+            return Ok(Self::default());
+        };
+        let redemptions: Self = serde_json::from_reader(file)?;
+        info!(
+            "Found {} usage-limited refresh token(s).",
+            redemptions.0.len()
+        );
+        Ok(redemptions)
+    }
+
+    pub fn save(
+        &self,
+        file_path: &Path,
+    ) -> Result<(), Error> {
+        trace!("Saving redemptions to <{}>…", file_path.display());
+        let json = serde_json::to_vec(self)?;
+        write_atomically(file_path, json)?;
+        Ok(())
+    }
+
+    /// Records one use of the refresh token identified by `nonce`.
+    ///
+    /// Returns `false` (and records nothing) if the token
+    /// has already been used `max_uses` times.
+    pub fn redeem(
+        &mut self,
+        nonce: &str,
+        max_uses: u32,
+    ) -> bool {
+        let uses = self.0.entry(nonce.to_owned()).or_default();
+        if *uses >= max_uses {
+            return false;
+        }
+        *uses += 1;
+        true
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("IO error: {0}")]
+    IOError(#[from] io::Error),
+    #[error("JSON error: {0}")]
+    JsonError(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Redemptions;
+
+    #[test]
+    fn test_redeem_until_max_uses() {
+        let mut redemptions = Redemptions::default();
+
+        assert!(redemptions.redeem("a", 2));
+        assert!(redemptions.redeem("a", 2));
+        assert!(!redemptions.redeem("a", 2));
+
+        assert!(redemptions.redeem("b", 1));
+        assert!(!redemptions.redeem("b", 1));
+    }
+}
@@ -3,7 +3,6 @@ use std::{
     hash::Hash,
     ops::Deref,
     str::FromStr,
-    sync::RwLock,
     time::SystemTime,
 };
 
@@ -11,36 +10,191 @@ use axum::{
     extract::{
         rejection::QueryRejection, FromRequestParts, OptionalFromRequestParts, Query, Request,
     },
-    http::{request, HeaderMap, HeaderValue, Uri},
+    http::{header::SET_COOKIE, request, HeaderMap, HeaderName, HeaderValue, Method, Uri},
     middleware::Next,
-    response::{IntoResponse, Redirect, Response},
+    response::{AppendHeaders, IntoResponse, Redirect, Response},
 };
 use axum_extra::{either::Either, extract::CookieJar};
-use biscuit_auth::{macros::authorizer, Biscuit};
-use lazy_static::lazy_static;
+use biscuit_auth::{
+    builder::{BlockBuilder, Fact},
+    macros::{authorizer, fact},
+    Biscuit, KeyPair,
+};
+use orangutan_refresh_token::EXPIRY_BLOCK_CONTEXT;
 use serde::Deserialize;
 use tracing::{debug, trace};
 
+#[cfg(feature = "jwt")]
+use crate::jwt::{self, JwtClaims};
 use crate::{
     config::*,
-    util::{add_cookie, add_padding, profiles},
+    sites,
+    util::{
+        add_cookie, add_padding, expiry, profiles, redact_uri, uri_without_query_params,
+        usage_limit,
+    },
 };
 
-lazy_static! {
-    pub static ref REVOKED_TOKENS: RwLock<HashSet<Vec<u8>>> = RwLock::default();
-}
-
 #[derive(Debug, Clone)]
 pub struct Token {
+    /// Used for authorization, whatever the token format.
     pub biscuit: Biscuit,
+    pub format: TokenFormat,
+    /// Other tokens provided in the same request, kept when using
+    /// [`TokenMergePolicy::Union`].
+    ///
+    /// NOTE: They are authorized separately, so the checks of a token
+    ///   (e.g. expiry, attenuation to a page) never apply to another one.
+    pub others: Vec<Biscuit>,
+}
+
+#[derive(Debug, Clone)]
+pub enum TokenFormat {
+    /// Orangutan v2 token.
+    Biscuit,
+    /// Orangutan v3 token. [`Token::biscuit`] is created from its claims.
+    #[cfg(feature = "jwt")]
+    Jwt(JwtClaims),
+}
+
+impl From<Biscuit> for Token {
+    fn from(biscuit: Biscuit) -> Self {
+        Self {
+            biscuit,
+            format: TokenFormat::Biscuit,
+            others: vec![],
+        }
+    }
 }
 
 impl Token {
+    /// Profiles of all tokens provided in the request.
     pub fn profiles(&self) -> Vec<String> {
-        profiles(&self.biscuit)
+        let mut profiles: Vec<String> = self.biscuits().flat_map(profiles).collect();
+        profiles.sort();
+        profiles.dedup();
+        profiles
+    }
+
+    /// When the token expires, if known.
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        match self.format {
+            TokenFormat::Biscuit => expiry(&self.biscuit),
+            #[cfg(feature = "jwt")]
+            TokenFormat::Jwt(ref claims) => {
+                chrono::DateTime::from_timestamp(claims.exp, 0).map(SystemTime::from)
+            },
+        }
+    }
+
+    /// Hex-encoded revocation identifiers of all tokens provided in the request,
+    /// and of the tokens they were derived from (e.g. the link a session was opened with).
+    pub fn lineage(&self) -> Vec<String> {
+        self.biscuits().flat_map(lineage).collect()
+    }
+
+    /// All tokens provided in the request, to be authorized separately.
+    pub fn biscuits(&self) -> impl Iterator<Item = &Biscuit> {
+        std::iter::once(&self.biscuit).chain(self.others.iter())
     }
 }
 
+/// What to do when a request contains multiple valid tokens
+/// (e.g. a token cookie and a `token` query param), configured
+/// with the `TOKEN_MERGE_POLICY` environment variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TokenMergePolicy {
+    /// `first`: Use the first token found (cookie, then `Authorization` headers,
+    /// then query param) and ignore the others.
+    First,
+    /// `union`: Keep all tokens. A page is accessible if any of them allows it.
+    #[default]
+    Union,
+    /// `reject`: Reject the request.
+    Reject,
+}
+
+impl FromStr for TokenMergePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(Self::First),
+            "union" => Ok(Self::Union),
+            "reject" => Ok(Self::Reject),
+            s => Err(format!(
+                "Invalid token merge policy '{s}' (expected 'first', 'union' or 'reject')."
+            )),
+        }
+    }
+}
+
+impl TokenMergePolicy {
+    /// Merges tokens found in a request, in the order they were found.
+    fn merge(
+        self,
+        tokens: Vec<Token>,
+    ) -> Result<Token, TokenError> {
+        let mut tokens = tokens.into_iter();
+        let mut token = tokens.next().ok_or(TokenError::Unauthorized)?;
+        match self {
+            Self::First => {
+                if tokens.len() > 0 {
+                    debug!("Ignoring {} other token(s) (first wins)", tokens.len());
+                }
+            },
+            Self::Union => token
+                .others
+                .extend(tokens.flat_map(|t| t.biscuits().cloned().collect::<Vec<_>>())),
+            Self::Reject if tokens.len() > 0 => Err(TokenError::MultipleTokens)?,
+            Self::Reject => {},
+        }
+        Ok(token)
+    }
+}
+
+/// Returns `true` if any block of `biscuit` was revoked
+/// (e.g. when a user logged out and revoked their session),
+/// or if a token it was derived from was revoked (see [`parent_facts`]).
+pub fn is_revoked(biscuit: &Biscuit) -> bool {
+    let site = sites::current();
+    let revoked_tokens = site.state.revoked_tokens.read().unwrap();
+    (biscuit.revocation_identifiers().iter()).any(|id| revoked_tokens.contains(id))
+        || (parents(biscuit).iter())
+            .any(|id| hex::decode(id).is_ok_and(|id| revoked_tokens.contains(&id)))
+}
+
+/// Hex-encoded identifiers in the `parent($id)` facts of `biscuit`.
+pub fn parents(biscuit: &Biscuit) -> Vec<String> {
+    let Ok(source) = biscuit.print_block_source(0) else {
+        return vec![];
+    };
+    (source.lines())
+        .filter_map(|line| line.strip_prefix("parent(\"")?.split('"').next())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Hex-encoded revocation identifiers of `biscuit` and of the tokens
+/// it was derived from.
+fn lineage(biscuit: &Biscuit) -> Vec<String> {
+    (biscuit.revocation_identifiers().iter())
+        .map(hex::encode)
+        .chain(parents(biscuit))
+        .collect()
+}
+
+fn parent_fact(id: String) -> Fact {
+    fact!("parent({id});")
+}
+
+/// `parent($id)` facts to add to the authority block of a token created
+/// from `token` (e.g. a share link), so revoking `token` revokes it too.
+#[cfg(feature = "share-link")]
+pub fn parent_facts(token: &Token) -> Vec<Fact> {
+    token.lineage().into_iter().map(parent_fact).collect()
+}
+
 impl Deref for Token {
     type Target = Biscuit;
 
@@ -54,29 +208,94 @@ impl Hash for Token {
         &self,
         state: &mut H,
     ) {
-        let signature = self.biscuit.container().authority.signature;
-        signature.to_bytes().hash(state)
+        match self.format {
+            TokenFormat::Biscuit => {
+                let signature = self.biscuit.container().authority.signature;
+                signature.to_bytes().hash(state)
+            },
+            #[cfg(feature = "jwt")]
+            TokenFormat::Jwt(ref claims) => claims.jti.hash(state),
+        }
+        for biscuit in self.others.iter() {
+            biscuit
+                .container()
+                .authority
+                .signature
+                .to_bytes()
+                .hash(state)
+        }
     }
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum TokenError {
-    // TODO: Re-enable Basic authentication
-    // Invalid,
     #[error("Invalid query: {0}")]
     InvalidQuery(#[from] QueryRejection),
     #[error("Unauthorized")]
     Unauthorized,
+    #[error("Multiple tokens provided, use only one.")]
+    MultipleTokens,
 }
 impl IntoResponse for TokenError {
     fn into_response(self) -> Response {
         match self {
             Self::InvalidQuery(err) => err.into_response(),
             Self::Unauthorized => crate::Error::Unauthorized.into_response(),
+            Self::MultipleTokens => crate::Error::ClientError(self.to_string()).into_response(),
         }
     }
 }
 
+/// Decodes a token (JWT or Biscuit) and checks it has not expired
+/// nor been revoked.
+///
+/// NOTE: Expired Biscuits would fail authorization anyway, but their profiles
+///   must not be used elsewhere (e.g. to choose the generated website).
+pub fn verify_token(
+    token: &str,
+    token_source: &str,
+) -> Option<Token> {
+    #[cfg(feature = "jwt")]
+    if jwt::is_jwt(token) {
+        let claims = (jwt::decode(token))
+            .inspect_err(|err| debug!("Invalid JWT from {token_source}: {err}"))
+            .ok()?;
+        let biscuit = (claims.to_biscuit())
+            .inspect_err(|err| debug!("Error creating biscuit from JWT: {err}"))
+            .ok()?;
+        trace!("Found JWT in {token_source}");
+        return Some(Token {
+            biscuit,
+            format: TokenFormat::Jwt(claims),
+            others: vec![],
+        });
+    }
+
+    // Because tokens can be passed as URL query params,
+    // they might have the "=" padding characters removed.
+    // We need to add them back.
+    let token = add_padding(token);
+    // NOTE: Tokens are only valid for the site whose root key signed them.
+    match Biscuit::from_base64(token, sites::current().root_key.public()) {
+        Ok(biscuit) if is_revoked(&biscuit) => {
+            debug!("Ignoring revoked biscuit from {token_source}");
+            None
+        },
+        Ok(biscuit) if expiry(&biscuit).is_some_and(|at| at <= SystemTime::now()) => {
+            debug!("Ignoring expired biscuit from {token_source}");
+            None
+        },
+        Ok(biscuit) => {
+            trace!("Found biscuit in {token_source}");
+            Some(Token::from(biscuit))
+        },
+        Err(err) => {
+            debug!("Error decoding biscuit from {token_source}: {err}");
+            None
+        },
+    }
+}
+
 impl<S> FromRequestParts<S> for Token
 where
     S: Send + Sync,
@@ -87,60 +306,33 @@ where
         parts: &mut request::Parts,
         state: &S,
     ) -> Result<Self, Self::Rejection> {
-        // if let Some(user_agent) = parts.headers.get(USER_AGENT) {
-        //     Ok(ExtractUserAgent(user_agent.clone()))
-        // } else {
-        //     Err((StatusCode::BAD_REQUEST, "`User-Agent` header is missing"))
-        // }
-        let mut biscuit: Option<Biscuit> = None;
-
-        fn process_token(
-            token: &str,
-            token_source: &str,
-            biscuit: &mut Option<Biscuit>,
-        ) {
-            // Because tokens can be passed as URL query params,
-            // they might have the "=" padding characters removed.
-            // We need to add them back.
-            let token = add_padding(token);
-            match (
-                biscuit.clone(),
-                Biscuit::from_base64(token, ROOT_KEY.public()),
-            ) {
-                (None, Ok(new_biscuit)) => {
-                    trace!("Found biscuit in {}", token_source);
-                    *biscuit = Some(new_biscuit);
-                },
-                (Some(acc), Ok(new_biscuit)) => {
-                    trace!("Making bigger biscuit from {}", token_source);
-                    if let Some(b) = merge_biscuits(&acc, &new_biscuit) {
-                        *biscuit = Some(b);
-                    }
-                },
-                (_, Err(err)) => {
-                    debug!("Error decoding biscuit from base64: {}", err);
-                },
-            }
+        // NOTE: Middlewares and handlers all extract the token,
+        //   so tokens are only verified once per request.
+        if let Some(VerifiedTokens(tokens)) = parts.extensions.get::<VerifiedTokens>() {
+            trace!("Tokens already verified for this request");
+            return TOKEN_MERGE_POLICY.merge(tokens.clone());
         }
 
+        // NOTE: Every source is verified separately (see `verify_token`),
+        //   then tokens are merged according to `TOKEN_MERGE_POLICY`.
+        let mut tokens: Vec<Token> = vec![];
+
         // Check cookies
-        let cookies = CookieJar::from_request_parts(parts, state)
-            .await
-            .map_err(|err| match err {})
-            .unwrap();
-        if let Some(cookie) = cookies.get(TOKEN_COOKIE_NAME) {
+        let Ok(cookies) = CookieJar::from_request_parts(parts, state).await;
+        if let Some(cookie) = cookies.get(&TOKEN_COOKIE.name) {
             trace!("Found token cookie");
             let token: &str = cookie.value();
-            process_token(token, "token cookie", &mut biscuit);
+            tokens.extend(verify_token(token, "token cookie"));
         } else {
             trace!("Did not find a token cookie");
         }
+        if let Some(cookie) = cookies.get(&TOKEN_COOKIE.query_token_cookie_name()) {
+            trace!("Found query token cookie");
+            tokens.extend(verify_token(cookie.value(), "query token cookie"));
+        }
 
         // Check authorization headers
-        let headers = HeaderMap::from_request_parts(parts, state)
-            .await
-            .map_err(|err| match err {})
-            .unwrap();
+        let Ok(headers) = HeaderMap::from_request_parts(parts, state).await;
         let authorization_headers: Vec<&HeaderValue> =
             headers.get_all("Authorization").into_iter().collect();
         trace!(
@@ -155,11 +347,23 @@ where
                     continue;
                 },
             };
-            if authorization.starts_with("Bearer ") {
+            if let Some(token) = authorization.strip_prefix("Bearer ") {
                 trace!("Bearer Authorization provided");
-                let token: &str = authorization.trim_start_matches("Bearer ");
-                process_token(token, "Bearer token", &mut biscuit);
+                tokens.extend(verify_token(token, "Bearer token"));
             } else if authorization.starts_with("Basic ") {
+                #[cfg(feature = "basic-auth")]
+                {
+                    trace!("Basic Authorization provided");
+                    let credentials: &str = authorization.trim_start_matches("Basic ");
+                    match (sites::current().state.basic_auth)
+                        .authenticate(credentials)
+                        .await
+                    {
+                        Some(biscuit) => tokens.push(Token::from(biscuit)),
+                        None => debug!("Invalid Basic authentication credentials"),
+                    }
+                }
+                #[cfg(not(feature = "basic-auth"))]
                 debug!("Basic authentication disabled");
             }
         }
@@ -168,13 +372,17 @@ where
         let query = Query::<HashMap<String, String>>::from_request_parts(parts, state).await?;
         if let Some(token) = query.get(TOKEN_QUERY_PARAM_NAME) {
             trace!("Found token query param");
-            process_token(&token, "token query param", &mut biscuit);
+            tokens.extend(verify_token(token, "token query param"));
         }
 
-        let biscuit = biscuit.ok_or(TokenError::Unauthorized)?;
-        Ok(Token { biscuit })
+        parts.extensions.insert(VerifiedTokens(tokens.clone()));
+        TOKEN_MERGE_POLICY.merge(tokens)
     }
 }
+
+/// Tokens found in a request, before being merged.
+#[derive(Clone)]
+struct VerifiedTokens(Vec<Token>);
 impl<S> OptionalFromRequestParts<S> for Token
 where
     S: Send + Sync,
@@ -187,11 +395,97 @@ where
     ) -> Result<Option<Self>, Self::Rejection> {
         match <Self as FromRequestParts<S>>::from_request_parts(parts, state).await {
             Ok(token) => Ok(Some(token)),
+            // NOTE: Don't silently treat the user as anonymous,
+            //   they might not understand why they lost access.
+            Err(err @ TokenError::MultipleTokens) => Err(err),
             Err(_) => Ok(None),
         }
     }
 }
 
+/// Maximum number of query token cookies remembered to remove them
+/// when logging out, so their paths fit in a cookie.
+const MAX_QUERY_TOKEN_PATHS: usize = 20;
+
+/// Paths of the query token cookies set in the browser, oldest first.
+fn query_token_paths(cookies: &CookieJar) -> Vec<String> {
+    (cookies.get(&TOKEN_COOKIE.query_token_paths_cookie_name()))
+        .and_then(|cookie| serde_urlencoded::from_str::<Vec<(String, String)>>(cookie.value()).ok())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(_, path)| path)
+        .collect()
+}
+
+/// `Set-Cookie` headers removing all query token cookies (e.g. when logging out).
+///
+/// NOTE: [`CookieJar`] only keeps one change per cookie name, and query token
+///   cookies all have the same name (they're scoped to different paths).
+///   Browsers don't send them with requests to other paths either,
+///   which is why their paths are remembered in another cookie.
+pub fn query_token_cookies_removal(
+    cookies: &CookieJar
+) -> AppendHeaders<Vec<(HeaderName, String)>> {
+    let mut removal_cookies = vec![TOKEN_COOKIE.query_token_paths_cookie(&[])];
+    removal_cookies.extend(
+        (query_token_paths(cookies).iter())
+            .map(|path| TOKEN_COOKIE.query_token_cookie(String::new(), path, None)),
+    );
+    AppendHeaders(
+        (removal_cookies.into_iter())
+            .map(|mut cookie| {
+                cookie.make_removal();
+                (SET_COOKIE, cookie.to_string())
+            })
+            .collect(),
+    )
+}
+
+/// Moves tokens passed as a query param (e.g. share links) into a cookie,
+/// then redirects to the same page without the token, so it doesn't stay
+/// in browser history, `Referer` headers or access logs.
+///
+/// NOTE: Only done for `GET` and `HEAD` requests, other requests
+///   (e.g. API calls) can't be redirected safely.
+pub async fn handle_query_token(
+    cookies: CookieJar,
+    req: Request,
+    next: Next,
+) -> Either<Response, (CookieJar, Redirect)> {
+    let query = req.uri().query().unwrap_or_default();
+    let query: HashMap<String, String> = serde_urlencoded::from_str(query).unwrap_or_default();
+    let Some(token) = query.get(TOKEN_QUERY_PARAM_NAME) else {
+        return Either::E1(next.run(req).await);
+    };
+    if !matches!(*req.method(), Method::GET | Method::HEAD) {
+        return Either::E1(next.run(req).await);
+    }
+
+    let uri = req.uri();
+    // NOTE: Invalid tokens are stripped too, they are useless anyway.
+    let cookies = match verify_token(token, "token query param") {
+        Some(verified) => {
+            let mut paths = query_token_paths(&cookies);
+            paths.retain(|path| path != uri.path());
+            paths.push(uri.path().to_owned());
+            // NOTE: Cookies of forgotten paths will only be removed when they expire.
+            paths.drain(..paths.len().saturating_sub(MAX_QUERY_TOKEN_PATHS));
+            cookies
+                .add(TOKEN_COOKIE.query_token_cookie(
+                    token.to_owned(),
+                    uri.path(),
+                    verified.expires_at(),
+                ))
+                .add(TOKEN_COOKIE.query_token_paths_cookie(&paths))
+        },
+        None => cookies,
+    };
+
+    let redirect_to = uri_without_query_params(uri, &[TOKEN_QUERY_PARAM_NAME]);
+    debug!("Redirecting to <{redirect_to}> from <{}>…", redact_uri(uri));
+    Either::E2((cookies, Redirect::to(&redirect_to)))
+}
+
 #[derive(Deserialize)]
 pub struct RefreshTokenQuery {
     #[serde(default)]
@@ -217,21 +511,20 @@ pub async fn handle_refresh_token(
         return Ok(Either::E1(next.run(req).await));
     };
 
-    // NOTE: We're sure there is a query since `refresh_token` is `Some`.
-    let query = req.uri().query().unwrap();
-    let mut query: HashMap<String, String> = serde_urlencoded::from_str(query)
-        .map_err(|err| crate::Error::ClientError(format!("Invalid query: {err}")))?;
-
     // URL-decode the string.
-    let mut refresh_token: String = urlencoding::decode(&refresh_token).unwrap().to_string();
+    let mut refresh_token: String = urlencoding::decode(&refresh_token)
+        .map_err(|err| crate::Error::ClientError(format!("Invalid refresh token: {err}")))?
+        .to_string();
 
     // Because tokens can be passed as URL query params,
     // they might have the "=" padding characters removed.
     // We need to add them back.
     refresh_token = add_padding(&refresh_token);
 
+    let site = sites::current();
+    let root_key = &site.root_key;
     let refresh_biscuit: Biscuit =
-        Biscuit::from_base64(refresh_token, ROOT_KEY.public()).map_err(|err| {
+        Biscuit::from_base64(refresh_token, root_key.public()).map_err(|err| {
             debug!("Error decoding biscuit from base64: {err}");
             crate::Error::Unauthorized
         })?;
@@ -251,7 +544,7 @@ pub async fn handle_refresh_token(
         .revocation_identifiers()
         .into_iter()
         .collect::<HashSet<Vec<u8>>>()
-        .intersection(&REVOKED_TOKENS.read().unwrap())
+        .intersection(&site.state.revoked_tokens.read().unwrap())
         .next()
         .cloned();
     if let Some(revoked_id) = revoked_id {
@@ -277,24 +570,13 @@ pub async fn handle_refresh_token(
 
     fn redirect_to_same_page_without_query_param(
         uri: &Uri,
-        query: &mut HashMap<String, String>,
         cookies: CookieJar,
-    ) -> Result<(CookieJar, Redirect), crate::Error> {
-        query.remove(REFRESH_TOKEN_QUERY_PARAM_NAME);
-        // TODO: Check if we need to URL-encode keys and values or if they are still encoded.
-        let query_segs: Vec<String> = query.iter().map(|(k, v)| format!("{k}={v}")).collect();
-        let redirect_to = if query_segs.is_empty() {
-            uri.path().to_string()
-        } else {
-            format!("{}?{}", uri.path(), query_segs.join("&"))
-        };
-        let redirect_to = Uri::from_str(&redirect_to)
-            .map_err(|err| crate::Error::InternalServerError(format!("{err}")))?;
-        debug!("Redirecting to <{redirect_to}> from <{uri}>…");
-        Ok((
-            cookies,
-            Redirect::to(redirect_to.path().to_string().as_str()),
-        ))
+    ) -> (CookieJar, Redirect) {
+        // NOTE: Fragments are never sent to the server, but browsers keep them
+        //   when following a redirect which doesn't have one.
+        let redirect_to = uri_without_query_params(uri, &[REFRESH_TOKEN_QUERY_PARAM_NAME, "force"]);
+        debug!("Redirecting to <{redirect_to}> from <{}>…", redact_uri(uri));
+        (cookies, Redirect::to(&redirect_to))
     }
 
     if let Some(token) = token {
@@ -304,44 +586,310 @@ pub async fn handle_refresh_token(
             //   access link and send it to the super admin's device, which increases the potential
             //   for such a sensitive link to be intercepted. As a safety measure, we don't do anything
             //   if a super admin uses a refresh token link.
-            return redirect_to_same_page_without_query_param(req.uri(), &mut query, cookies)
-                .map(Either::E2);
+            return Ok(Either::E2(redirect_to_same_page_without_query_param(
+                req.uri(),
+                cookies,
+            )));
         }
     }
 
     trace!("Baking new biscuit from refresh token");
-    let block_0 = refresh_biscuit.print_block_source(0).unwrap();
-    let mut builder = Biscuit::builder();
-    builder.add_code(block_0).unwrap();
-    let new_biscuit = builder.build(&ROOT_KEY).map_err(|err| {
+    let new_biscuit = bake_access_token(&refresh_biscuit, root_key).map_err(|err| {
         crate::Error::InternalServerError(format!(
-            "Error: Could not append block to biscuit: {err}"
+            "Error: Could not bake biscuit from refresh token: {err}"
         ))
     })?;
     debug!("Successfully created new biscuit from refresh token");
 
+    // Enforce usage limits (e.g. single-use links).
+    // NOTE: Only once the access token is baked, so a failure doesn't burn a use.
+    if let Some((nonce, max_uses)) = usage_limit(&refresh_biscuit) {
+        trace!("Checking if refresh token has been used too many times…");
+        // NOTE: Saving writes to disk, which must not block the runtime.
+        //   The lock is held while saving so concurrent saves can't reorder.
+        let site = site.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut redemptions = site.state.redemptions.write().unwrap();
+            if !redemptions.redeem(&nonce, max_uses) {
+                debug!("Refresh token has already been used {max_uses} time(s) ({nonce})");
+                return Err(crate::Error::RefreshTokenAlreadyUsed);
+            }
+            Ok(redemptions.save(&site.data_file(REDEMPTIONS_FILE))?)
+        })
+        .await
+        .map_err(|err| {
+            crate::Error::InternalServerError(format!("Could not record refresh token use: {err}"))
+        })??;
+    }
+
     // Save token to a HTTP Cookie
     let cookies = add_cookie(&new_biscuit, cookies)?;
 
     // Redirect to the same page without the refresh token query param
-    redirect_to_same_page_without_query_param(req.uri(), &mut query, cookies).map(Either::E2)
+    Ok(Either::E2(redirect_to_same_page_without_query_param(
+        req.uri(),
+        cookies,
+    )))
 }
 
-fn merge_biscuits(
-    b1: &Biscuit,
-    b2: &Biscuit,
-) -> Option<Biscuit> {
-    let source = b1.authorizer().unwrap().dump_code();
-    let new_code = b2.authorizer().unwrap().dump_code();
+fn is_expiry_block(context: &Option<String>) -> bool {
+    context.as_deref() == Some(EXPIRY_BLOCK_CONTEXT)
+}
 
+/// Whether `biscuit` is a refresh token which was not exchanged
+/// for an access token yet (see [`bake_access_token`]).
+pub fn is_refresh_token(biscuit: &Biscuit) -> bool {
+    biscuit.context().iter().any(is_expiry_block)
+}
+
+/// Creates an access token from a refresh token, dropping the block which
+/// limits the lifetime of the refresh token but keeping all other blocks
+/// (e.g. when a user attenuated their own token to link another device).
+///
+/// NOTE: Refresh tokens created before blocks were marked with
+///   [`EXPIRY_BLOCK_CONTEXT`] have an unmarked expiry block,
+///   so we keep only the authority block in this case.
+fn bake_access_token(
+    refresh_biscuit: &Biscuit,
+    root_key: &KeyPair,
+) -> Result<Biscuit, biscuit_auth::error::Token> {
+    let block_0 = refresh_biscuit.print_block_source(0)?;
     let mut builder = Biscuit::builder();
-    builder.add_code(source).unwrap();
-    builder.add_code(new_code).unwrap();
-    match builder.build(&ROOT_KEY) {
-        Ok(b) => Some(b),
-        Err(err) => {
-            debug!("Error: Could not append block to biscuit: {}", err);
-            None
+    builder.add_code(block_0)?;
+    // NOTE: Signing the authority block again gives it new revocation identifiers,
+    //   so we record the refresh token's to revoke the access token with it.
+    for id in (refresh_biscuit.revocation_identifiers().iter()).map(hex::encode) {
+        builder.add_fact(parent_fact(id))?;
+    }
+    let mut biscuit = builder.build(root_key)?;
+
+    let contexts = refresh_biscuit.context();
+    if !is_refresh_token(refresh_biscuit) {
+        return Ok(biscuit);
+    }
+
+    for (i, context) in contexts.into_iter().enumerate().skip(1) {
+        if is_expiry_block(&context) {
+            continue;
+        }
+        let mut block = BlockBuilder::new();
+        block.add_code(refresh_biscuit.print_block_source(i)?)?;
+        if let Some(context) = context {
+            block.set_context(context);
+        }
+        biscuit = biscuit.append(block)?;
+    }
+
+    Ok(biscuit)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use biscuit_auth::{macros::biscuit, KeyPair};
+
+    use super::{verify_token, Token, TokenError, TokenMergePolicy};
+    use crate::config::ROOT_KEY;
+
+    fn token(profile: &str) -> Token {
+        Token::from(
+            biscuit!("profile({profile});", profile = profile.to_owned())
+                .build(&KeyPair::new())
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_merge_policies() {
+        let tokens = || vec![token("amis"), token("famille")];
+
+        let first = TokenMergePolicy::First.merge(tokens()).unwrap();
+        assert_eq!(first.profiles(), vec!["amis"]);
+
+        let union = TokenMergePolicy::Union.merge(tokens()).unwrap();
+        assert_eq!(union.profiles(), vec!["amis", "famille"]);
+
+        assert!(matches!(
+            TokenMergePolicy::Reject.merge(tokens()),
+            Err(TokenError::MultipleTokens)
+        ));
+        assert!(TokenMergePolicy::Reject.merge(vec![token("amis")]).is_ok());
+
+        assert!(matches!(
+            TokenMergePolicy::Union.merge(vec![]),
+            Err(TokenError::Unauthorized)
+        ));
+    }
+
+    #[test]
+    fn test_expired_tokens_are_ignored() {
+        let token = |expires_at: SystemTime| {
+            biscuit!(r#"profile("amis"); check if time($time), $time <= {expires_at};"#)
+                .build(&ROOT_KEY)
+                .unwrap()
+                .to_base64()
+                .unwrap()
+        };
+        let hour = Duration::from_secs(60 * 60);
+
+        assert!(verify_token(&token(SystemTime::now() + hour), "test").is_some());
+        assert!(verify_token(&token(SystemTime::now() - hour), "test").is_none());
+    }
+}
+
+/// Tests for [`handle_refresh_token`], going through a router like real requests.
+#[cfg(test)]
+mod refresh_token_tests {
+    use std::time::{Duration, SystemTime};
+
+    use axum::{
+        body::Body,
+        http::{
+            header::{COOKIE, LOCATION, SET_COOKIE},
+            Request, StatusCode,
         },
+        middleware,
+        response::Response,
+        routing::get,
+        Router,
+    };
+    use biscuit_auth::{
+        macros::{biscuit, block},
+        Biscuit,
+    };
+    use orangutan_refresh_token::EXPIRY_BLOCK_CONTEXT;
+    use tower::Service as _;
+
+    use super::handle_refresh_token;
+    use crate::config::ROOT_KEY;
+
+    fn refresh_token(
+        profile: &str,
+        expires_at: SystemTime,
+    ) -> Biscuit {
+        let mut expiry = block!("check if time($time), $time <= {expires_at};");
+        expiry.set_context(EXPIRY_BLOCK_CONTEXT.to_owned());
+        biscuit!("profile({profile});", profile = profile.to_owned())
+            .build(&ROOT_KEY)
+            .and_then(|biscuit| biscuit.append(expiry))
+            .unwrap()
+    }
+
+    fn tomorrow() -> SystemTime {
+        SystemTime::now() + Duration::from_secs(24 * 60 * 60)
+    }
+
+    async fn get_page(
+        uri: &str,
+        cookie: Option<&Biscuit>,
+    ) -> Response {
+        // NOTE: Errors are rendered using the website's "not found" page.
+        let harness = crate::e2e::harness();
+        let _lock = harness.lock().await;
+
+        let mut app = Router::new()
+            .route("/{*path}", get(|| async { "Page" }))
+            .route_layer(middleware::from_fn(handle_refresh_token));
+        let mut request = Request::builder().uri(uri);
+        if let Some(biscuit) = cookie {
+            request = request.header(COOKIE, format!("token={}", biscuit.to_base64().unwrap()));
+        }
+        app.call(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    fn location(response: &Response) -> &str {
+        response.headers()[LOCATION].to_str().unwrap()
+    }
+
+    fn sets_token_cookie(response: &Response) -> bool {
+        (response.headers().get_all(SET_COOKIE).iter())
+            .any(|value| value.to_str().unwrap().starts_with("token="))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_redirect_keeps_query() {
+        let token = refresh_token("amis", tomorrow()).to_base64().unwrap();
+        let response = get_page(
+            &format!("/blog/?b=1&refresh_token={token}&a=x%20y&q=%23"),
+            None,
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(location(&response), "/blog/?b=1&a=x+y&q=%23");
+        assert!(sets_token_cookie(&response));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_expired_token() {
+        let expired = SystemTime::now() - Duration::from_secs(60);
+        let token = refresh_token("amis", expired).to_base64().unwrap();
+        let response = get_page(&format!("/blog/?refresh_token={token}"), None).await;
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(!sets_token_cookie(&response));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_revoked_token() {
+        // NOTE: Revocation identifiers are unique, revoking them doesn't affect other tests.
+        let biscuit = refresh_token("amis", tomorrow());
+        let revocation_ids = biscuit.revocation_identifiers();
+        let site = crate::sites::current();
+        (site.state.revoked_tokens.write().unwrap()).extend(revocation_ids.iter().cloned());
+        let token = biscuit.to_base64().unwrap();
+        let response = get_page(&format!("/blog/?refresh_token={token}"), None).await;
+        // Clean up before asserting, so a failure doesn't leak them.
+        (site.state.revoked_tokens.write().unwrap()).retain(|id| !revocation_ids.contains(id));
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(!sets_token_cookie(&response));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_malformed_token() {
+        let response = get_page("/blog/?refresh_token=not-a-token", None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // NOTE: Invalid UTF-8 is decoded lossily, it must not panic.
+        let response = get_page("/blog/?refresh_token=%FF%FE", None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let signed_by_someone_else = biscuit!(r#"profile("*");"#)
+            .build(&biscuit_auth::KeyPair::new())
+            .unwrap()
+            .to_base64()
+            .unwrap();
+        let response = get_page(
+            &format!("/blog/?refresh_token={signed_by_someone_else}"),
+            None,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_super_admin_force() {
+        let admin = biscuit!(r#"profile("*");"#).build(&ROOT_KEY).unwrap();
+        let token = refresh_token("amis", tomorrow()).to_base64().unwrap();
+
+        // Super admins don't lose their profile by accident…
+        let response = get_page(&format!("/blog/?refresh_token={token}"), Some(&admin)).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(location(&response), "/blog/");
+        assert!(!sets_token_cookie(&response));
+
+        // …unless they really want to.
+        let response = get_page(
+            &format!("/blog/?refresh_token={token}&force=true"),
+            Some(&admin),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(location(&response), "/blog/");
+        assert!(sets_token_cookie(&response));
     }
 }
@@ -0,0 +1,247 @@
+//! Routes allowing users to request access to a page they cannot see,
+//! and admins to approve such requests by issuing a link.
+
+use std::{path::PathBuf, sync::Arc};
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{Html, Redirect},
+    routing::{get, post},
+    Extension, Form, Router,
+};
+use orangutan_helpers::config::DEFAULT_PROFILE;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+use url::Url;
+
+use crate::{
+    access_requests::{AccessRequest, AccessRequestStatus, MAX_MESSAGE_LENGTH},
+    config::ACCESS_REQUESTS_FILE,
+    context,
+    issued_links::{issue_link, LinkRequest},
+    request_guards::Token,
+    sites::Site,
+    util::{is_local_path, templating::render},
+    AppState, Error,
+};
+
+pub(super) fn router() -> Router<AppState> {
+    Router::<AppState>::new()
+        .route("/_request-access", post(request_access))
+        .route("/_access-requests", get(access_requests))
+        .route("/_access-requests/approve", post(approve))
+        .route("/_access-requests/reject", post(reject))
+}
+
+pub(super) fn templates() -> Vec<(&'static str, &'static str)> {
+    vec![(
+        "access-requests.html",
+        include_str!("templates/access-requests.html.tera"),
+    )]
+}
+
+#[derive(Deserialize)]
+struct RequestAccessForm {
+    path: String,
+    name: String,
+    #[serde(default)]
+    message: String,
+}
+
+async fn request_access(
+    token: Token,
+    State(app_state): State<AppState>,
+    Extension(site): Extension<Arc<Site>>,
+    Form(form): Form<RequestAccessForm>,
+) -> Result<(StatusCode, Html<String>), Error> {
+    let name = form.name.trim();
+    if name.is_empty() {
+        Err(Error::ClientError("A name is required.".to_owned()))?
+    }
+    if form.message.chars().count() > MAX_MESSAGE_LENGTH {
+        Err(Error::ClientError(format!(
+            "Messages cannot be longer than {MAX_MESSAGE_LENGTH} characters."
+        )))?
+    }
+    // NOTE: The path ends up in a link sent by an admin,
+    //   it must not be able to point to another website.
+    let is_page = is_local_path(&form.path)
+        && (site.workspace.page_metadata(&PathBuf::from(&form.path)))
+            .map_err(orangutan_helpers::generate::Error::CannotReadPageMetadata)?
+            .is_some();
+    if !is_page {
+        Err(Error::ClientError(format!(
+            "<{}> is not a page.",
+            form.path
+        )))?
+    }
+
+    let request = AccessRequest::new(
+        name.to_owned(),
+        token.profiles(),
+        form.path.to_owned(),
+        form.message.trim().to_owned(),
+    );
+    let mut access_requests = site.state.access_requests.write().unwrap();
+    if !access_requests.add(request) {
+        Err(Error::ClientError(
+            "Too many pending access requests.".to_owned(),
+        ))?
+    }
+    access_requests.save(&site.data_file(ACCESS_REQUESTS_FILE))?;
+    debug!("New access request for <{}>", form.path);
+
+    let html = render(&app_state.tera, "forbidden.html", context! {
+        page_title: "Access requested",
+        path: form.path,
+        request_access: true,
+        requested: true,
+    })?;
+
+    Ok((StatusCode::ACCEPTED, Html(html)))
+}
+
+#[derive(Serialize)]
+struct AccessRequestRow {
+    #[serde(flatten)]
+    request: AccessRequest,
+    /// Profiles allowed to read the page, to pre-fill the approval form.
+    suggested_profiles: Vec<String>,
+}
+
+fn access_requests_page_(
+    app_state: &AppState,
+    site: &Site,
+    link: Option<String>,
+) -> Result<Html<String>, Error> {
+    let mut requests: Vec<AccessRequestRow> =
+        (site.state.access_requests.read().unwrap().all().iter())
+            .map(|request| AccessRequestRow {
+                suggested_profiles: (site.workspace)
+                    .page_metadata(&PathBuf::from(&request.path))
+                    .ok()
+                    .flatten()
+                    .map(|page| {
+                        (page.read_allowed.into_iter())
+                            .filter(|p| p != DEFAULT_PROFILE)
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                request: request.clone(),
+            })
+            .collect();
+    // Most recent first
+    requests.reverse();
+
+    let html = render(
+        &app_state.tera,
+        "access-requests.html",
+        context! { page_title: "Access requests", requests, link },
+    )?;
+
+    Ok(Html(html))
+}
+
+async fn access_requests(
+    token: Token,
+    State(app_state): State<AppState>,
+    Extension(site): Extension<Arc<Site>>,
+) -> Result<Html<String>, Error> {
+    if !token.profiles().contains(&"*".to_owned()) {
+        Err(Error::Unauthorized)?
+    }
+
+    access_requests_page_(&app_state, &site, None)
+}
+
+#[derive(Deserialize)]
+struct ApproveForm {
+    id: String,
+    /// Comma separated.
+    profiles: String,
+    ttl: String,
+}
+
+/// Issues a link giving access to the requested page, for the admin to send.
+async fn approve(
+    token: Token,
+    State(app_state): State<AppState>,
+    Extension(site): Extension<Arc<Site>>,
+    Form(form): Form<ApproveForm>,
+) -> Result<Html<String>, Error> {
+    if !token.profiles().contains(&"*".to_owned()) {
+        Err(Error::Unauthorized)?
+    }
+
+    let link = {
+        let mut access_requests = site.state.access_requests.write().unwrap();
+        let request = (access_requests.get_mut(&form.id)).ok_or(Error::ClientError(format!(
+            "Unknown request '{}'.",
+            form.id
+        )))?;
+
+        // NOTE: Paths are checked when requests are made, but requests
+        //   could have been saved by a version which didn't check them.
+        if !is_local_path(&request.path) {
+            Err(Error::ClientError(format!(
+                "<{}> is not a local path.",
+                request.path
+            )))?
+        }
+        let url = (Url::parse(&site.website_root))
+            .and_then(|website_root| website_root.join(&request.path))
+            .map_err(|err| {
+                Error::InternalServerError(format!(
+                    "Could not build the link to <{}>: {err}",
+                    request.path
+                ))
+            })?;
+
+        let link_request = LinkRequest {
+            name: request.name.to_owned(),
+            profiles: (form.profiles.split(","))
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(ToOwned::to_owned)
+                .collect(),
+            ttl: form.ttl,
+            url: url.to_string(),
+            max_uses: None,
+        };
+        let (link, _) = issue_link(&site, link_request, token.profiles())?;
+
+        request.status = AccessRequestStatus::Approved;
+        access_requests.save(&site.data_file(ACCESS_REQUESTS_FILE))?;
+        link
+    };
+    debug!("Approved access request {}", form.id);
+
+    access_requests_page_(&app_state, &site, Some(link))
+}
+
+#[derive(Deserialize)]
+struct RejectForm {
+    id: String,
+}
+
+async fn reject(
+    token: Token,
+    Extension(site): Extension<Arc<Site>>,
+    Form(form): Form<RejectForm>,
+) -> Result<Redirect, Error> {
+    if !token.profiles().contains(&"*".to_owned()) {
+        Err(Error::Unauthorized)?
+    }
+
+    let mut access_requests = site.state.access_requests.write().unwrap();
+    let request = (access_requests.get_mut(&form.id)).ok_or(Error::ClientError(format!(
+        "Unknown request '{}'.",
+        form.id
+    )))?;
+    request.status = AccessRequestStatus::Rejected;
+    access_requests.save(&site.data_file(ACCESS_REQUESTS_FILE))?;
+    debug!("Rejected access request {}", form.id);
+
+    Ok(Redirect::to("/_access-requests"))
+}
@@ -0,0 +1,290 @@
+//! JSON API mirroring the admin pages, for automation.
+//!
+//! All routes except the OpenAPI description require a super admin (`*` profile)
+//! access token (Biscuit or JWT) passed as a `Bearer` token in the `Authorization` header.
+//! Cookies and query params are ignored on purpose, and so are refresh tokens.
+
+use std::{sync::Arc, time::SystemTime};
+
+use axum::{
+    extract::{rejection::JsonRejection, FromRequestParts},
+    http::{header, request, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use biscuit_auth::macros::authorizer;
+use serde::Serialize;
+use serde_json::json;
+use tracing::debug;
+
+use crate::{
+    inventory::{self, PermissionMatrix, WebsiteSummary},
+    request_guards::{is_refresh_token, verify_token, Token},
+    routes::{
+        debug_routes::{AccessLog, ErrorLog},
+        update_content_routes::update_content,
+    },
+    sites::{self, Site},
+    util::error,
+    AppState, Error,
+};
+
+const OPENAPI_DESCRIPTION: &str = include_str!("openapi.yaml");
+
+pub(super) fn router() -> Router<AppState> {
+    #[allow(unused_mut)]
+    let mut router = Router::<AppState>::new()
+        .route("/_api/v1/openapi.yaml", get(openapi))
+        .route("/_api/v1/info", get(info))
+        .route("/_api/v1/errors", get(errors))
+        .route("/_api/v1/access-logs", get(access_logs))
+        .route("/_api/v1/revoked-tokens", get(revoked_tokens))
+        .route("/_api/v1/websites", get(websites))
+        .route("/_api/v1/permissions", get(permissions))
+        .route("/_api/v1/refresh", post(refresh));
+
+    #[cfg(feature = "token-generator")]
+    {
+        router = router
+            .route(
+                "/_api/v1/links",
+                get(links::list_links).post(links::generate_link),
+            )
+            .route("/_api/v1/links/{id}/revoke", post(links::revoke_link))
+            .route("/_api/v1/links/{id}/reissue", post(links::reissue_link));
+    }
+
+    router
+}
+
+/// Wraps [`crate::Error`] to render it as JSON.
+pub struct ApiError(Error);
+
+impl<E: Into<Error>> From<E> for ApiError {
+    fn from(err: E) -> Self {
+        Self(err.into())
+    }
+}
+
+/// So invalid request bodies get the same JSON errors
+/// (see [`WithRejection`](axum_extra::extract::WithRejection)).
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self(Error::ClientError(rejection.body_text()))
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.0.status_code();
+        let code = match self.0 {
+            Error::Unauthorized => "unauthorized",
+            Error::Forbidden => "forbidden",
+            Error::TokenRevoked => "token_revoked",
+            Error::RefreshTokenAlreadyUsed => "refresh_token_already_used",
+            Error::ClientError(_) => "bad_request",
+            _ => "internal_server_error",
+        };
+        let message = if status.is_server_error() {
+            // NOTE: Do not leak internal details.
+            error(format!("{}", self.0));
+            "Internal server error".to_owned()
+        } else {
+            debug!("{}", self.0);
+            format!("{}", self.0)
+        };
+
+        (status, Json(json!({ "error": code, "message": message }))).into_response()
+    }
+}
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+
+/// A super admin token, read from the `Authorization` header only.
+pub struct AdminToken(Token);
+
+impl<S> FromRequestParts<S> for AdminToken
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or(Error::Unauthorized)?;
+        let token = verify_token(token.trim(), "API Bearer token").ok_or(Error::Unauthorized)?;
+        // NOTE: Refresh tokens are meant to be exchanged once, not used directly.
+        if is_refresh_token(&token.biscuit) {
+            debug!("Refusing refresh token as API token");
+            Err(Error::Unauthorized)?
+        }
+
+        // NOTE: This also refuses attenuated tokens (e.g. share links).
+        let authorizer = authorizer!(
+            r#"
+            time({now});
+            allow if true;
+            "#,
+            now = SystemTime::now(),
+        );
+        if let Err(err) = token.biscuit.authorize(&authorizer) {
+            debug!("API token is invalid: {err}");
+            Err(Error::Unauthorized)?
+        }
+
+        if !token.profiles().contains(&"*".to_owned()) {
+            Err(Error::Forbidden)?
+        }
+
+        Ok(Self(token))
+    }
+}
+
+async fn openapi() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "application/yaml")],
+        OPENAPI_DESCRIPTION,
+    )
+}
+
+#[derive(Serialize)]
+struct Info {
+    profiles: Vec<String>,
+    biscuit: String,
+}
+
+async fn info(AdminToken(token): AdminToken) -> ApiResult<Info> {
+    Ok(Json(Info {
+        profiles: token.profiles(),
+        biscuit: token.biscuit.print(),
+    }))
+}
+
+async fn errors(
+    _: AdminToken,
+    Extension(site): Extension<Arc<Site>>,
+) -> ApiResult<Vec<ErrorLog>> {
+    Ok(Json(site.state.errors.read().unwrap().clone()))
+}
+
+async fn access_logs(
+    _: AdminToken,
+    Extension(site): Extension<Arc<Site>>,
+) -> ApiResult<Vec<AccessLog>> {
+    Ok(Json(site.state.access_logs.read().unwrap().clone()))
+}
+
+/// Hex-encoded revocation identifiers.
+async fn revoked_tokens(
+    _: AdminToken,
+    Extension(site): Extension<Arc<Site>>,
+) -> ApiResult<Vec<String>> {
+    let mut revoked_tokens: Vec<String> = (site.state.revoked_tokens.read().unwrap().iter())
+        .map(hex::encode)
+        .collect();
+    revoked_tokens.sort();
+    Ok(Json(revoked_tokens))
+}
+
+async fn websites(
+    _: AdminToken,
+    Extension(site): Extension<Arc<Site>>,
+) -> ApiResult<Vec<WebsiteSummary>> {
+    Ok(Json(inventory::website_summaries(&site)))
+}
+
+async fn permissions(
+    _: AdminToken,
+    Extension(site): Extension<Arc<Site>>,
+) -> ApiResult<PermissionMatrix> {
+    Ok(Json(inventory::permission_matrix(&site.workspace)))
+}
+
+/// Same as the `/update-content/github` webhook.
+async fn refresh(
+    _: AdminToken,
+    Extension(site): Extension<Arc<Site>>,
+) -> Result<StatusCode, ApiError> {
+    // NOTE: Pulling the repository and generating websites takes a while,
+    //   it must not block the runtime.
+    tokio::task::spawn_blocking(move || sites::sync_scope(site.clone(), || update_content(&site)))
+        .await
+        .map_err(|err| Error::InternalServerError(format!("Could not update content: {err}")))??;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(feature = "token-generator")]
+mod links {
+    use std::sync::Arc;
+
+    use axum::{extract::Path, http::StatusCode, Extension, Json};
+    use axum_extra::extract::WithRejection;
+    use serde::Serialize;
+
+    use super::{AdminToken, ApiError, ApiResult};
+    use crate::{
+        issued_links::{self, issue_link, IssuedLink, LinkRequest},
+        sites::Site,
+        Error,
+    };
+
+    #[derive(Serialize)]
+    pub struct GeneratedLink {
+        link: String,
+        #[serde(flatten)]
+        issued_link: IssuedLink,
+    }
+
+    pub async fn list_links(
+        _: AdminToken,
+        Extension(site): Extension<Arc<Site>>,
+    ) -> ApiResult<Vec<IssuedLink>> {
+        Ok(Json(site.state.issued_links.read().unwrap().all().clone()))
+    }
+
+    pub async fn generate_link(
+        AdminToken(token): AdminToken,
+        Extension(site): Extension<Arc<Site>>,
+        WithRejection(Json(request), _): WithRejection<Json<LinkRequest>, ApiError>,
+    ) -> Result<(StatusCode, Json<GeneratedLink>), ApiError> {
+        let (link, issued_link) = issue_link(&site, request, token.profiles())?;
+        Ok((
+            StatusCode::CREATED,
+            Json(GeneratedLink { link, issued_link }),
+        ))
+    }
+
+    pub async fn revoke_link(
+        _: AdminToken,
+        Extension(site): Extension<Arc<Site>>,
+        Path(id): Path<String>,
+    ) -> Result<StatusCode, ApiError> {
+        if !issued_links::revoke_link(&site, &id)? {
+            Err(Error::ClientError(format!("Unknown link '{id}'.")))?
+        }
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    pub async fn reissue_link(
+        AdminToken(token): AdminToken,
+        Extension(site): Extension<Arc<Site>>,
+        Path(id): Path<String>,
+    ) -> Result<(StatusCode, Json<GeneratedLink>), ApiError> {
+        let request = (site.state.issued_links.read().unwrap().get(&id))
+            .map(LinkRequest::from)
+            .ok_or(Error::ClientError(format!("Unknown link '{id}'.")))?;
+        let (link, issued_link) = issue_link(&site, request, token.profiles())?;
+        Ok((
+            StatusCode::CREATED,
+            Json(GeneratedLink { link, issued_link }),
+        ))
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, Method, StatusCode},
+    response::{Html, Redirect},
+    routing::{get, post},
+    Extension, Form, Json, Router,
+};
+use orangutan_helpers::{workspace::Workspace, PageMetadata};
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::{
+    auth::{can_comment, is_authorized, RequestFacts},
+    comments::{Comment, CommentContent, MAX_COMMENT_LENGTH, MAX_REACTION_LENGTH},
+    context,
+    request_guards::Token,
+    sites::Site,
+    util::templating::render,
+    AppState, Error,
+};
+
+pub(super) fn router() -> Router<AppState> {
+    Router::<AppState>::new()
+        .route("/_comments", get(list_comments).post(post_comment))
+        .route("/_comments/moderate", get(moderation_page))
+        .route("/_comments/delete", post(delete_comment))
+}
+
+pub(super) fn templates() -> Vec<(&'static str, &'static str)> {
+    vec![(
+        "moderate-comments.html",
+        include_str!("templates/moderate-comments.html.tera"),
+    )]
+}
+
+fn find_page(
+    workspace: &Workspace,
+    path: &str,
+) -> Result<PageMetadata, Error> {
+    (workspace.page_metadata(&PathBuf::from(path)))
+        .map_err(orangutan_helpers::generate::Error::CannotReadPageMetadata)?
+        .ok_or(Error::ClientError(format!("<{path}> is not a page.")))
+}
+
+#[derive(Deserialize)]
+struct CommentsQuery {
+    path: String,
+}
+
+async fn list_comments(
+    token: Option<Token>,
+    Extension(site): Extension<Arc<Site>>,
+    method: Method,
+    headers: HeaderMap,
+    Query(query): Query<CommentsQuery>,
+) -> Result<Json<Vec<Comment>>, Error> {
+    let page = find_page(&site.workspace, &query.path)?;
+
+    let user_profiles = token.as_ref().map(Token::profiles).unwrap_or_default();
+    let request_facts =
+        RequestFacts::new(method, &headers, &site.workspace.website_id(&user_profiles));
+    if !is_authorized(token, &page, &request_facts) {
+        Err(Error::Forbidden)?
+    }
+
+    let comments = site.state.comments.read(&page.path)?;
+    Ok(Json(comments))
+}
+
+#[derive(Deserialize)]
+struct CommentForm {
+    path: String,
+    #[serde(default)]
+    text: Option<String>,
+    #[serde(default)]
+    reaction: Option<String>,
+}
+
+async fn post_comment(
+    token: Token,
+    Extension(site): Extension<Arc<Site>>,
+    method: Method,
+    headers: HeaderMap,
+    Form(form): Form<CommentForm>,
+) -> Result<(StatusCode, Json<Comment>), Error> {
+    let page = find_page(&site.workspace, &form.path)?;
+
+    let user_profiles = token.profiles();
+    let request_facts =
+        RequestFacts::new(method, &headers, &site.workspace.website_id(&user_profiles));
+    if !can_comment(Some(token), &page, &request_facts) {
+        Err(Error::Forbidden)?
+    }
+
+    let content = match (form.text, form.reaction) {
+        (Some(text), None) if !text.trim().is_empty() => {
+            if text.chars().count() > MAX_COMMENT_LENGTH {
+                Err(Error::ClientError(format!(
+                    "Comments cannot be longer than {MAX_COMMENT_LENGTH} characters."
+                )))?
+            }
+            CommentContent::Comment {
+                text: text.trim().to_owned(),
+            }
+        },
+        (None, Some(emoji)) if !emoji.trim().is_empty() => {
+            if emoji.chars().count() > MAX_REACTION_LENGTH {
+                Err(Error::ClientError(format!(
+                    "Reactions cannot be longer than {MAX_REACTION_LENGTH} characters."
+                )))?
+            }
+            CommentContent::Reaction {
+                emoji: emoji.trim().to_owned(),
+            }
+        },
+        _ => Err(Error::ClientError(
+            "Expected either a `text` or a `reaction`.".to_owned(),
+        ))?,
+    };
+
+    let comment = Comment::new(user_profiles, content);
+    site.state.comments.add(&page.path, comment.clone())?;
+    debug!("New comment on <{}>", page.path.display());
+
+    Ok((StatusCode::CREATED, Json(comment)))
+}
+
+async fn moderation_page(
+    token: Token,
+    State(app_state): State<AppState>,
+    Extension(site): Extension<Arc<Site>>,
+) -> Result<Html<String>, Error> {
+    if !token.profiles().contains(&"*".to_owned()) {
+        Err(Error::Unauthorized)?
+    }
+
+    let pages: Vec<(String, Vec<Comment>)> = site
+        .state
+        .comments
+        .all()?
+        .into_iter()
+        .map(|(path, comments)| (path.display().to_string(), comments))
+        .collect();
+
+    let html = render(
+        &app_state.tera,
+        "moderate-comments.html",
+        context! { page_title: "Comments", pages },
+    )?;
+
+    Ok(Html(html))
+}
+
+#[derive(Deserialize)]
+struct DeleteCommentForm {
+    path: String,
+    id: String,
+}
+
+async fn delete_comment(
+    token: Token,
+    Extension(site): Extension<Arc<Site>>,
+    Form(form): Form<DeleteCommentForm>,
+) -> Result<Redirect, Error> {
+    if !token.profiles().contains(&"*".to_owned()) {
+        Err(Error::Unauthorized)?
+    }
+
+    if (site.state.comments).delete(Path::new(&form.path), &form.id)? {
+        debug!("Deleted comment {} on <{}>", form.id, form.path);
+    } else {
+        debug!("Comment {} on <{}> not found", form.id, form.path);
+    }
+
+    Ok(Redirect::to("/_comments/moderate"))
+}
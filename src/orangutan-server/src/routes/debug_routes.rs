@@ -1,26 +1,11 @@
-use std::sync::{Arc, RwLock};
+use std::sync::Arc;
 
-use axum::{routing::get, Router};
+use axum::{routing::get, Extension, Router};
 use axum_extra::extract::CookieJar;
 use chrono::{DateTime, Utc};
-use lazy_static::lazy_static;
-
-use crate::{
-    request_guards::{Token, REVOKED_TOKENS},
-    AppState, Error,
-};
-
-lazy_static! {
-    /// A list of runtime errors, used to show error logs in an admin page
-    /// without having to open the cloud hosting provider's logs.
-    ///
-    /// // NOTE: `Arc` prevents race conditions
-    pub(crate) static ref ERRORS: Arc<RwLock<Vec<ErrorLog>>> = Arc::default();
-    /// Access logs, per "user".
-    ///
-    /// // NOTE: `Arc` prevents race conditions
-    pub(crate) static ref ACCESS_LOGS: Arc<RwLock<Vec<AccessLog>>> = Arc::default();
-}
+use serde::Serialize;
+
+use crate::{request_guards::Token, sites::Site, AppState, Error};
 
 pub(super) fn router() -> Router<AppState> {
     #[allow(unused_mut)]
@@ -60,12 +45,17 @@ pub(super) fn templates() -> Vec<(&'static str, &'static str)> {
 
 #[cfg(feature = "templating")]
 mod index {
-    use axum::{extract::State, response::Html};
+    use std::sync::Arc;
 
-    use crate::{request_guards::Token, AppState, Error};
+    use axum::{extract::State, response::Html, Extension};
 
-    fn admin_page_(tera: &tera::Tera) -> Result<Html<String>, Error> {
-        use crate::{context, util::templating::render};
+    use crate::{request_guards::Token, sites::Site, AppState, Error};
+
+    fn admin_page_(
+        tera: &tera::Tera,
+        site: &Site,
+    ) -> Result<Html<String>, Error> {
+        use crate::{context, inventory, util::templating::render};
 
         let pages = vec![
             "/_info",
@@ -77,14 +67,42 @@ mod index {
         let pages = {
             let mut pages = pages;
             pages.push("_generate-token");
+            pages.push("/_issued-links");
             pages
         };
-
-        let html = render(
-            tera,
-            "admin.html",
-            context! { page_title: "Admin pages", pages },
-        )?;
+        #[cfg(feature = "comments")]
+        let pages = {
+            let mut pages = pages;
+            pages.push("/_comments/moderate");
+            pages
+        };
+        #[cfg(feature = "impersonation")]
+        let pages = {
+            let mut pages = pages;
+            pages.push("/_impersonate");
+            pages
+        };
+        #[cfg(feature = "request-access")]
+        let pages = {
+            let mut pages = pages;
+            pages.push("/_access-requests");
+            pages
+        };
+        #[cfg(feature = "request-access")]
+        let pending_access_requests = site.state.access_requests.read().unwrap().pending().count();
+        #[cfg(not(feature = "request-access"))]
+        let pending_access_requests = 0;
+
+        let websites = inventory::website_summaries(site);
+        let matrix = inventory::permission_matrix(&site.workspace);
+
+        let html = render(tera, "admin.html", context! {
+            page_title: "Admin dashboard",
+            pages,
+            pending_access_requests,
+            websites,
+            matrix,
+        })?;
 
         Ok(Html(html))
     }
@@ -92,12 +110,13 @@ mod index {
     pub async fn admin_page(
         token: Token,
         State(app_state): State<AppState>,
+        Extension(site): Extension<Arc<Site>>,
     ) -> Result<Html<String>, Error> {
         if !token.profiles().contains(&"*".to_owned()) {
             Err(Error::Unauthorized)?
         }
 
-        admin_page_(&app_state.tera)
+        admin_page_(&app_state.tera, &site)
     }
 }
 
@@ -125,18 +144,24 @@ async fn get_user_info(token: Option<Token>) -> String {
     }
 }
 
+/// A runtime error, shown in admin pages without having
+/// to open the cloud hosting provider's logs.
+#[derive(Debug, Clone, Serialize)]
 pub struct ErrorLog {
     pub timestamp: DateTime<Utc>,
     pub line: String,
 }
 
-async fn errors(token: Token) -> Result<String, Error> {
+async fn errors(
+    token: Token,
+    Extension(site): Extension<Arc<Site>>,
+) -> Result<String, Error> {
     if !token.profiles().contains(&"*".to_owned()) {
         Err(Error::Unauthorized)?
     }
 
     let mut res = String::new();
-    for log in ERRORS.read().unwrap().iter() {
+    for log in site.state.errors.read().unwrap().iter() {
         res.push_str(&format!("{} | {}\n", log.timestamp, log.line));
     }
 
@@ -150,13 +175,20 @@ async fn errors(token: Token) -> Result<String, Error> {
 ///   That day we will change this type to just `String`.
 type User = Vec<String>;
 
+#[derive(Debug, Clone, Serialize)]
 pub struct AccessLog {
     pub timestamp: DateTime<Utc>,
     pub user: User,
     pub path: String,
+    /// Hex-encoded revocation identifiers of the user's tokens
+    /// (see [`Token::lineage`]), to know which link they came from.
+    pub tokens: Vec<String>,
 }
 
-async fn access_logs(token: Token) -> Result<String, Error> {
+async fn access_logs(
+    token: Token,
+    Extension(site): Extension<Arc<Site>>,
+) -> Result<String, Error> {
     if !token.profiles().contains(&"*".to_owned()) {
         Err(Error::Unauthorized)?
     }
@@ -166,7 +198,8 @@ async fn access_logs(token: Token) -> Result<String, Error> {
         timestamp,
         user,
         path,
-    } in ACCESS_LOGS.read().unwrap().iter()
+        ..
+    } in site.state.access_logs.read().unwrap().iter()
     {
         let mut profiles = user.clone();
         // Sort profiles so they are always presented in the same order
@@ -184,23 +217,29 @@ async fn access_logs(token: Token) -> Result<String, Error> {
 }
 
 pub fn log_access(
+    site: &Site,
     user: User,
     path: String,
+    tokens: Vec<String>,
 ) {
-    ACCESS_LOGS.write().unwrap().push(AccessLog {
+    site.state.access_logs.write().unwrap().push(AccessLog {
         timestamp: Utc::now(),
         user,
         path,
+        tokens,
     })
 }
 
-async fn revoked_tokens(token: Token) -> Result<String, Error> {
+async fn revoked_tokens(
+    token: Token,
+    Extension(site): Extension<Arc<Site>>,
+) -> Result<String, Error> {
     if !token.profiles().contains(&"*".to_owned()) {
         Err(Error::Forbidden)?
     }
 
     let mut res = String::new();
-    for token in REVOKED_TOKENS.read().unwrap().iter() {
+    for token in site.state.revoked_tokens.read().unwrap().iter() {
         res.push_str(std::str::from_utf8(token).unwrap_or("<cannot parse>"));
         res.push('\n');
     }
@@ -210,14 +249,21 @@ async fn revoked_tokens(token: Token) -> Result<String, Error> {
 
 #[cfg(feature = "token-generator")]
 pub mod token_generator {
-    use axum::response::Html;
-    use axum::{extract::State, Form};
-    use orangutan_refresh_token::RefreshToken;
+    use std::sync::Arc;
+
+    use axum::{extract::State, response::Html, Extension, Form};
     use serde::Deserialize;
 
-    use crate::{context, request_guards::Token, util::templating::render, AppState, Error};
+    use crate::{
+        context,
+        issued_links::{issue_link, LinkRequest},
+        request_guards::Token,
+        sites::Site,
+        util::templating::render,
+        AppState, Error,
+    };
 
-    fn token_generation_form_(
+    pub(crate) fn token_generation_form_(
         tera: &tera::Tera,
         link: Option<String>,
         base_url: &str,
@@ -234,12 +280,13 @@ pub mod token_generator {
     pub async fn token_generation_form(
         token: Token,
         State(app_state): State<AppState>,
+        Extension(site): Extension<Arc<Site>>,
     ) -> Result<Html<String>, Error> {
         if !token.profiles().contains(&"*".to_owned()) {
             Err(Error::Unauthorized)?
         }
 
-        token_generation_form_(&app_state.tera, None, &app_state.website_root)
+        token_generation_form_(&app_state.tera, None, &site.website_root)
     }
 
     #[derive(Deserialize)]
@@ -248,29 +295,43 @@ pub mod token_generator {
         name: String,
         profiles: String,
         url: String,
+        /// Empty means "unlimited".
+        #[serde(default)]
+        max_uses: String,
     }
 
     pub async fn generate_token(
         token: Token,
         State(app_state): State<AppState>,
+        Extension(site): Extension<Arc<Site>>,
         Form(form): Form<GenerateTokenForm>,
     ) -> Result<Html<String>, Error> {
         if !token.profiles().contains(&"*".to_owned()) {
             Err(Error::Unauthorized)?
         }
 
-        let mut profiles = vec![form.name.to_owned()];
-        profiles.append(&mut form.profiles.split(",").map(ToOwned::to_owned).collect());
-        if profiles.contains(&"*".to_string()) {
-            Err(Error::ClientError(format!(
-                "Profiles cannot contain '*' (got {profiles:?})."
-            )))?
-        }
+        let max_uses = match form.max_uses.trim() {
+            "" => None,
+            max_uses => Some(max_uses.parse::<u32>().map_err(|err| {
+                Error::ClientError(format!("Invalid max uses '{max_uses}': {err}"))
+            })?),
+        };
 
-        let token = RefreshToken::try_from(form.ttl.to_owned(), profiles.into_iter())?;
-        let token_base64 = token.as_base64()?;
-        let link = format!("{}?refresh_token={token_base64}", form.url);
+        let request = LinkRequest {
+            name: form.name,
+            profiles: form
+                .profiles
+                .split(",")
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(ToOwned::to_owned)
+                .collect(),
+            ttl: form.ttl,
+            url: form.url,
+            max_uses,
+        };
+        let (link, _) = issue_link(&site, request, token.profiles())?;
 
-        token_generation_form_(&app_state.tera, Some(link), &app_state.website_root)
+        token_generation_form_(&app_state.tera, Some(link), &site.website_root)
     }
 }
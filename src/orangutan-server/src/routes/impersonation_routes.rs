@@ -0,0 +1,222 @@
+//! Routes allowing super admins to preview the website as other profiles.
+//!
+//! Impersonation is stored in a separate session cookie, so the admin's own token
+//! is left untouched. Pages are then served through the same [`WebsiteId`] and
+//! [`is_authorized`] path as for real users, with a banner on top.
+//!
+//! [`WebsiteId`]: orangutan_helpers::website_id::WebsiteId
+//! [`is_authorized`]: crate::auth::is_authorized
+
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::{
+        header::{CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE},
+        HeaderValue,
+    },
+    response::{Html, Redirect, Response},
+    routing::{get, post},
+    Extension, Form, Router,
+};
+use axum_extra::extract::CookieJar;
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::{
+    config::{IMPERSONATION_COOKIE_NAME, TOKEN_COOKIE},
+    context,
+    request_guards::Token,
+    sites::Site,
+    util::{error, in_memory_biscuit, redirect_path, templating::render},
+    AppState, Error,
+};
+
+pub(super) fn router() -> Router<AppState> {
+    Router::<AppState>::new()
+        .route("/_impersonate", get(impersonation_page).post(impersonate))
+        .route("/_impersonate/stop", post(stop_impersonating))
+}
+
+pub(super) fn templates() -> Vec<(&'static str, &'static str)> {
+    vec![
+        (
+            "impersonate.html",
+            include_str!("templates/impersonate.html.tera"),
+        ),
+        (
+            "impersonation-banner.html",
+            include_str!("templates/impersonation-banner.html.tera"),
+        ),
+    ]
+}
+
+/// Returns the impersonated profiles, if `token` belongs to a super admin
+/// who enabled impersonation.
+pub fn impersonated_profiles(
+    token: Option<&Token>,
+    cookies: &CookieJar,
+) -> Option<Vec<String>> {
+    let cookie = cookies.get(IMPERSONATION_COOKIE_NAME)?;
+    if !token?.profiles().contains(&"*".to_owned()) {
+        debug!("Ignoring impersonation cookie as user is not a super admin.");
+        return None;
+    }
+    Some(parse_profiles(cookie.value()))
+}
+
+fn parse_profiles(profiles: &str) -> Vec<String> {
+    profiles
+        .split(",")
+        .map(str::trim)
+        // NOTE: Impersonating a super admin makes no sense.
+        .filter(|p| !p.is_empty() && *p != "*")
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+/// Creates a token with the given profiles, to be used instead of the admin's.
+pub fn impersonation_token(profiles: &[String]) -> Result<Token, Error> {
+    let biscuit = in_memory_biscuit(profiles.iter().cloned()).map_err(|err| {
+        Error::InternalServerError(format!("Could not create impersonation token: {err}"))
+    })?;
+    Ok(Token::from(biscuit))
+}
+
+/// Adds a banner on top of HTML pages, so admins don't forget they are impersonating.
+pub async fn add_banner(
+    tera: &tera::Tera,
+    profiles: &[String],
+    path: &str,
+    response: Response,
+) -> Response {
+    let is_html = (response.headers().get(CONTENT_TYPE))
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with(mime::TEXT_HTML.essence_str()));
+    if !is_html {
+        return response;
+    }
+
+    let banner = match render(
+        tera,
+        "impersonation-banner.html",
+        context! { profiles, path },
+    ) {
+        Ok(banner) => banner,
+        Err(err) => {
+            error(format!("Could not render impersonation banner: {err}"));
+            return response;
+        },
+    };
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            error(format!(
+                "Could not read page to add impersonation banner: {err}"
+            ));
+            return Response::from_parts(parts, Body::empty());
+        },
+    };
+    let html = String::from_utf8_lossy(&bytes);
+    // Insert the banner right after the opening `<body>` tag.
+    let html = match (html.find("<body")).and_then(|i| html[i..].find('>').map(|j| i + j + 1)) {
+        Some(index) => format!("{}{banner}{}", &html[..index], &html[index..]),
+        None => format!("{banner}{html}"),
+    };
+
+    parts.headers.remove(CONTENT_LENGTH);
+    // Never cache impersonated pages.
+    parts
+        .headers
+        .insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    Response::from_parts(parts, Body::from(html))
+}
+
+#[derive(Deserialize)]
+struct ImpersonationQuery {
+    /// Profiles, comma separated.
+    #[serde(default, rename = "as")]
+    profiles: Option<String>,
+    #[serde(default)]
+    path: Option<String>,
+}
+
+async fn impersonation_page(
+    token: Token,
+    State(app_state): State<AppState>,
+    Extension(site): Extension<Arc<Site>>,
+    cookies: CookieJar,
+    Query(query): Query<ImpersonationQuery>,
+) -> Result<Html<String>, Error> {
+    if !token.profiles().contains(&"*".to_owned()) {
+        Err(Error::Unauthorized)?
+    }
+
+    let current = impersonated_profiles(Some(&token), &cookies);
+    let mut known_profiles: Vec<String> =
+        (site.workspace.used_profiles().iter()).cloned().collect();
+    known_profiles.sort();
+
+    let html = render(&app_state.tera, "impersonate.html", context! {
+        page_title: "View website as",
+        profiles: query.profiles.or(current.map(|p| p.join(","))),
+        path: query.path,
+        known_profiles,
+    })?;
+
+    Ok(Html(html))
+}
+
+#[derive(Deserialize)]
+struct ImpersonationForm {
+    /// Profiles, comma separated.
+    profiles: String,
+    #[serde(default)]
+    path: Option<String>,
+}
+
+async fn impersonate(
+    token: Token,
+    cookies: CookieJar,
+    Form(form): Form<ImpersonationForm>,
+) -> Result<(CookieJar, Redirect), Error> {
+    if !token.profiles().contains(&"*".to_owned()) {
+        Err(Error::Unauthorized)?
+    }
+
+    let profiles = parse_profiles(&form.profiles).join(",");
+    debug!("Impersonating '{profiles}'");
+    let cookie = TOKEN_COOKIE.companion_cookie(IMPERSONATION_COOKIE_NAME, profiles);
+
+    Ok((cookies.add(cookie), Redirect::to(&redirect_path(form.path))))
+}
+
+#[derive(Deserialize)]
+struct StopImpersonatingForm {
+    #[serde(default)]
+    path: Option<String>,
+}
+
+async fn stop_impersonating(
+    cookies: CookieJar,
+    Form(form): Form<StopImpersonatingForm>,
+) -> (CookieJar, Redirect) {
+    let cookie = TOKEN_COOKIE.companion_cookie(IMPERSONATION_COOKIE_NAME, String::new());
+    (
+        cookies.remove(cookie),
+        Redirect::to(&redirect_path(form.path)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_profiles;
+
+    #[test]
+    fn test_cannot_impersonate_super_admin() {
+        assert_eq!(parse_profiles("amis, *,famille,"), vec!["amis", "famille"]);
+    }
+}
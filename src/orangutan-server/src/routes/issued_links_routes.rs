@@ -0,0 +1,199 @@
+//! Admin pages listing links generated with the token generator.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    response::{Html, Redirect},
+    routing::{get, post},
+    Extension, Form, Router,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    context,
+    issued_links::{issue_link, revoke_link, IssuedLink, LinkRequest},
+    request_guards::Token,
+    routes::debug_routes::token_generator::token_generation_form_,
+    sites::Site,
+    util::templating::render,
+    AppState, Error,
+};
+
+pub(super) fn router() -> Router<AppState> {
+    Router::<AppState>::new()
+        .route("/_issued-links", get(issued_links))
+        .route("/_issued-links/revoke", post(revoke))
+        .route("/_issued-links/reissue", post(reissue))
+}
+
+pub(super) fn templates() -> Vec<(&'static str, &'static str)> {
+    vec![(
+        "issued-links.html",
+        include_str!("templates/issued-links.html.tera"),
+    )]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum LinkStatus {
+    Active,
+    Expired,
+    Revoked,
+}
+
+impl LinkStatus {
+    fn of(
+        link: &IssuedLink,
+        now: DateTime<Utc>,
+    ) -> Self {
+        if link.revoked_at.is_some() {
+            Self::Revoked
+        } else if link.expires_at.is_some_and(|expires_at| expires_at <= now) {
+            Self::Expired
+        } else {
+            Self::Active
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Active => "active",
+            Self::Expired => "expired",
+            Self::Revoked => "revoked",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct IssuedLinkRow {
+    #[serde(flatten)]
+    link: IssuedLink,
+    status: LinkStatus,
+    first_used_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct IssuedLinksQuery {
+    /// Part of the link name.
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    profile: Option<String>,
+    /// `active`, `expired` or `revoked`.
+    #[serde(default)]
+    status: Option<String>,
+}
+
+impl IssuedLinksQuery {
+    fn matches(
+        &self,
+        row: &IssuedLinkRow,
+    ) -> bool {
+        let name_matches = match self.name.as_deref().map(str::trim) {
+            None | Some("") => true,
+            Some(name) => row.link.name.contains(name),
+        };
+        let profile_matches = match self.profile.as_deref().map(str::trim) {
+            None | Some("") => true,
+            Some(profile) => row.link.profiles.iter().any(|p| p == profile),
+        };
+        let status_matches = match self.status.as_deref() {
+            None | Some("") => true,
+            Some(status) => status == row.status.as_str(),
+        };
+        name_matches && profile_matches && status_matches
+    }
+}
+
+async fn issued_links(
+    token: Token,
+    State(app_state): State<AppState>,
+    Extension(site): Extension<Arc<Site>>,
+    Query(query): Query<IssuedLinksQuery>,
+) -> Result<Html<String>, Error> {
+    if !token.profiles().contains(&"*".to_owned()) {
+        Err(Error::Unauthorized)?
+    }
+
+    let now = Utc::now();
+    let access_logs = site.state.access_logs.read().unwrap();
+    let mut links: Vec<IssuedLinkRow> = (site.state.issued_links)
+        .read()
+        .unwrap()
+        .all()
+        .iter()
+        .map(|link| {
+            // NOTE: Access tokens baked from a link keep its revocation
+            //   identifiers, so we can find them in access logs.
+            let mut uses = access_logs
+                .iter()
+                .filter(|log| (log.tokens.iter()).any(|id| link.revocation_ids.contains(id)))
+                .map(|log| log.timestamp);
+            let first_used_at = uses.next();
+            let last_used_at = uses.next_back().or(first_used_at);
+            IssuedLinkRow {
+                link: link.clone(),
+                status: LinkStatus::of(link, now),
+                first_used_at,
+                last_used_at,
+            }
+        })
+        .filter(|row| query.matches(row))
+        .collect();
+    // Most recent first
+    links.reverse();
+
+    let html = render(
+        &app_state.tera,
+        "issued-links.html",
+        context! { page_title: "Issued links", links, query },
+    )?;
+
+    Ok(Html(html))
+}
+
+#[derive(Deserialize)]
+struct IssuedLinkForm {
+    id: String,
+}
+
+async fn revoke(
+    token: Token,
+    Extension(site): Extension<Arc<Site>>,
+    Form(form): Form<IssuedLinkForm>,
+) -> Result<Redirect, Error> {
+    if !token.profiles().contains(&"*".to_owned()) {
+        Err(Error::Unauthorized)?
+    }
+
+    if !revoke_link(&site, &form.id)? {
+        Err(Error::ClientError(format!("Unknown link '{}'.", form.id)))?
+    }
+
+    Ok(Redirect::to("/_issued-links"))
+}
+
+/// Generates a new link with the same parameters as an existing one.
+async fn reissue(
+    token: Token,
+    State(app_state): State<AppState>,
+    Extension(site): Extension<Arc<Site>>,
+    Form(form): Form<IssuedLinkForm>,
+) -> Result<Html<String>, Error> {
+    if !token.profiles().contains(&"*".to_owned()) {
+        Err(Error::Unauthorized)?
+    }
+
+    let request = (site.state.issued_links)
+        .read()
+        .unwrap()
+        .get(&form.id)
+        .map(LinkRequest::from)
+        .ok_or(Error::ClientError(format!("Unknown link '{}'.", form.id)))?;
+    let (link, _) = issue_link(&site, request, token.profiles())?;
+
+    token_generation_form_(&app_state.tera, Some(link), &site.website_root)
+}
@@ -0,0 +1,149 @@
+//! Routes allowing users to log in their other devices,
+//! using short-lived links or codes (see [`crate::device_codes`]).
+
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::StatusCode,
+    response::{Html, IntoResponse as _, Redirect, Response},
+    routing::get,
+    Extension, Form, Router,
+};
+use orangutan_refresh_token::RefreshToken;
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::{
+    config::REFRESH_TOKEN_QUERY_PARAM_NAME,
+    context,
+    device_codes::{Redemption, DEVICE_LINK_TTL},
+    request_guards::Token,
+    sites::Site,
+    util::templating::render,
+    AppState, Error,
+};
+
+pub(super) fn router() -> Router<AppState> {
+    Router::<AppState>::new()
+        .route("/_link-device", get(link_device_page).post(link_device))
+        .route("/_link-device/code", get(enter_code_page).post(redeem_code))
+}
+
+pub(super) fn templates() -> Vec<(&'static str, &'static str)> {
+    vec![
+        (
+            "link-device.html",
+            include_str!("templates/link-device.html.tera"),
+        ),
+        (
+            "link-device-code.html",
+            include_str!("templates/link-device-code.html.tera"),
+        ),
+    ]
+}
+
+fn link_device_page_(
+    tera: &tera::Tera,
+    link: Option<String>,
+    code: Option<String>,
+) -> Result<Html<String>, Error> {
+    let html = render(tera, "link-device.html", context! {
+        page_title: "Log in another device",
+        link,
+        code,
+        ttl_minutes: DEVICE_LINK_TTL.as_secs() / 60,
+    })?;
+
+    Ok(Html(html))
+}
+
+async fn link_device_page(
+    _token: Token,
+    State(app_state): State<AppState>,
+) -> Result<Html<String>, Error> {
+    link_device_page_(&app_state.tera, None, None)
+}
+
+#[derive(Deserialize)]
+struct LinkDeviceForm {
+    /// Checkbox, present only if checked.
+    #[serde(default)]
+    with_code: Option<String>,
+}
+
+async fn link_device(
+    token: Token,
+    State(app_state): State<AppState>,
+    Extension(site): Extension<Arc<Site>>,
+    Form(form): Form<LinkDeviceForm>,
+) -> Result<Html<String>, Error> {
+    // NOTE: We attenuate the user's token instead of creating a new one
+    //   so the other device can never get more rights than this one.
+    let refresh_token = RefreshToken::attenuate(&token.biscuit, DEVICE_LINK_TTL)?;
+    let refresh_token = refresh_token.as_base64()?;
+    let link = format!(
+        "{}?{REFRESH_TOKEN_QUERY_PARAM_NAME}={refresh_token}",
+        site.website_root.as_str(),
+    );
+
+    let code = if form.with_code.is_some() {
+        Some(site.state.device_codes.add(refresh_token))
+    } else {
+        None
+    };
+
+    link_device_page_(&app_state.tera, Some(link), code)
+}
+
+fn enter_code_page_(
+    tera: &tera::Tera,
+    error: Option<&str>,
+) -> Result<Html<String>, Error> {
+    let html = render(
+        tera,
+        "link-device-code.html",
+        context! { page_title: "Log in with a code", error },
+    )?;
+
+    Ok(Html(html))
+}
+
+async fn enter_code_page(State(app_state): State<AppState>) -> Result<Html<String>, Error> {
+    enter_code_page_(&app_state.tera, None)
+}
+
+#[derive(Deserialize)]
+struct CodeForm {
+    code: String,
+}
+
+async fn redeem_code(
+    State(app_state): State<AppState>,
+    Extension(site): Extension<Arc<Site>>,
+    // NOTE: Set by the server, but not when calling the app directly (e.g. in tests).
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+    Form(form): Form<CodeForm>,
+) -> Result<Response, Error> {
+    let client = connect_info.map(|Extension(ConnectInfo(addr))| addr.ip());
+    match site.state.device_codes.redeem(&form.code, client) {
+        Redemption::Redeemed(refresh_token) => {
+            debug!("Device code redeemed");
+            // Let the refresh token middleware do the rest.
+            let redirect_to = format!("/?{REFRESH_TOKEN_QUERY_PARAM_NAME}={refresh_token}");
+            Ok(Redirect::to(&redirect_to).into_response())
+        },
+        Redemption::Invalid => {
+            debug!("Invalid or expired device code");
+            let html = enter_code_page_(&app_state.tera, Some("Invalid or expired code."))?;
+            Ok(html.into_response())
+        },
+        Redemption::TooManyFailures => {
+            let html = enter_code_page_(
+                &app_state.tera,
+                Some("Too many invalid codes were typed. Try again later."),
+            )?;
+            Ok((StatusCode::TOO_MANY_REQUESTS, html).into_response())
+        },
+    }
+}
@@ -0,0 +1,123 @@
+//! Routes allowing users to log out.
+//!
+//! Logging out removes Orangutan's cookies only (other cookies set
+//! by the website are left untouched), including tokens from share links. When submitted with `revoke`,
+//! the session token is also revoked so a copy of the cookie stops working.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::Query,
+    http::HeaderName,
+    response::{AppendHeaders, Redirect},
+    routing::get,
+    Extension, Form, Router,
+};
+use axum_extra::extract::CookieJar;
+use biscuit_auth::Biscuit;
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::{
+    config::TOKEN_COOKIE,
+    request_guards::query_token_cookies_removal,
+    sites::Site,
+    util::{add_padding, redirect_path},
+    AppState, Error,
+};
+
+pub(super) fn router() -> Router<AppState> {
+    Router::<AppState>::new().route("/_logout", get(logout).post(logout_form))
+}
+
+#[derive(Deserialize)]
+struct LogoutQuery {
+    /// Where to go after logging out.
+    #[serde(default)]
+    path: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LogoutForm {
+    #[serde(default)]
+    path: Option<String>,
+    /// Set by a checkbox, so any value means `true`.
+    #[serde(default)]
+    revoke: Option<String>,
+}
+
+/// Cookies to remove, and `Set-Cookie` headers [`CookieJar`] can't express.
+type ClearedCookies = (CookieJar, AppendHeaders<Vec<(HeaderName, String)>>);
+
+async fn logout(
+    cookies: CookieJar,
+    Query(query): Query<LogoutQuery>,
+) -> (ClearedCookies, Redirect) {
+    (
+        clear_cookies(cookies),
+        Redirect::to(&redirect_path(query.path)),
+    )
+}
+
+// NOTE: Revoking is only allowed using `POST`, so a link cannot
+//   revoke someone's session without them knowing.
+async fn logout_form(
+    Extension(site): Extension<Arc<Site>>,
+    cookies: CookieJar,
+    Form(form): Form<LogoutForm>,
+) -> Result<(ClearedCookies, Redirect), Error> {
+    if form.revoke.is_some() {
+        let revocation_ids = (cookies.get(&TOKEN_COOKIE.name))
+            .map(|cookie| revocation_ids(&site, cookie.value()))
+            .unwrap_or_default();
+        if !revocation_ids.is_empty() {
+            site.workspace.revoke_tokens(&revocation_ids)?;
+            site.state
+                .revoked_tokens
+                .write()
+                .unwrap()
+                .extend(revocation_ids);
+            debug!("Revoked session token");
+        }
+    }
+
+    Ok((
+        clear_cookies(cookies),
+        Redirect::to(&redirect_path(form.path)),
+    ))
+}
+
+fn clear_cookies(cookies: CookieJar) -> ClearedCookies {
+    let query_token_cookies = query_token_cookies_removal(&cookies);
+    let cookies = cookies.remove(TOKEN_COOKIE.removal_cookie());
+    // NOTE: Stop impersonating too, or an admin logging back in
+    //   would still see the website as someone else.
+    #[cfg(feature = "impersonation")]
+    let cookies = cookies.remove(
+        TOKEN_COOKIE.companion_cookie(crate::config::IMPERSONATION_COOKIE_NAME, String::new()),
+    );
+    (cookies, query_token_cookies)
+}
+
+/// Returns what to revoke for the session token `token`,
+/// or nothing if it wasn't issued by Orangutan.
+fn revocation_ids(
+    site: &Site,
+    token: &str,
+) -> Vec<Vec<u8>> {
+    #[cfg(feature = "jwt")]
+    if crate::jwt::is_jwt(token) {
+        return (crate::jwt::decode(token).ok())
+            .and_then(|claims| hex::decode(claims.jti).ok())
+            .into_iter()
+            .collect();
+    }
+
+    match Biscuit::from_base64(add_padding(token), site.root_key.public()) {
+        Ok(biscuit) => biscuit.revocation_identifiers(),
+        Err(err) => {
+            debug!("Not revoking invalid session token: {err}");
+            vec![]
+        },
+    }
+}
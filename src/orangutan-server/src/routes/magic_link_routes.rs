@@ -0,0 +1,105 @@
+//! Routes allowing users to log in by receiving a link by email.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::State, http::StatusCode, response::Html, routing::get, Extension, Form, Router,
+};
+use orangutan_refresh_token::RefreshToken;
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::{
+    config::REFRESH_TOKEN_QUERY_PARAM_NAME,
+    context,
+    magic_link::{send_magic_link, MAGIC_LINK_TTL, SMTP_CONFIG},
+    sites::{self, Site},
+    util::{error, templating::render},
+    AppState, Error,
+};
+
+pub(super) fn router() -> Router<AppState> {
+    Router::<AppState>::new().route("/_login", get(login_page).post(login))
+}
+
+pub(super) fn templates() -> Vec<(&'static str, &'static str)> {
+    vec![("login.html", include_str!("templates/login.html.tera"))]
+}
+
+fn login_page_(
+    tera: &tera::Tera,
+    sent: bool,
+    error: Option<&str>,
+) -> Result<Html<String>, Error> {
+    let html = render(tera, "login.html", context! {
+        page_title: "Log in",
+        sent,
+        error,
+        ttl_minutes: MAGIC_LINK_TTL.as_secs() / 60,
+    })?;
+
+    Ok(Html(html))
+}
+
+async fn login_page(State(app_state): State<AppState>) -> Result<Html<String>, Error> {
+    login_page_(&app_state.tera, false, None)
+}
+
+#[derive(Deserialize)]
+struct LoginForm {
+    email: String,
+}
+
+async fn login(
+    State(app_state): State<AppState>,
+    Extension(site): Extension<Arc<Site>>,
+    Form(form): Form<LoginForm>,
+) -> Result<(StatusCode, Html<String>), Error> {
+    let Some(smtp_config) = SMTP_CONFIG.as_ref() else {
+        Err(Error::InternalServerError(
+            "Email login requested but SMTP is not configured.".to_owned(),
+        ))?
+    };
+
+    let email = form.email.trim().to_lowercase();
+    if !site.state.login_attempts.lock().unwrap().record(&email) {
+        debug!("Too many login emails requested");
+        let html = login_page_(
+            &app_state.tera,
+            false,
+            Some("Too many emails were sent to this address. Try again later."),
+        )?;
+        return Ok((StatusCode::TOO_MANY_REQUESTS, html));
+    }
+
+    // NOTE: The response is the same whether the address is known or not,
+    //   so this page cannot be used to find out who can log in.
+    match site.state.email_users.profiles(&email) {
+        Some(profiles) => {
+            // NOTE: Links are single-use, in case an email is forwarded.
+            let token = RefreshToken::new_with_key(
+                &site.root_key,
+                MAGIC_LINK_TTL,
+                profiles.into_iter(),
+                Some(1),
+            )?;
+            let link = format!(
+                "{}?{REFRESH_TOKEN_QUERY_PARAM_NAME}={}",
+                site.website_root.as_str(),
+                token.as_base64()?,
+            );
+            // NOTE: Send in the background so response times don't tell
+            //   whether the address is known either.
+            let smtp_config = smtp_config.clone();
+            tokio::spawn(sites::scope(site, async move {
+                if let Err(err) = send_magic_link(&smtp_config, &email, &link).await {
+                    error(format!("Could not send login email: {err}"));
+                }
+            }));
+        },
+        None => debug!("Login email requested for an unknown address"),
+    }
+
+    let html = login_page_(&app_state.tera, true, None)?;
+    Ok((StatusCode::OK, html))
+}
@@ -1,27 +1,30 @@
-use std::{path::PathBuf, str::FromStr as _};
+use std::{path::PathBuf, str::FromStr as _, sync::Arc};
 
 use axum::{
     body::Body,
     extract::{Request, State},
-    http::{header::HeaderMap, Uri},
-    response::Response,
+    http::{header::HeaderMap, StatusCode, Uri},
+    response::{IntoResponse as _, Response},
     routing::get,
-    Router,
-};
-use orangutan_helpers::{
-    generate::generate_website_if_needed,
-    page_metadata,
-    website_id::{website_dir, WebsiteId},
+    Extension, Router,
 };
+#[cfg(feature = "impersonation")]
+use axum_extra::extract::CookieJar;
+use orangutan_helpers::{website_id::WebsiteId, workspace::Workspace, PageMetadata};
 use tower::ServiceExt;
 use tower_http::services::{fs::ServeFileSystemResponseBody, ServeDir, ServeFile};
 use tracing::{debug, trace};
 
+#[cfg(feature = "impersonation")]
+use crate::routes::impersonation_routes;
 use crate::{
-    auth::is_authorized,
+    auth::{is_authorized, RequestFacts},
+    caching::{self, CachePolicy},
+    config::{DISGUISE_FORBIDDEN_PAGES, FORBIDDEN_FILE},
     request_guards::Token,
     routes::debug_routes::log_access,
-    util::{accepts, VecExt as _},
+    sites::Site,
+    util::{accepts, error, VecExt as _},
     AppState, Error,
 };
 
@@ -34,57 +37,213 @@ pub(super) fn router() -> Router<AppState> {
 // #[axum::debug_handler]
 #[tracing::instrument(skip_all, fields(uri))]
 async fn handle_request(
-    State(_app_state): State<AppState>,
+    State(app_state): State<AppState>,
+    Extension(site): Extension<Arc<Site>>,
     uri: Uri,
     token: Option<Token>,
-    ref headers: HeaderMap,
+    #[cfg(feature = "impersonation")] cookies: CookieJar,
+    headers: HeaderMap,
     req: Request<Body>,
-) -> Result<Response<ServeFileSystemResponseBody>, Error> {
+) -> Result<Response, Error> {
     let path = uri.path();
+    let method = req.method().clone();
+
+    // Super admins can preview the website as other profiles.
+    #[cfg(feature = "impersonation")]
+    let impersonated = impersonation_routes::impersonated_profiles(token.as_ref(), &cookies);
+    #[cfg(not(feature = "impersonation"))]
+    let impersonated: Option<Vec<String>> = None;
+    #[cfg(feature = "impersonation")]
+    let token = match impersonated {
+        Some(ref profiles) => Some(impersonation_routes::impersonation_token(profiles)?),
+        None => token,
+    };
 
     let user_profiles: Vec<String> = token.as_ref().map(Token::profiles).unwrap_or_default();
     // debug!("User has profiles {user_profiles:?}");
-    tracing::Span::current().record("profiles", &user_profiles.sorted().join(","));
+    tracing::Span::current().record("profiles", user_profiles.sorted().join(","));
 
-    let website_id = WebsiteId::from(&user_profiles);
-    tracing::Span::current().record("website_id", &website_id.name());
+    let workspace = &site.workspace;
+    let website_id = workspace.website_id(&user_profiles);
+    tracing::Span::current().record("website_id", website_id.name());
 
     // Log access only if the page is HTML.
     // WARN: This solution is far from perfect as someone requesting a page
     //   without setting the `Accept` header would not be logged even though
     //   they’d get the file back.
-    if accepts(headers, mime::TEXT_HTML) {
-        log_access(user_profiles.to_owned(), path.to_owned());
+    // NOTE: Impersonated accesses are not logged, so they don't mess with statistics.
+    if impersonated.is_none() && accepts(&headers, mime::TEXT_HTML) {
+        let tokens = token.as_ref().map(Token::lineage).unwrap_or_default();
+        log_access(&site, user_profiles.to_owned(), path.to_owned(), tokens);
     }
 
     // Generate the website if needed.
-    generate_website_if_needed(&website_id)?;
+    workspace.generate_website_if_needed(&website_id)?;
 
-    let page_relpath = PathBuf::from_str(&path).unwrap();
-    let Some(page_metadata) = page_metadata(&page_relpath)
-        .map_err(orangutan_helpers::generate::Error::CannotReadPageMetadata)?
-    else {
+    let page_relpath = PathBuf::from_str(path).unwrap();
+    // NOTE: Files in a page bundle (e.g. images) inherit the page's metadata.
+    let Some(page_metadata) = workspace.file_metadata(&page_relpath)? else {
         // If metadata can’t be found, it means it’s a static file.
+        if site.strict_static_files {
+            debug!("File <{path}> did not match any page nor rule, denying access (strict mode).");
+            return Err(Error::Forbidden);
+        }
         trace!("File <{path}> did not explicitly allow profiles, serving static file.");
-        return Ok(serve_file(&website_id, req).await);
+        let cache_policy = cache_policy(impersonated.as_ref(), None, &website_id, path);
+        let response = serve_cached_file(workspace, &website_id, cache_policy, req).await;
+        return Ok(with_banner(&app_state, impersonated, path, response).await);
     };
 
-    let allowed_profiles = page_metadata.read_allowed;
-    tracing::Span::current().record("allowed_profiles", &allowed_profiles.join(","));
+    tracing::Span::current().record("allowed_profiles", page_metadata.read_allowed.join(","));
 
-    if is_authorized(token, allowed_profiles) {
-        Ok(serve_file(&website_id, req).await)
+    let request_facts = RequestFacts::new(method, &headers, &website_id);
+    let is_authenticated = token.is_some();
+    if is_authorized(token, &page_metadata, &request_facts) {
+        let cache_policy = cache_policy(
+            impersonated.as_ref(),
+            Some(&page_metadata),
+            &website_id,
+            path,
+        );
+        let response = serve_cached_file(workspace, &website_id, cache_policy, req).await;
+        Ok(with_banner(&app_state, impersonated, path, response).await)
+    } else if is_authenticated && !*DISGUISE_FORBIDDEN_PAGES {
+        debug!("No allowed profile found in token, explaining why.");
+        let mut response = forbidden_page(&app_state, workspace, &website_id, path).await;
+        caching::set_headers(&mut response, CachePolicy::Private, None);
+        Ok(with_banner(&app_state, impersonated, path, response).await)
+    } else if !is_authenticated && basic_auth_enabled(&site) {
+        debug!("No token found, asking for Basic authentication credentials.");
+        Err(Error::Unauthorized)
     } else {
         debug!("No allowed profile found in token.");
         Err(Error::Forbidden)
     }
 }
 
+/// NOTE: Impersonated responses contain a banner, so they must not be cached.
+fn cache_policy(
+    impersonated: Option<&Vec<String>>,
+    page: Option<&PageMetadata>,
+    website_id: &WebsiteId,
+    path: &str,
+) -> CachePolicy {
+    match impersonated {
+        Some(_) => CachePolicy::Private,
+        None => CachePolicy::new(page, website_id, path),
+    }
+}
+
+/// Serves a file the user can read, with caching headers
+/// (answering `304 Not Modified` if the user already has it).
+///
+/// NOTE: Private files are never stored, so they don't need an `ETag`.
+async fn serve_cached_file(
+    workspace: &Workspace,
+    website_id: &WebsiteId,
+    cache_policy: CachePolicy,
+    req: Request<Body>,
+) -> Response {
+    let etag = match cache_policy {
+        CachePolicy::Private => None,
+        _ => match workspace.revision() {
+            Ok(revision) => Some(caching::etag(&revision, website_id)),
+            Err(err) => {
+                error(format!("Could not read website revision: {err}"));
+                None
+            },
+        },
+    };
+    let path = req.uri().path().to_owned();
+    let not_modified = (etag.clone()).filter(|etag| caching::is_not_modified(req.headers(), etag));
+
+    // NOTE: The file is served first, so `304 Not Modified` is only
+    //   answered for files which exist (not the "not found" page).
+    let mut response = serve_file(workspace, website_id, req).await.map(Body::new);
+    if let Some(etag) = not_modified.filter(|_| response.status() == StatusCode::OK) {
+        trace!("File <{path}> not modified.");
+        return caching::not_modified(cache_policy, etag);
+    }
+    caching::set_headers(&mut response, cache_policy, etag);
+    response
+}
+
+/// Tells authenticated users they don't have access to a page,
+/// instead of pretending it doesn't exist (which is confusing when a link expired).
+///
+/// Websites can customize this page by defining a `403.html` file.
+#[cfg_attr(not(feature = "templating"), allow(unused_variables))]
+async fn forbidden_page(
+    app_state: &AppState,
+    workspace: &Workspace,
+    website_id: &WebsiteId,
+    path: &str,
+) -> Response {
+    let custom_page = (workspace.website_dir(website_id)).join(FORBIDDEN_FILE);
+    if custom_page.is_file() {
+        let response = ServeFile::new(custom_page)
+            .oneshot(Request::new(Body::empty()))
+            .await
+            .map_err(|err| match err {})
+            .unwrap();
+        return (StatusCode::FORBIDDEN, response).into_response();
+    }
+
+    #[cfg(feature = "templating")]
+    {
+        use axum::response::Html;
+
+        use crate::{context, util::templating::render};
+
+        match render(&app_state.tera, "forbidden.html", context! {
+            page_title: "Access denied",
+            path,
+            request_access: cfg!(feature = "request-access"),
+            requested: false,
+        }) {
+            Ok(html) => return (StatusCode::FORBIDDEN, Html(html)).into_response(),
+            Err(err) => crate::util::error(format!("Could not render forbidden page: {err}")),
+        }
+    }
+
+    (
+        StatusCode::FORBIDDEN,
+        "403 Forbidden. You don't have access to this page.",
+    )
+        .into_response()
+}
+
+#[cfg(feature = "impersonation")]
+async fn with_banner(
+    app_state: &AppState,
+    impersonated: Option<Vec<String>>,
+    path: &str,
+    response: Response,
+) -> Response {
+    match impersonated {
+        Some(profiles) => {
+            impersonation_routes::add_banner(&app_state.tera, &profiles, path, response).await
+        },
+        None => response,
+    }
+}
+
+#[cfg(not(feature = "impersonation"))]
+async fn with_banner(
+    _app_state: &AppState,
+    _impersonated: Option<Vec<String>>,
+    _path: &str,
+    response: Response,
+) -> Response {
+    response
+}
+
 async fn serve_file(
+    workspace: &Workspace,
     website_id: &WebsiteId,
     req: Request<Body>,
 ) -> Response<ServeFileSystemResponseBody> {
-    let website_dir = website_dir(&website_id);
+    let website_dir = workspace.website_dir(website_id);
 
     let fallback = website_dir.join(crate::config::NOT_FOUND_FILE);
     trace!(
@@ -105,3 +264,13 @@ async fn serve_file(
         .map_err(|err| match err {})
         .unwrap()
 }
+
+#[cfg(feature = "basic-auth")]
+fn basic_auth_enabled(site: &Site) -> bool {
+    site.state.basic_auth.is_enabled()
+}
+
+#[cfg(not(feature = "basic-auth"))]
+fn basic_auth_enabled(_site: &Site) -> bool {
+    false
+}
@@ -3,8 +3,26 @@
 // Copyright: 2023–2024, Rémi Bardon <remi@remibardon.name>
 // License: Mozilla Public License v2.0 (MPL v2.0)
 
+#[cfg(feature = "request-access")]
+pub mod access_request_routes;
+pub mod api_routes;
+#[cfg(feature = "comments")]
+pub mod comments_routes;
 pub mod debug_routes;
+#[cfg(feature = "impersonation")]
+pub mod impersonation_routes;
+#[cfg(feature = "token-generator")]
+pub mod issued_links_routes;
+#[cfg(feature = "link-device")]
+pub mod link_device_routes;
+pub mod logout_routes;
+#[cfg(feature = "magic-link")]
+pub mod magic_link_routes;
 pub mod main_route;
+#[cfg(feature = "oidc")]
+pub mod oidc_routes;
+#[cfg(feature = "share-link")]
+pub mod share_routes;
 pub mod update_content_routes;
 
 use axum::Router;
@@ -12,17 +30,82 @@ use axum::Router;
 use crate::AppState;
 
 pub(super) fn router() -> Router<AppState> {
-    Router::<AppState>::new()
+    #[allow(unused_mut)]
+    let mut router = Router::<AppState>::new()
         .merge(main_route::router())
         .merge(update_content_routes::router())
         .merge(debug_routes::router())
+        .merge(logout_routes::router())
+        .merge(api_routes::router());
+
+    #[cfg(feature = "token-generator")]
+    {
+        router = router.merge(issued_links_routes::router());
+    }
+
+    #[cfg(feature = "link-device")]
+    {
+        router = router.merge(link_device_routes::router());
+    }
+
+    #[cfg(feature = "share-link")]
+    {
+        router = router.merge(share_routes::router());
+    }
+
+    #[cfg(feature = "comments")]
+    {
+        router = router.merge(comments_routes::router());
+    }
+
+    #[cfg(feature = "impersonation")]
+    {
+        router = router.merge(impersonation_routes::router());
+    }
+
+    #[cfg(feature = "request-access")]
+    {
+        router = router.merge(access_request_routes::router());
+    }
+
+    #[cfg(feature = "magic-link")]
+    {
+        router = router.merge(magic_link_routes::router());
+    }
+
+    #[cfg(feature = "oidc")]
+    {
+        router = router.merge(oidc_routes::router());
+    }
+
+    router
 }
 
 #[cfg(feature = "templating")]
 pub(super) fn templates() -> Vec<(&'static str, &'static str)> {
-    vec![
-        vec![("base.html", include_str!("templates/base.html.tera"))],
+    [
+        vec![
+            ("base.html", include_str!("templates/base.html.tera")),
+            (
+                "forbidden.html",
+                include_str!("templates/forbidden.html.tera"),
+            ),
+        ],
         debug_routes::templates(),
+        #[cfg(feature = "token-generator")]
+        issued_links_routes::templates(),
+        #[cfg(feature = "link-device")]
+        link_device_routes::templates(),
+        #[cfg(feature = "share-link")]
+        share_routes::templates(),
+        #[cfg(feature = "comments")]
+        comments_routes::templates(),
+        #[cfg(feature = "impersonation")]
+        impersonation_routes::templates(),
+        #[cfg(feature = "request-access")]
+        access_request_routes::templates(),
+        #[cfg(feature = "magic-link")]
+        magic_link_routes::templates(),
     ]
     .concat()
 }
@@ -0,0 +1,124 @@
+//! Routes allowing users to log in with an OpenID Connect identity provider.
+
+use std::sync::Arc;
+
+use axum::{extract::Query, response::Redirect, routing::get, Extension, Router};
+use axum_extra::extract::{
+    cookie::{Cookie, SameSite},
+    CookieJar,
+};
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::{
+    config::{OIDC_STATE_COOKIE_NAME, TOKEN_COOKIE},
+    oidc::{self, finish_login, start_login, CALLBACK_PATH, OIDC_CONFIG, PENDING_LOGIN_TTL},
+    sites::Site,
+    util::{add_cookie, in_memory_biscuit, redirect_path},
+    AppState, Error,
+};
+
+pub(super) fn router() -> Router<AppState> {
+    Router::<AppState>::new()
+        .route("/_login/oidc", get(login))
+        .route(CALLBACK_PATH, get(callback))
+}
+
+fn config() -> Result<&'static oidc::OidcConfig, Error> {
+    OIDC_CONFIG.as_ref().ok_or(Error::InternalServerError(
+        "OpenID Connect login requested but it is not configured.".to_owned(),
+    ))
+}
+
+#[derive(Deserialize)]
+struct LoginQuery {
+    /// Where to go after logging in.
+    #[serde(default)]
+    path: Option<String>,
+}
+
+/// Only sent back to the callback, and only for the duration of a login.
+fn state_cookie(state: String) -> Cookie<'static> {
+    let mut cookie = TOKEN_COOKIE.companion_cookie(OIDC_STATE_COOKIE_NAME, state);
+    cookie.set_path(CALLBACK_PATH);
+    cookie.set_max_age(time::Duration::try_from(PENDING_LOGIN_TTL).ok());
+    // NOTE: The identity provider redirects to the callback from another site,
+    //   `SameSite=Strict` cookies would not be sent.
+    if cookie.same_site() == Some(SameSite::Strict) {
+        cookie.set_same_site(SameSite::Lax);
+    }
+    cookie
+}
+
+async fn login(
+    Extension(site): Extension<Arc<Site>>,
+    cookies: CookieJar,
+    Query(query): Query<LoginQuery>,
+) -> Result<(CookieJar, Redirect), Error> {
+    let (url, state) = start_login(
+        config()?,
+        &site.state.oidc_logins,
+        site.website_root.as_str(),
+        redirect_path(query.path),
+    )
+    .await?;
+
+    Ok((cookies.add(state_cookie(state)), Redirect::to(&url)))
+}
+
+#[derive(Deserialize)]
+struct CallbackQuery {
+    #[serde(default)]
+    code: Option<String>,
+    #[serde(default)]
+    state: Option<String>,
+    /// Set by the identity provider if the user cancelled for example.
+    #[serde(default)]
+    error: Option<String>,
+}
+
+async fn callback(
+    Extension(site): Extension<Arc<Site>>,
+    cookies: CookieJar,
+    Query(query): Query<CallbackQuery>,
+) -> Result<(CookieJar, Redirect), Error> {
+    let (Some(code), Some(state)) = (query.code, query.state) else {
+        Err(Error::ClientError(format!(
+            "OpenID Connect login failed: {}",
+            query.error.unwrap_or("missing code".to_owned()),
+        )))?
+    };
+
+    let browser_state =
+        (cookies.get(OIDC_STATE_COOKIE_NAME)).map(|cookie| cookie.value().to_owned());
+    let cookies = cookies.remove(state_cookie(String::new()));
+
+    let (profiles, path) = match finish_login(
+        config()?,
+        &site.state.oidc_rules,
+        &site.state.oidc_logins,
+        site.website_root.as_str(),
+        code,
+        &state,
+        browser_state.as_deref(),
+    )
+    .await
+    {
+        Ok(res) => res,
+        Err(err @ (oidc::Error::UnknownState | oidc::Error::StateMismatch)) => {
+            Err(Error::ClientError(format!("{err}")))?
+        },
+        Err(err) => Err(err)?,
+    };
+    if profiles.is_empty() {
+        debug!("No OpenID Connect rule matched, not logging in.");
+        Err(Error::Forbidden)?
+    }
+
+    let biscuit = in_memory_biscuit(profiles).map_err(|err| {
+        Error::InternalServerError(format!("Could not create OpenID Connect token: {err}"))
+    })?;
+    let cookies = add_cookie(&biscuit, cookies)?;
+
+    Ok((cookies, Redirect::to(&path)))
+}
@@ -0,0 +1,176 @@
+//! Routes allowing users to share a single page they have access to.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, Method},
+    response::Html,
+    routing::get,
+    Extension, Form, Router,
+};
+use biscuit_auth::{
+    macros::{block, fact},
+    Biscuit, KeyPair,
+};
+use iso8601_duration::Duration as IsoDuration;
+use orangutan_helpers::config::DEFAULT_PROFILE;
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::{
+    auth::{is_authorized, RequestFacts},
+    config::TOKEN_QUERY_PARAM_NAME,
+    context,
+    request_guards::{parent_facts, Token},
+    sites::Site,
+    util::{self, templating::render},
+    AppState, Error,
+};
+
+pub(super) fn router() -> Router<AppState> {
+    Router::<AppState>::new().route("/_share", get(share_page).post(share))
+}
+
+pub(super) fn templates() -> Vec<(&'static str, &'static str)> {
+    vec![("share.html", include_str!("templates/share.html.tera"))]
+}
+
+fn share_page_(
+    tera: &tera::Tera,
+    path: Option<String>,
+    link: Option<String>,
+) -> Result<Html<String>, Error> {
+    let html = render(
+        tera,
+        "share.html",
+        context! { page_title: "Share a page", path, link },
+    )?;
+
+    Ok(Html(html))
+}
+
+#[derive(Deserialize)]
+struct SharePageQuery {
+    #[serde(default)]
+    path: Option<String>,
+}
+
+async fn share_page(
+    _token: Token,
+    State(app_state): State<AppState>,
+    Query(query): Query<SharePageQuery>,
+) -> Result<Html<String>, Error> {
+    share_page_(&app_state.tera, query.path, None)
+}
+
+#[derive(Deserialize)]
+struct ShareForm {
+    path: String,
+    /// ISO 8601 duration. Empty means the link never expires
+    /// (unless the user's own token expires).
+    #[serde(default)]
+    ttl: String,
+}
+
+async fn share(
+    token: Token,
+    State(app_state): State<AppState>,
+    Extension(site): Extension<Arc<Site>>,
+    headers: HeaderMap,
+    Form(form): Form<ShareForm>,
+) -> Result<Html<String>, Error> {
+    let Some(page_metadata) = (site.workspace)
+        .page_metadata(&PathBuf::from(&form.path))
+        .map_err(orangutan_helpers::generate::Error::CannotReadPageMetadata)?
+    else {
+        Err(Error::ClientError(format!(
+            "<{}> is not a page, it cannot be shared.",
+            form.path
+        )))?
+    };
+    // Sharing a page one cannot read would give a useless link.
+    let website_id = site.workspace.website_id(&token.profiles());
+    let request_facts = RequestFacts::new(Method::GET, &headers, &website_id);
+    if !is_authorized(Some(token.clone()), &page_metadata, &request_facts) {
+        Err(Error::Forbidden)?
+    }
+    let path = page_metadata.path;
+
+    let expiry = match form.ttl.trim() {
+        "" => None,
+        ttl => Some(
+            IsoDuration::parse(ttl)
+                .ok()
+                .and_then(|d| d.to_std())
+                .ok_or(Error::ClientError(format!(
+                    "Invalid expiry '{ttl}'. Use an ISO 8601 duration without years or months."
+                )))?,
+        ),
+    };
+
+    let share_token = share_token(
+        &token,
+        page_metadata.read_allowed.to_vec(),
+        &path,
+        expiry,
+        &site.root_key,
+    )
+    .map_err(|err| Error::InternalServerError(format!("Could not create share link: {err}")))?;
+    let share_token = share_token.to_base64().map_err(|err| {
+        Error::InternalServerError(format!("Could not convert share token to Base64: {err}"))
+    })?;
+    let link = format!(
+        "{}{}?{TOKEN_QUERY_PARAM_NAME}={share_token}",
+        site.website_root.trim_end_matches('/'),
+        path.display(),
+    );
+    debug!("Created share link for <{}>", path.display());
+
+    share_page_(&app_state.tera, Some(form.path), Some(link))
+}
+
+/// Creates a token which can only read the page at `path`.
+fn share_token(
+    token: &Token,
+    read_allowed: Vec<String>,
+    path: &Path,
+    expiry: Option<std::time::Duration>,
+    root_key: &KeyPair,
+) -> Result<Biscuit, biscuit_auth::error::Token> {
+    // NOTE: Attenuating a token cannot remove facts, so attenuating the sharer's
+    //   token would leak all their profiles (used to choose the generated website
+    //   and by admin routes). We create a new token with only the profiles
+    //   allowed to read the page instead, which expires and is revoked
+    //   with the sharer's token.
+    let profiles = token.profiles();
+    let is_super_admin = profiles.contains(&"*".to_owned());
+    let mut builder = Biscuit::builder();
+    for profile in (read_allowed.into_iter())
+        .filter(|p| p != DEFAULT_PROFILE)
+        .filter(|p| is_super_admin || profiles.contains(p))
+    {
+        builder.add_fact(fact!("profile({profile});"))?;
+    }
+    for fact in parent_facts(token) {
+        builder.add_fact(fact)?;
+    }
+    let biscuit = builder.build(root_key)?;
+
+    let mut block = block!(
+        "check if path($path), $path == {path};",
+        path = path.display().to_string(),
+    );
+    let expiry = (token.biscuits().filter_map(util::expiry))
+        .chain(expiry.map(|expiry| SystemTime::now() + expiry))
+        .min();
+    if let Some(expiry) = expiry {
+        block.merge(block!("check if time($time), $time <= {expiry};"));
+    }
+    block.set_context("share_link".to_owned());
+    biscuit.append(block)
+}
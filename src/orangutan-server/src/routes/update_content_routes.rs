@@ -1,7 +1,9 @@
-use axum::{extract::Path, routing::post, Router};
-use orangutan_helpers::generate::{self, *};
+use std::sync::Arc;
 
-use crate::{error, request_guards::REVOKED_TOKENS, AppState};
+use axum::{extract::Path, routing::post, Extension, Router};
+use orangutan_helpers::generate;
+
+use crate::{error, sites::Site, AppState};
 
 pub(super) fn router() -> Router<AppState> {
     Router::<AppState>::new()
@@ -10,30 +12,86 @@ pub(super) fn router() -> Router<AppState> {
 }
 
 /// TODO: [Validate webhook deliveries](https://docs.github.com/en/webhooks/using-webhooks/validating-webhook-deliveries#validating-webhook-deliveries)
-async fn update_content_github() -> Result<(), crate::Error> {
+async fn update_content_github(Extension(site): Extension<Arc<Site>>) -> Result<(), crate::Error> {
+    update_content(&site)
+}
+
+/// Pulls the repository of `site` and regenerates its websites.
+pub fn update_content(site: &Site) -> Result<(), crate::Error> {
+    let workspace = &site.workspace;
+
     // Update repository
-    pull_repository().map_err(Error::CannotPullOutdatedRepository)?;
+    workspace
+        .pull_repository()
+        .map_err(Error::CannotPullOutdatedRepository)?;
 
     // Read revoked tokens list
     // FIXME: This cannot be reverted
-    *REVOKED_TOKENS.write().unwrap() =
-        read_revoked_tokens().map_err(Error::CannotReadRevokedTokens)?;
+    site.read_revoked_tokens()
+        .map_err(Error::CannotReadRevokedTokens)?;
+
+    update_auth_config(site)?;
 
     // Remove outdated websites
-    let state = trash_outdated_websites().map_err(Error::CannotTrashOutdatedWebsites)?;
+    let state = workspace
+        .trash_outdated_websites()
+        .map_err(Error::CannotTrashOutdatedWebsites)?;
 
     // Pre-generate default website as we will access it at some point anyway
-    match generate_default_website().map_err(Error::WebsiteGenerationError) {
+    match workspace
+        .generate_default_website()
+        .map_err(Error::WebsiteGenerationError)
+    {
         Err(err) => {
             error(format!("{err}"));
-            recover_trash(state).map_err(Error::CannotRecoverTrash)?
+            workspace
+                .recover_trash(state)
+                .map_err(Error::CannotRecoverTrash)?
         },
-        Ok(()) => empty_trash(state).map_err(Error::CannotEmptyTrash)?,
+        Ok(()) => workspace
+            .empty_trash(state)
+            .map_err(Error::CannotEmptyTrash)?,
     }
 
     Ok(())
 }
 
+/// Reads authentication methods configured in the repository of `site`.
+#[cfg_attr(
+    not(any(feature = "basic-auth", feature = "magic-link", feature = "oidc")),
+    allow(unused_variables)
+)]
+pub fn update_auth_config(site: &Site) -> Result<(), Error> {
+    #[cfg(any(feature = "basic-auth", feature = "magic-link", feature = "oidc"))]
+    let workspace = &site.workspace;
+
+    // Read Basic authentication credentials
+    #[cfg(feature = "basic-auth")]
+    site.state.basic_auth.set_credentials(
+        workspace
+            .read_basic_auth_credentials()
+            .map_err(Error::CannotReadBasicAuthCredentials)?,
+    );
+
+    // Read users allowed to log in by email
+    #[cfg(feature = "magic-link")]
+    site.state.email_users.set(
+        workspace
+            .read_email_users()
+            .map_err(Error::CannotReadEmailUsers)?,
+    );
+
+    // Read OpenID Connect rules
+    #[cfg(feature = "oidc")]
+    site.state.oidc_rules.set(
+        workspace
+            .read_oidc_rules()
+            .map_err(Error::CannotReadOidcRules)?,
+    );
+
+    Ok(())
+}
+
 async fn update_content_other(Path(source): Path<String>) -> crate::Error {
     crate::Error::ClientError(format!("Source '{source}' is not supported."))
 }
@@ -46,6 +104,15 @@ pub enum Error {
     CannotPullOutdatedRepository(generate::Error),
     #[error("Cannot read revoked tokens: {0}")]
     CannotReadRevokedTokens(generate::Error),
+    #[cfg(feature = "basic-auth")]
+    #[error("Cannot read Basic authentication credentials: {0}")]
+    CannotReadBasicAuthCredentials(generate::Error),
+    #[cfg(feature = "magic-link")]
+    #[error("Cannot read users: {0}")]
+    CannotReadEmailUsers(generate::Error),
+    #[cfg(feature = "oidc")]
+    #[error("Cannot read OpenID Connect rules: {0}")]
+    CannotReadOidcRules(generate::Error),
     #[error("Cannot trash outdated websites: {0}")]
     CannotTrashOutdatedWebsites(generate::Error),
     #[error("Cannot recover trash: {0}")]
@@ -0,0 +1,457 @@
+//! Virtual hosting: one process can serve multiple websites,
+//! chosen using the request's `Host` header.
+//!
+//! Sites are declared in a JSON file whose path is given by the `SITES_FILE`
+//! environment variable:
+//!
+//! ```json
+//! [
+//!   {
+//!     "host": "blog.example.org",
+//!     "repository": "https://github.com/example/blog.git",
+//!     "key": "blog"
+//!   }
+//! ]
+//! ```
+//!
+//! - `host`: Value of the `Host` header (port excluded).
+//! - `repository`: Git repository of the website.
+//! - `key`: Name of the site's root Biscuit key, read like the default one
+//!   (`KEY_<key>` environment variable, or `<key>.key` in the keys directory
+//!   if `KEYS_MODE=LOCAL`). Each site needs its own key, so tokens
+//!   of a site cannot be used on another one.
+//! - `base_dir` (optional): Where generated websites and data files are stored.
+//!   Defaults to `.orangutan/sites/<host>`.
+//! - `website_root` (optional): URL used in links (e.g. share links).
+//!   Defaults to `https://<host>`.
+//! - `strict_static_files` (optional): Deny files without metadata nor rule.
+//!   Defaults to `STRICT_STATIC_FILES`.
+//!
+//! Requests for other hosts are served by the default site,
+//! configured with `WEBSITE_REPOSITORY` like when `SITES_FILE` is not set.
+//!
+//! Each site has its own generated websites (hence its own profiles),
+//! data files (e.g. comments, issued links), revoked tokens, logs and authentication methods
+//! (Basic authentication credentials, email users and OpenID Connect rules
+//! are read from the site's repository). Webhooks (e.g. `POST /update-content/github`)
+//! and admin pages only affect the site matching the `Host` header.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use axum::{
+    extract::{Request, State},
+    http::header::HOST,
+    middleware::Next,
+    response::Response,
+};
+use biscuit_auth::KeyPair;
+use lazy_static::lazy_static;
+use orangutan_helpers::{
+    config::BASE_DIR,
+    generate,
+    readers::keys_reader::KeysReader,
+    workspace::{Workspace, WORKSPACE},
+};
+use serde::Deserialize;
+use tracing::trace;
+
+#[cfg(feature = "basic-auth")]
+use crate::basic_auth::BasicAuth;
+#[cfg(feature = "link-device")]
+use crate::device_codes::DeviceCodes;
+#[cfg(feature = "magic-link")]
+use crate::magic_link::{EmailUsers, LoginAttempts};
+#[cfg(feature = "oidc")]
+use crate::oidc::{OidcLogins, OidcRules};
+#[cfg(feature = "website-root")]
+use crate::util::WebsiteRoot;
+#[cfg(feature = "request-access")]
+use crate::{access_requests::AccessRequests, config::ACCESS_REQUESTS_FILE};
+#[cfg(feature = "comments")]
+use crate::{comments::Comments, config::COMMENTS_DIR};
+#[cfg(feature = "token-generator")]
+use crate::{config::ISSUED_LINKS_FILE, issued_links::IssuedLinks};
+use crate::{
+    config::{REDEMPTIONS_FILE, ROOT_KEY, STRICT_STATIC_FILES},
+    redemptions::Redemptions,
+    routes::debug_routes::{AccessLog, ErrorLog},
+    AppState,
+};
+
+lazy_static! {
+    /// The site configured with environment variables, used for unknown hosts
+    /// and by code which doesn't have access to the request.
+    pub static ref DEFAULT_SITE: Arc<Site> = Arc::new(Site {
+        host: None,
+        state: SiteState::new(&WORKSPACE.base_dir),
+        workspace: WORKSPACE.clone(),
+        root_key: KeyPair::from(&ROOT_KEY.private()),
+        #[cfg(feature = "website-root")]
+        website_root: WebsiteRoot::try_from_env().unwrap_or_default(),
+        strict_static_files: *STRICT_STATIC_FILES,
+    });
+}
+
+tokio::task_local! {
+    static CURRENT_SITE: Arc<Site>;
+}
+
+/// A website served by Orangutan.
+#[derive(Debug)]
+pub struct Site {
+    /// `None` for the default site.
+    pub host: Option<String>,
+    pub workspace: Arc<Workspace>,
+    pub root_key: KeyPair,
+    #[cfg(feature = "website-root")]
+    pub website_root: WebsiteRoot,
+    /// See [`STRICT_STATIC_FILES`].
+    pub strict_static_files: bool,
+    pub state: SiteState,
+}
+
+impl Site {
+    /// Path of the data file (or directory) `name` of this site.
+    pub fn data_file(
+        &self,
+        name: &str,
+    ) -> PathBuf {
+        self.workspace.base_dir.join(name)
+    }
+
+    /// Reads the revoked tokens list of the site's repository.
+    pub fn read_revoked_tokens(&self) -> Result<(), generate::Error> {
+        *self.state.revoked_tokens.write().unwrap() = self.workspace.read_revoked_tokens()?;
+        Ok(())
+    }
+
+    /// Reads data files saved by previous runs.
+    pub fn read_data_files(&self) -> Result<(), crate::Error> {
+        *self.state.redemptions.write().unwrap() =
+            Redemptions::read(&self.data_file(REDEMPTIONS_FILE))?;
+        #[cfg(feature = "token-generator")]
+        {
+            *self.state.issued_links.write().unwrap() =
+                IssuedLinks::read(&self.data_file(ISSUED_LINKS_FILE))?;
+        }
+        #[cfg(feature = "request-access")]
+        {
+            *self.state.access_requests.write().unwrap() =
+                AccessRequests::read(&self.data_file(ACCESS_REQUESTS_FILE))?;
+        }
+        Ok(())
+    }
+}
+
+/// What a site keeps in memory: its data, authentication methods and logs.
+#[derive(Debug)]
+pub struct SiteState {
+    /// Revocation identifiers of revoked tokens (from the repository,
+    /// and revoked since it was read).
+    pub revoked_tokens: RwLock<HashSet<Vec<u8>>>,
+    /// How many times usage-limited refresh tokens have been redeemed.
+    pub redemptions: RwLock<Redemptions>,
+    #[cfg(feature = "token-generator")]
+    pub issued_links: RwLock<IssuedLinks>,
+    #[cfg(feature = "request-access")]
+    pub access_requests: RwLock<AccessRequests>,
+    #[cfg(feature = "comments")]
+    pub comments: Comments,
+    #[cfg(feature = "basic-auth")]
+    pub basic_auth: BasicAuth,
+    #[cfg(feature = "magic-link")]
+    pub email_users: EmailUsers,
+    /// NOTE: Limits are short-lived, there is no need to persist them.
+    #[cfg(feature = "magic-link")]
+    pub login_attempts: std::sync::Mutex<LoginAttempts>,
+    #[cfg(feature = "oidc")]
+    pub oidc_rules: OidcRules,
+    #[cfg(feature = "oidc")]
+    pub oidc_logins: OidcLogins,
+    #[cfg(feature = "link-device")]
+    pub device_codes: DeviceCodes,
+    pub errors: RwLock<Vec<ErrorLog>>,
+    /// Access logs, per "user".
+    pub access_logs: RwLock<Vec<AccessLog>>,
+}
+
+impl SiteState {
+    #[cfg_attr(not(feature = "comments"), allow(unused_variables))]
+    fn new(base_dir: &Path) -> Self {
+        Self {
+            revoked_tokens: RwLock::default(),
+            redemptions: RwLock::default(),
+            #[cfg(feature = "token-generator")]
+            issued_links: RwLock::default(),
+            #[cfg(feature = "request-access")]
+            access_requests: RwLock::default(),
+            #[cfg(feature = "comments")]
+            comments: Comments::new(base_dir.join(COMMENTS_DIR)),
+            #[cfg(feature = "basic-auth")]
+            basic_auth: BasicAuth::default(),
+            #[cfg(feature = "magic-link")]
+            email_users: EmailUsers::default(),
+            #[cfg(feature = "magic-link")]
+            login_attempts: std::sync::Mutex::default(),
+            #[cfg(feature = "oidc")]
+            oidc_rules: OidcRules::default(),
+            #[cfg(feature = "oidc")]
+            oidc_logins: OidcLogins::default(),
+            #[cfg(feature = "link-device")]
+            device_codes: DeviceCodes::default(),
+            errors: RwLock::default(),
+            access_logs: RwLock::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SiteConfig {
+    host: String,
+    repository: String,
+    key: String,
+    #[serde(default)]
+    base_dir: Option<PathBuf>,
+    #[cfg_attr(not(feature = "website-root"), allow(dead_code))]
+    #[serde(default)]
+    website_root: Option<String>,
+    #[serde(default)]
+    strict_static_files: Option<bool>,
+}
+
+impl SiteConfig {
+    /// Reads the site's root key, from environment variables or the keys directory.
+    fn read_key(&self) -> Result<KeyPair, String> {
+        (<dyn KeysReader>::detect().get_biscuit_key(&self.key)).map_err(|err| {
+            format!(
+                "Could not read key '{}' of <{}>: {err}",
+                self.key, self.host
+            )
+        })
+    }
+
+    fn into_site(
+        self,
+        root_key: KeyPair,
+    ) -> Site {
+        let base_dir =
+            (self.base_dir.clone()).unwrap_or_else(|| BASE_DIR.join("sites").join(&self.host));
+        Site {
+            state: SiteState::new(&base_dir),
+            workspace: Arc::new(Workspace::new(&base_dir, self.repository)),
+            root_key,
+            #[cfg(feature = "website-root")]
+            website_root: WebsiteRoot::from(
+                (self.website_root).unwrap_or(format!("https://{}", self.host)),
+            ),
+            strict_static_files: (self.strict_static_files).unwrap_or(*STRICT_STATIC_FILES),
+            host: Some(self.host),
+        }
+    }
+}
+
+/// All sites served by Orangutan, by host.
+#[derive(Debug)]
+pub struct Sites {
+    default: Arc<Site>,
+    by_host: HashMap<String, Arc<Site>>,
+}
+
+impl Default for Sites {
+    fn default() -> Self {
+        Self {
+            default: DEFAULT_SITE.clone(),
+            by_host: HashMap::new(),
+        }
+    }
+}
+
+impl Sites {
+    pub fn try_from_env() -> Result<Self, String> {
+        match std::env::var("SITES_FILE") {
+            Ok(path) => {
+                let file = File::open(&path)
+                    .map_err(|err| format!("Could not open sites file <{path}>: {err}"))?;
+                let configs: Vec<SiteConfig> = serde_json::from_reader(file)
+                    .map_err(|err| format!("Invalid sites file <{path}>: {err}"))?;
+                Self::from_configs(configs, SiteConfig::read_key)
+            },
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    fn from_configs(
+        configs: Vec<SiteConfig>,
+        read_key: impl Fn(&SiteConfig) -> Result<KeyPair, String>,
+    ) -> Result<Self, String> {
+        let mut sites = Self::default();
+        for config in configs {
+            let host = config.host.to_lowercase();
+            if sites.by_host.contains_key(&host) {
+                Err(format!("Host <{host}> is declared multiple times."))?
+            }
+            let root_key = read_key(&config)?;
+            let site = SiteConfig { host, ..config }.into_site(root_key);
+            // NOTE: Sites sharing a key would accept each other's tokens.
+            let public_key = site.root_key.public();
+            if let Some(other) = (sites.all()).find(|other| other.root_key.public() == public_key) {
+                Err(format!(
+                    "<{}> must not use the same key as {}.",
+                    site.host.as_deref().unwrap_or_default(),
+                    (other.host.as_ref())
+                        .map_or("the default site".to_owned(), |host| format!("<{host}>")),
+                ))?
+            }
+            sites
+                .by_host
+                .insert(site.host.clone().unwrap(), Arc::new(site));
+        }
+        Ok(sites)
+    }
+
+    /// Returns the site serving `host` (which might contain a port).
+    pub fn get(
+        &self,
+        host: Option<&str>,
+    ) -> Arc<Site> {
+        let host = host.map(|host| match host.rsplit_once(':') {
+            // NOTE: IPv6 addresses contain `:`, but are enclosed in brackets.
+            Some((host, port)) if !port.contains(']') => host.to_lowercase(),
+            _ => host.to_lowercase(),
+        });
+        (host.and_then(|host| self.by_host.get(&host)))
+            .unwrap_or(&self.default)
+            .clone()
+    }
+
+    /// The default site first, then other sites.
+    pub fn all(&self) -> impl Iterator<Item = &Arc<Site>> {
+        std::iter::once(&self.default).chain(self.by_host.values())
+    }
+}
+
+/// The site serving the current request
+/// (or the default site outside of a request).
+///
+/// NOTE: Handlers should prefer the [`Site`] request extension,
+///   this is for code which doesn't have access to the request
+///   (e.g. token extractors or error responses).
+pub fn current() -> Arc<Site> {
+    (CURRENT_SITE.try_with(Arc::clone)).unwrap_or_else(|_| DEFAULT_SITE.clone())
+}
+
+/// Finds the site matching the `Host` header, and makes it available
+/// as a request extension and through [`current`].
+pub async fn site_middleware(
+    State(app_state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    // NOTE: HTTP/2 requests use the `:authority` pseudo-header instead of `Host`.
+    let host = (req.uri().host().map(ToOwned::to_owned))
+        .or_else(|| (req.headers().get(HOST)?.to_str().ok()).map(ToOwned::to_owned));
+    let site = app_state.sites.get(host.as_deref());
+    trace!("Serving site {:?}", site.host);
+
+    req.extensions_mut().insert(site.clone());
+    scope(site, next.run(req)).await
+}
+
+/// Same as [`scope`], for blocking code (e.g. in [`tokio::task::spawn_blocking`]).
+pub fn sync_scope<R>(
+    site: Arc<Site>,
+    f: impl FnOnce() -> R,
+) -> R {
+    CURRENT_SITE.sync_scope(site, f)
+}
+
+/// Runs `f` as if it served `site`, e.g. for tasks spawned by a request.
+pub async fn scope<F: Future>(
+    site: Arc<Site>,
+    f: F,
+) -> F::Output {
+    CURRENT_SITE.scope(site, f).await
+}
+
+#[cfg(test)]
+mod tests {
+    use biscuit_auth::{KeyPair, PrivateKey};
+    use sha2::{Digest as _, Sha256};
+
+    use super::{SiteConfig, Sites};
+    use crate::config::ROOT_KEY;
+
+    /// Same as [`Sites::from_configs`], without environment variables
+    /// (tests run concurrently): keys are derived from their name,
+    /// and `default` is the default site's key.
+    fn from_configs(configs: Vec<SiteConfig>) -> Result<Sites, String> {
+        Sites::from_configs(configs, |config| {
+            if config.key == "default" {
+                return Ok(KeyPair::from(&ROOT_KEY.private()));
+            }
+            let private_key = PrivateKey::from_bytes(&Sha256::digest(&config.key)).unwrap();
+            Ok(KeyPair::from(&private_key))
+        })
+    }
+
+    fn config_with_key(
+        host: &str,
+        key: &str,
+    ) -> SiteConfig {
+        serde_json::from_str(&format!(
+            r#"{{ "host": "{host}", "repository": "https://example.org/{host}.git", "key": "{key}" }}"#
+        ))
+        .unwrap()
+    }
+
+    /// A site with its own key, named after its host.
+    fn config(host: &str) -> SiteConfig {
+        config_with_key(host, host)
+    }
+
+    #[test]
+    fn test_get() {
+        let sites = from_configs(vec![config("blog.example.org")]).unwrap();
+        assert_eq!(
+            sites.get(Some("blog.example.org")).host.as_deref(),
+            Some("blog.example.org"),
+        );
+        assert_eq!(
+            sites.get(Some("Blog.Example.org:8080")).host.as_deref(),
+            Some("blog.example.org"),
+        );
+        assert_eq!(sites.get(Some("example.org")).host, None);
+        assert_eq!(sites.get(Some("[::1]")).host, None);
+        assert_eq!(sites.get(None).host, None);
+        assert_eq!(
+            sites.get(Some("blog.example.org")).workspace.repository,
+            "https://example.org/blog.example.org.git",
+        );
+    }
+
+    #[test]
+    fn test_duplicate_hosts() {
+        assert!(from_configs(vec![config("a.example.org"), config("A.example.org")]).is_err());
+    }
+
+    #[test]
+    fn test_keys_are_required_and_distinct() {
+        let without_key = serde_json::from_str::<SiteConfig>(
+            r#"{ "host": "c.example.org", "repository": "https://example.org/c.git" }"#,
+        );
+        assert!(without_key.is_err());
+
+        let c = config("c.example.org");
+        let shared_key = config_with_key("d.example.org", &c.key);
+        assert!(from_configs(vec![c, shared_key]).is_err());
+
+        let default_key = config_with_key("e.example.org", "default");
+        assert!(from_configs(vec![default_key]).is_err());
+    }
+}
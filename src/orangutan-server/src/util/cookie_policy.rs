@@ -0,0 +1,243 @@
+//! Attributes of the token cookie, configured with environment variables:
+//!
+//! - `TOKEN_COOKIE_NAME` (defaults to `token`)
+//! - `TOKEN_COOKIE_DOMAIN` (e.g. `example.org` to share the session with subdomains,
+//!   defaults to the current host only)
+//! - `TOKEN_COOKIE_MAX_AGE`: ISO 8601 duration without years or months
+//!   (defaults to 5 years, capped at the token's own expiry)
+//! - `TOKEN_COOKIE_SAME_SITE`: `strict` (default), `lax` or `none`
+//! - `TOKEN_COOKIE_SECURE`: `true` (default) or `false`
+//!   (`false` is meant for plain-HTTP `localhost`)
+
+use std::time::SystemTime;
+
+use axum_extra::extract::cookie::{Cookie, SameSite};
+use iso8601_duration::Duration as IsoDuration;
+use time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct CookiePolicy {
+    pub name: String,
+    pub domain: Option<String>,
+    pub max_age: Duration,
+    pub same_site: SameSite,
+    pub secure: bool,
+}
+
+impl Default for CookiePolicy {
+    fn default() -> Self {
+        Self {
+            name: "token".to_owned(),
+            domain: None,
+            max_age: Duration::days(365 * 5),
+            same_site: SameSite::Strict,
+            secure: true,
+        }
+    }
+}
+
+impl CookiePolicy {
+    pub fn try_from_env() -> Result<Self, String> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let default = Self::default();
+        let max_age = match var("TOKEN_COOKIE_MAX_AGE") {
+            Some(max_age) => (IsoDuration::parse(&max_age).ok())
+                .and_then(|d| d.to_std())
+                .and_then(|d| Duration::try_from(d).ok())
+                .ok_or(format!(
+                    "Invalid `TOKEN_COOKIE_MAX_AGE` '{max_age}'. Use an ISO 8601 duration without years or months."
+                ))?,
+            None => default.max_age,
+        };
+        let same_site = match var("TOKEN_COOKIE_SAME_SITE").as_deref() {
+            Some("strict") | None => SameSite::Strict,
+            Some("lax") => SameSite::Lax,
+            Some("none") => SameSite::None,
+            Some(same_site) => Err(format!(
+                "Invalid `TOKEN_COOKIE_SAME_SITE` '{same_site}' (expected 'strict', 'lax' or 'none')."
+            ))?,
+        };
+        let secure = match var("TOKEN_COOKIE_SECURE").as_deref() {
+            Some("true") | None => true,
+            Some("false") => false,
+            Some(secure) => Err(format!(
+                "Invalid `TOKEN_COOKIE_SECURE` '{secure}' (expected 'true' or 'false')."
+            ))?,
+        };
+        // NOTE: Browsers reject `SameSite=None` cookies without `Secure`.
+        if same_site == SameSite::None && !secure {
+            Err("`TOKEN_COOKIE_SAME_SITE=none` requires `TOKEN_COOKIE_SECURE=true`.")?
+        }
+
+        Ok(Self {
+            name: var("TOKEN_COOKIE_NAME")
+                .filter(|name| !name.is_empty())
+                .unwrap_or(default.name),
+            domain: var("TOKEN_COOKIE_DOMAIN").filter(|domain| !domain.is_empty()),
+            max_age,
+            same_site,
+            secure,
+        })
+    }
+
+    /// Builds the token cookie. It never outlives `expires_at`, if known.
+    pub fn cookie(
+        &self,
+        value: String,
+        expires_at: Option<SystemTime>,
+    ) -> Cookie<'static> {
+        let max_age = match expires_at {
+            Some(expires_at) => {
+                let remaining = (expires_at.duration_since(SystemTime::now()))
+                    .ok()
+                    .and_then(|d| Duration::try_from(d).ok())
+                    .unwrap_or(Duration::ZERO);
+                self.max_age.min(remaining)
+            },
+            None => self.max_age,
+        };
+        let mut cookie = self.companion_cookie(&self.name, value);
+        cookie.set_max_age(max_age);
+        cookie
+    }
+
+    /// Builds a session cookie with the same attributes as the token cookie
+    /// (e.g. to remember someone is impersonating other profiles).
+    ///
+    /// NOTE: It can also be passed to [`CookieJar::remove`].
+    ///
+    /// [`CookieJar::remove`]: axum_extra::extract::CookieJar::remove
+    pub fn companion_cookie(
+        &self,
+        name: &str,
+        value: String,
+    ) -> Cookie<'static> {
+        let mut cookie = Cookie::build((name.to_owned(), value)).path("/").build();
+        if let Some(ref domain) = self.domain {
+            cookie.set_domain(domain.clone());
+        }
+        cookie.set_http_only(true);
+        cookie.set_secure(self.secure);
+        cookie.set_same_site(self.same_site);
+        cookie
+    }
+
+    /// Name of the cookie storing a token passed as a query param (e.g. a share link).
+    pub fn query_token_cookie_name(&self) -> String {
+        format!("{}_shared", self.name)
+    }
+
+    /// Builds the cookie storing a token passed as a query param.
+    ///
+    /// NOTE: It is scoped to `path`, like the token was when it was in the URL,
+    ///   and doesn't replace the user's own token cookie.
+    pub fn query_token_cookie(
+        &self,
+        value: String,
+        path: &str,
+        expires_at: Option<SystemTime>,
+    ) -> Cookie<'static> {
+        let mut cookie = self.cookie(value, expires_at);
+        cookie.set_name(self.query_token_cookie_name());
+        cookie.set_path(path.to_owned());
+        cookie
+    }
+
+    /// Name of the cookie listing the paths of query token cookies,
+    /// so they can be removed when logging out.
+    pub fn query_token_paths_cookie_name(&self) -> String {
+        format!("{}_shared_paths", self.name)
+    }
+
+    /// Builds the cookie listing the paths of query token cookies.
+    ///
+    /// NOTE: It lives as long as the longest query token cookie can.
+    pub fn query_token_paths_cookie(
+        &self,
+        paths: &[String],
+    ) -> Cookie<'static> {
+        let value = serde_urlencoded::to_string(
+            paths.iter().map(|path| ("path", path)).collect::<Vec<_>>(),
+        )
+        .unwrap_or_default();
+        let mut cookie = self.companion_cookie(&self.query_token_paths_cookie_name(), value);
+        cookie.set_max_age(self.max_age);
+        cookie
+    }
+
+    /// A cookie matching the token cookie's name, path and domain,
+    /// so it can be passed to [`CookieJar::remove`].
+    ///
+    /// [`CookieJar::remove`]: axum_extra::extract::CookieJar::remove
+    pub fn removal_cookie(&self) -> Cookie<'static> {
+        self.companion_cookie(&self.name, String::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        time::{Duration as StdDuration, SystemTime},
+    };
+
+    use axum_extra::extract::cookie::SameSite;
+    use time::Duration;
+
+    use super::CookiePolicy;
+
+    fn policy(vars: &[(&str, &str)]) -> Result<CookiePolicy, String> {
+        let vars: HashMap<&str, &str> = vars.iter().cloned().collect();
+        CookiePolicy::from_vars(|name| vars.get(name).map(|v| v.to_string()))
+    }
+
+    #[test]
+    fn test_from_vars() {
+        let policy = policy(&[
+            ("TOKEN_COOKIE_NAME", "session"),
+            ("TOKEN_COOKIE_DOMAIN", "example.org"),
+            ("TOKEN_COOKIE_MAX_AGE", "P30D"),
+            ("TOKEN_COOKIE_SAME_SITE", "lax"),
+            ("TOKEN_COOKIE_SECURE", "false"),
+        ])
+        .unwrap();
+        assert_eq!(policy.name, "session");
+        assert_eq!(policy.domain.as_deref(), Some("example.org"));
+        assert_eq!(policy.max_age, Duration::days(30));
+        assert_eq!(policy.same_site, SameSite::Lax);
+        assert!(!policy.secure);
+
+        assert!(super::CookiePolicy::default().secure);
+        assert!(self::policy(&[("TOKEN_COOKIE_MAX_AGE", "P1Y")]).is_err());
+        assert!(self::policy(&[("TOKEN_COOKIE_SAME_SITE", "none")]).is_ok());
+        assert!(self::policy(&[
+            ("TOKEN_COOKIE_SAME_SITE", "none"),
+            ("TOKEN_COOKIE_SECURE", "false"),
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_max_age_capped_at_expiry() {
+        let policy = CookiePolicy::default();
+        let cookie = policy.cookie("abc".to_owned(), None);
+        assert_eq!(cookie.max_age(), Some(policy.max_age));
+
+        let expires_at = SystemTime::now() + StdDuration::from_secs(3600);
+        let cookie = policy.cookie("abc".to_owned(), Some(expires_at));
+        assert!(cookie.max_age().unwrap() <= Duration::hours(1));
+        assert!(cookie.max_age().unwrap() > Duration::minutes(59));
+    }
+
+    #[test]
+    fn test_companion_cookie_follows_policy() {
+        let policy = policy(&[("TOKEN_COOKIE_SECURE", "false")]).unwrap();
+        let cookie = policy.companion_cookie("impersonate", "amis".to_owned());
+        assert_eq!(cookie.secure(), Some(false));
+        assert_eq!(cookie.same_site(), Some(SameSite::Strict));
+        assert_eq!(cookie.max_age(), None);
+    }
+}
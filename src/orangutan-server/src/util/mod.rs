@@ -1,49 +1,128 @@
+mod cookie_policy;
 #[cfg(feature = "templating")]
 pub mod templating;
-#[cfg(feature = "token-generator")]
+#[cfg(feature = "website-root")]
 mod website_root;
 
-use axum_extra::extract::{
-    cookie::{Cookie, SameSite},
-    CookieJar,
+use std::{
+    fs::{self, File},
+    io::{self, Write as _},
+    path::Path,
+    time::SystemTime,
 };
+
+use axum::http::Uri;
+use axum_extra::extract::CookieJar;
 use biscuit_auth::{
     builder::{Fact, Term},
     Biscuit,
 };
-use chrono::Utc;
-use time::Duration;
-use tracing::error;
+use chrono::{DateTime, Utc};
+use tracing::{debug, error};
 
-#[cfg(feature = "token-generator")]
+pub use self::cookie_policy::CookiePolicy;
+#[cfg(feature = "website-root")]
 pub use self::website_root::WebsiteRoot;
 use crate::{
-    config::TOKEN_COOKIE_NAME,
-    routes::debug_routes::{ErrorLog, ERRORS},
+    config::{REFRESH_TOKEN_QUERY_PARAM_NAME, TOKEN_COOKIE, TOKEN_QUERY_PARAM_NAME},
+    routes::debug_routes::ErrorLog,
+    sites,
 };
 
+/// Writes `contents` to a temporary file then renames it to `file_path`,
+/// so a crash never leaves a half-written file behind.
+pub fn write_atomically(
+    file_path: &Path,
+    contents: impl AsRef<[u8]>,
+) -> io::Result<()> {
+    let mut tmp_file_name = file_path.file_name().unwrap_or_default().to_owned();
+    tmp_file_name.push(".tmp");
+    let tmp_file_path = file_path.with_file_name(tmp_file_name);
+
+    let mut file = File::create(&tmp_file_path)?;
+    file.write_all(contents.as_ref())?;
+    file.sync_all()?;
+    fs::rename(tmp_file_path, file_path)
+}
+
+/// Logs `err`, and keeps it so admins of the current site can see it.
 pub fn error(err: String) {
-    ERRORS.write().unwrap().push(ErrorLog {
+    (sites::current().state.errors.write().unwrap()).push(ErrorLog {
         timestamp: Utc::now(),
         line: err.to_owned(),
     });
     error!(err);
 }
 
+/// Creates a Biscuit with the given profiles, without any check.
+///
+/// WARN: Such tokens never expire, only give them to users
+///   whose identity was verified (like access tokens baked from refresh tokens).
+#[cfg(any(feature = "impersonation", feature = "basic-auth", feature = "oidc"))]
+pub fn in_memory_biscuit(
+    profiles: impl IntoIterator<Item = String>
+) -> Result<Biscuit, biscuit_auth::error::Token> {
+    use biscuit_auth::macros::fact;
+
+    let mut builder = Biscuit::builder();
+    for profile in profiles {
+        builder.add_fact(fact!("profile({profile});"))?;
+    }
+    builder.build(&crate::sites::current().root_key)
+}
+
+/// Whether `path` is a path on the current host, and not a URL pointing elsewhere.
+///
+/// NOTE: Browsers treat `\` like `/` and ignore tabs and newlines,
+///   so `/\evil.example` would be a protocol-relative URL too.
+pub fn is_local_path(path: &str) -> bool {
+    path.starts_with('/')
+        && !matches!(path.chars().nth(1), Some('/' | '\\'))
+        && !path.chars().any(char::is_control)
+}
+
+/// Only allow local paths, to avoid open redirects.
+pub fn redirect_path(path: Option<String>) -> String {
+    match path {
+        Some(path) if is_local_path(&path) => path,
+        _ => "/".to_owned(),
+    }
+}
+
+/// Returns the profiles in `biscuit`, ignoring malformed `profile` facts.
 pub fn profiles(biscuit: &Biscuit) -> Vec<String> {
-    biscuit
-        .authorizer()
-        .unwrap()
-        .query_all("data($name) <- profile($name)")
-        .unwrap()
-        .iter()
-        .map(|f: &Fact| match f.predicate.terms.get(0).unwrap() {
-            Term::Str(s) => s.clone(),
-            t => panic!("Term {t} should be of type String"),
+    let facts: Vec<Fact> = match (biscuit.authorizer())
+        .and_then(|mut authorizer| authorizer.query_all("data($name) <- profile($name)"))
+    {
+        Ok(facts) => facts,
+        Err(err) => {
+            debug!("Could not read profiles from biscuit: {err}");
+            return vec![];
+        },
+    };
+    (facts.iter())
+        .filter_map(|f| match f.predicate.terms.first() {
+            Some(Term::Str(s)) => Some(s.clone()),
+            t => {
+                debug!("Ignoring invalid profile {t:?}");
+                None
+            },
         })
         .collect()
 }
 
+/// Returns the `nonce` and `max_uses` of a usage-limited refresh token,
+/// or `None` if the token can be used any number of times.
+pub fn usage_limit(biscuit: &Biscuit) -> Option<(String, u32)> {
+    let facts: Vec<(String, i64)> = biscuit
+        .authorizer()
+        .ok()?
+        .query_all("data($nonce, $max_uses) <- nonce($nonce), max_uses($max_uses)")
+        .ok()?;
+    let (nonce, max_uses) = facts.into_iter().next()?;
+    Some((nonce, u32::try_from(max_uses).unwrap_or_default()))
+}
+
 pub fn add_padding(base64_string: &str) -> String {
     // If the base64 string is already padded, don't do anything.
     if base64_string.ends_with("=") {
@@ -59,6 +138,76 @@ pub fn add_padding(base64_string: &str) -> String {
     }
 }
 
+/// Returns the earliest expiry date of `biscuit`, if one of its blocks
+/// contains a `time($time), $time <= <date>` check.
+///
+/// NOTE: Biscuits don't have an expiry field, so we read block sources
+///   the same way [`EXPIRY_BLOCK_CONTEXT`] checks are written.
+///
+/// [`EXPIRY_BLOCK_CONTEXT`]: orangutan_refresh_token::EXPIRY_BLOCK_CONTEXT
+pub fn expiry(biscuit: &Biscuit) -> Option<SystemTime> {
+    (0..biscuit.block_count())
+        .filter_map(|i| biscuit.print_block_source(i).ok())
+        .flat_map(|source| {
+            (source.lines())
+                .filter(|line| line.contains("time($time)"))
+                .filter_map(|line| line.split("$time <= ").nth(1))
+                .filter_map(|date| {
+                    let date = date.split([',', ';', ' ']).next()?;
+                    DateTime::parse_from_rfc3339(date).ok()
+                })
+                .collect::<Vec<_>>()
+        })
+        .min()
+        .map(SystemTime::from)
+}
+
+/// Query params which contain secrets, and must not appear in logs.
+const REDACTED_QUERY_PARAMS: [&str; 3] = [
+    TOKEN_QUERY_PARAM_NAME,
+    REFRESH_TOKEN_QUERY_PARAM_NAME,
+    // OpenID Connect authorization codes.
+    "code",
+];
+
+/// Returns `uri` without the `names` query params, keeping other params
+/// in order and properly URL-encoded.
+pub fn uri_without_query_params(
+    uri: &Uri,
+    names: &[&str],
+) -> String {
+    let query: Vec<(String, String)> =
+        serde_urlencoded::from_str(uri.query().unwrap_or_default()).unwrap_or_default();
+    let query: Vec<(String, String)> = (query.into_iter())
+        .filter(|(k, _)| !names.contains(&k.as_str()))
+        .collect();
+    match serde_urlencoded::to_string(query) {
+        Ok(query) if !query.is_empty() => format!("{}?{query}", uri.path()),
+        _ => uri.path().to_owned(),
+    }
+}
+
+/// Formats `uri` for logs, hiding the values of [`REDACTED_QUERY_PARAMS`].
+pub fn redact_uri(uri: &Uri) -> String {
+    let Some(query) = uri.query() else {
+        return uri.path().to_owned();
+    };
+    let Ok(query) = serde_urlencoded::from_str::<Vec<(String, String)>>(query) else {
+        return format!("{}?<invalid query>", uri.path());
+    };
+    let query: Vec<(String, String)> = (query.into_iter())
+        .map(|(k, v)| match REDACTED_QUERY_PARAMS.contains(&k.as_str()) {
+            true => (k, "REDACTED".to_owned()),
+            false => (k, v),
+        })
+        .collect();
+    format!(
+        "{}?{}",
+        uri.path(),
+        serde_urlencoded::to_string(query).unwrap_or_default(),
+    )
+}
+
 /// Returns a new [CookieJar] which _must_ be returned from the handler
 /// as part of the response for the changes to be propagated.
 /// See [CookieJar]'s documentation for examples.
@@ -66,17 +215,21 @@ pub fn add_cookie(
     biscuit: &Biscuit,
     cookies: CookieJar,
 ) -> Result<CookieJar, crate::Error> {
+    // NOTE: Biscuits which can be converted to JWTs without losing
+    //   attenuations are stored as JWTs (see design v3).
+    #[cfg(feature = "migrate-biscuits")]
+    if let Some(claims) = crate::jwt::claims_from_biscuit(biscuit) {
+        let jwt = crate::jwt::encode(&claims).map_err(|err| {
+            crate::Error::InternalServerError(format!("Error setting token cookie: {err}"))
+        })?;
+        let expires_at = DateTime::from_timestamp(claims.exp, 0).map(SystemTime::from);
+        return Ok(cookies.add(TOKEN_COOKIE.cookie(jwt, expires_at)));
+    }
+
     let base64 = biscuit.to_base64().map_err(|err| {
         crate::Error::InternalServerError(format!("Error setting token cookie: {err}"))
     })?;
-    let cookie = Cookie::build((TOKEN_COOKIE_NAME, base64))
-        .path("/")
-        .max_age(Duration::days(365 * 5))
-        .http_only(true)
-        .secure(true)
-        .same_site(SameSite::Strict)
-        .build();
-    Ok(cookies.clone().add(cookie))
+    Ok(cookies.add(TOKEN_COOKIE.cookie(base64, expiry(biscuit))))
 }
 
 pub fn accepts(
@@ -115,9 +268,25 @@ pub fn accepts(
     mimes.any(|mime| mime.essence_str() == expected_mime)
 }
 
+pub trait VecExt {
+    fn sorted(&self) -> Self;
+}
+
+impl<T: Ord + Clone> VecExt for Vec<T> {
+    fn sorted(&self) -> Self {
+        let mut res = self.clone();
+        res.sort();
+        res
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::add_padding;
+    use axum::http::Uri;
+
+    use super::{
+        add_padding, expiry, redact_uri, redirect_path, uri_without_query_params, write_atomically,
+    };
 
     #[test]
     fn test_base64_padding() {
@@ -132,6 +301,80 @@ mod tests {
         assert_eq!(add_padding("abcd"), "abcd".to_string());
     }
 
+    #[test]
+    fn test_write_atomically() {
+        let dir = std::env::temp_dir().join(format!("orangutan-util-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file_path = dir.join("data.json");
+
+        write_atomically(&file_path, "old").unwrap();
+        write_atomically(&file_path, "new").unwrap();
+        assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "new");
+        assert!(!dir.join("data.json.tmp").exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_no_open_redirect() {
+        assert_eq!(redirect_path(Some("/blog/".to_owned())), "/blog/");
+        assert_eq!(redirect_path(Some("//evil.example".to_owned())), "/");
+        assert_eq!(redirect_path(Some("/\\evil.example".to_owned())), "/");
+        assert_eq!(redirect_path(Some("/\t/evil.example".to_owned())), "/");
+        assert_eq!(redirect_path(Some("/a\\b".to_owned())), "/a\\b");
+        assert_eq!(redirect_path(Some("https://evil.example".to_owned())), "/");
+        assert_eq!(redirect_path(None), "/");
+    }
+
+    #[test]
+    fn test_uri_without_query_params() {
+        let uri: Uri = "/blog/?b=1&token=abc&a=x%20y".parse().unwrap();
+        assert_eq!(
+            uri_without_query_params(&uri, &["token"]),
+            "/blog/?b=1&a=x+y"
+        );
+        let uri: Uri = "/blog/?token=abc&force=true".parse().unwrap();
+        assert_eq!(
+            uri_without_query_params(&uri, &["token", "force"]),
+            "/blog/"
+        );
+        // Encoded `#` must stay encoded, or it would start a fragment.
+        let uri: Uri = "/blog/?q=a%23b&token=abc".parse().unwrap();
+        assert_eq!(uri_without_query_params(&uri, &["token"]), "/blog/?q=a%23b");
+    }
+
+    #[test]
+    fn test_redact_uri() {
+        let uri: Uri = "/blog/?refresh_token=abc&page=2".parse().unwrap();
+        assert_eq!(redact_uri(&uri), "/blog/?refresh_token=REDACTED&page=2");
+        let uri: Uri = "/blog/".parse().unwrap();
+        assert_eq!(redact_uri(&uri), "/blog/");
+    }
+
+    #[test]
+    fn test_expiry() {
+        use std::time::SystemTime;
+
+        use biscuit_auth::{
+            macros::{biscuit, block},
+            KeyPair,
+        };
+        use chrono::DateTime;
+
+        let biscuit = biscuit!(r#"profile("amis");"#)
+            .build(&KeyPair::new())
+            .unwrap();
+        assert_eq!(expiry(&biscuit), None);
+
+        let date = SystemTime::from(DateTime::parse_from_rfc3339("2030-01-01T00:00:00Z").unwrap());
+        let sooner =
+            SystemTime::from(DateTime::parse_from_rfc3339("2029-01-01T00:00:00Z").unwrap());
+        let biscuit = (biscuit.append(block!("check if time($time), $time <= {date};")))
+            .and_then(|b| b.append(block!("check if time($time), $time <= {sooner};")))
+            .unwrap();
+        assert_eq!(expiry(&biscuit), Some(sooner));
+    }
+
     // #[test]
     // fn test_should_force_token_refresh() {
     //     assert_eq!(should_force_token_refresh(None), false);
@@ -151,15 +394,3 @@ mod tests {
     //     );
     // }
 }
-
-pub trait VecExt {
-    fn sorted(&self) -> Self;
-}
-
-impl<T: Ord + Clone> VecExt for Vec<T> {
-    fn sorted(&self) -> Self {
-        let mut res = self.clone();
-        res.sort();
-        res
-    }
-}
@@ -1,5 +1,3 @@
-use super::error;
-
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Could not serialize Tera context: {0}")]
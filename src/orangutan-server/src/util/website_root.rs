@@ -6,7 +6,7 @@ lazy_static! {
     static ref WEBSITE_ROOT: String = std::env::var("WEBSITE_ROOT").unwrap_or_default();
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct WebsiteRoot(String);
 
 impl Deref for WebsiteRoot {
@@ -17,6 +17,12 @@ impl Deref for WebsiteRoot {
     }
 }
 
+impl From<String> for WebsiteRoot {
+    fn from(website_root: String) -> Self {
+        Self(website_root)
+    }
+}
+
 impl WebsiteRoot {
     pub fn try_from_env() -> Result<Self, &'static str> {
         if WEBSITE_ROOT.is_empty() {